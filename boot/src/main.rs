@@ -3,72 +3,115 @@
 
 extern crate alloc;
 
+mod menu;
+
 use core::{mem, ptr};
+use alloc::boxed::Box;
 use alloc::vec::Vec;
 use anyhow::{anyhow, Result};
 use elf::ElfBytes;
-use elf::abi::PT_LOAD;
+use elf::abi::{ET_DYN, PF_W, PF_X, PT_LOAD};
 use elf::endian::LittleEndian;
 use uefi::mem::memory_map::MemoryMap;
-use uefi::println;
+use uefi::{println, CStr16, CString16};
 use uefi::prelude::*;
-use uefi::boot::MemoryType;
-use uefi::fs::FileSystem;
+use uefi::boot::{AllocateType, MemoryType};
+use uefi::fs::{FileSystem, PathBuf};
 use uefi::proto::console::gop::{GraphicsOutput, PixelFormat};
-use uefi::proto::console::text::Input;
+use uefi::proto::rng::Rng;
 use uefi::table::cfg::ACPI2_GUID;
 use x86_64::addr;
 use x86_64::registers::control::{Cr3, Cr3Flags};
 use x86_64::structures::paging::PageSize;
 use x86_64::structures::paging::Size2MiB;
-use x86_64::structures::paging::{FrameAllocator, PageTable, PhysFrame, Size4KiB};
+use x86_64::structures::paging::{FrameAllocator, PageTable, PageTableFlags, PhysFrame, Size4KiB};
 
-use kernel::memory;
-use kernel::memory::{GlobalFrameAllocator, MemoryPool, KERNEL_END, KERNEL_SIZE, KERNEL_START};
-use kernel::drivers::video::framebuffer::Framebuffer;
+use kernel::bootinfo::{BootInfo, FirmwareKind, FirmwareRegion, Region};
+use kernel::{memory, random, relocation};
+use kernel::bootconfig::Config;
+use kernel::cmdline::Cmdline;
+use kernel::memory::{GlobalFrameAllocator, MemoryPool, KASLR_END, KASLR_START, KERNEL_SIZE};
+use kernel::drivers::video::framebuffer::{Framebuffer, Pixel};
 
 // TODO: do not pass framebuffer
 
-type KStart = extern "sysv64" fn(u64, *const MemoryPool, usize, Framebuffer) -> !;
+type KStart = extern "sysv64" fn(&'static mut BootInfo) -> !;
+
+const CONFIG_PATH: &CStr16 = cstr16!("\\boot.cfg");
+const INITRD_MEMORY: MemoryType = MemoryType::custom(0x80000001);
+
+struct Kernel {
+    entry:  KStart,
+    symtab: Region,
+    strtab: Region
+}
+
+struct Segment {
+    offset: u64,
+    vaddr:  u64,
+    size:   u64,
+    flags:  u32
+}
 
 struct Memory {
-    kernel: MemoryPool,
-    free:   Vec<MemoryPool>
+    kernel:   MemoryPool,
+    segments: Vec<Segment>,
+    free:     Vec<MemoryPool>,
+    firmware: Vec<FirmwareRegion>
 }
 
 impl Memory {
     fn build() -> Result<Memory> {
         println!("[+] Building Memory Map");
 
-        let mut kernel = None;
+        let map = boot::memory_map(MemoryType::BOOT_SERVICES_DATA)?;
 
-        let free = boot::memory_map(MemoryType::BOOT_SERVICES_DATA)?
-            .entries()
+        let kernel = Memory::conventional(&map)
+            .find(|pool| pool.size() >= KERNEL_SIZE)
+            .map(|pool| MemoryPool { start: pool.start, end: pool.start + KERNEL_SIZE })
+            .ok_or(anyhow!("Not enough memory"))?;
+
+        // Reserve the kernel region so later allocations in the loader cannot land inside it
+        boot::allocate_pages(AllocateType::Address(kernel.start), MemoryType::LOADER_DATA, (KERNEL_SIZE / Size4KiB::SIZE) as usize)?;
+
+        Ok(
+            Memory {
+                kernel,
+                segments: Vec::new(),
+                // Filled in from the final memory map once boot services are gone, so no allocation may happen then
+                free:     Vec::with_capacity(map.len() + 16),
+                firmware: Vec::with_capacity(map.len() + 16)
+            }
+        )
+    }
+
+    fn conventional(map: &impl MemoryMap) -> impl Iterator<Item = MemoryPool> + '_ {
+        map.entries()
             .filter(|e| e.ty == MemoryType::CONVENTIONAL)
             .map(|e| {
                 let start = addr::align_up(e.phys_start, Size2MiB::SIZE);
                 let end = addr::align_down(e.phys_start + e.page_count * 4096, Size2MiB::SIZE);
-                (start, end)
-            })
-            .filter(|(start, end)| end > start)
-            .map(|(start, end)| {
-                if kernel.is_none() && end - start >= KERNEL_SIZE {
-                    let kend = start + KERNEL_SIZE;
-
-                    kernel = Some(MemoryPool { start, end: kend });
-                    MemoryPool { start: kend, end }
-                } else {
-                    MemoryPool { start, end }
-                }
+                MemoryPool { start, end }
             })
-            .collect::<Vec<MemoryPool>>();
+            .filter(|pool| pool.end > pool.start)
+    }
 
-        Ok(
-            Memory {
-                kernel: kernel.ok_or(anyhow!("Not enough memory"))?,
-                free
-            }
-        )
+    fn collect_free(&mut self, map: &impl MemoryMap) {
+        for pool in Memory::conventional(map) {
+            if self.free.len() == self.free.capacity() { break; }
+            self.free.push(pool);
+        }
+
+        for e in map.entries() {
+            let kind = match e.ty {
+                MemoryType::ACPI_RECLAIM      => FirmwareKind::AcpiReclaimable,
+                MemoryType::ACPI_NON_VOLATILE => FirmwareKind::AcpiNvs,
+                _                             => continue
+            };
+
+            if self.firmware.len() == self.firmware.capacity() { break; }
+            self.firmware.push(FirmwareRegion { start: e.phys_start, end: e.phys_start + e.page_count * 4096, kind });
+        }
     }
 
     unsafe fn init_page_table() -> Result<()> {
@@ -89,32 +132,128 @@ impl Memory {
         Ok(())
     }
 
-    unsafe fn map_kernel(&self) {
+    unsafe fn map_kernel(&self) -> Result<()> {
         println!("[+] Mapping Memory");
 
-        println!("Mapping 0x{:x} -- 0x{:x} to 0x{:x} -- 0x{:x}", self.kernel.start, self.kernel.end - 1, KERNEL_START, KERNEL_END);
-        memory::map(self.kernel, KERNEL_START);
+        // NX must be enabled before any entry carries the bit
+        memory::enable_protection();
+
+        for segment in &self.segments {
+            let mut flags = PageTableFlags::empty();
+            if segment.flags & PF_W != 0 { flags |= PageTableFlags::WRITABLE; }
+            if segment.flags & PF_X == 0 { flags |= memory::no_execute(); }
+
+            let pstart = self.kernel.start + segment.offset;
+            println!("Mapping 0x{:x} -- 0x{:x} to 0x{:x} ({flags:?})", pstart, pstart + segment.size - 1, segment.vaddr);
+
+            // Large pages wherever the segment allows, which is the heap and the stack
+            let large = [pstart, segment.vaddr, segment.size].iter().all(|x| x % Size2MiB::SIZE == 0);
+            if large {
+                memory::map_flags(MemoryPool { start: pstart, end: pstart + segment.size }, segment.vaddr, flags)?;
+            } else {
+                memory::map_pages(pstart, segment.vaddr, segment.size, flags)?;
+            }
+        }
+
+        Ok(())
     }
 }
 
-fn load_kernel(mem: &Memory) -> Result<KStart> {
-    println!("[+] Loading Kernel");
-
+fn read_file(path: &str) -> Result<Vec<u8>> {
     let fs_proto = boot::get_image_file_system(boot::image_handle())?;
     let mut fs = FileSystem::new(fs_proto);
 
-    let buf = fs.read(cstr16!("\\kernel.elf"))?;
+    Ok(fs.read(PathBuf::from(CString16::try_from(path)?))?)
+}
+
+fn load_config() -> Config {
+    let fs_proto = boot::get_image_file_system(boot::image_handle());
+    let text = fs_proto
+        .map(FileSystem::new)
+        .map_err(anyhow::Error::from)
+        .and_then(|mut fs| Ok(fs.read_to_string(CONFIG_PATH)?));
+
+    match text {
+        Ok(text) => Config::parse(&text).unwrap_or_else(|e| {
+            println!("[!] {e}, using defaults");
+            Config::fallback()
+        }),
+        Err(_) => Config::fallback()
+    }
+}
+
+fn kernel_base(kaslr: bool) -> u64 {
+    if !kaslr { return KASLR_START; }
+
+    let slots = (KASLR_END - KASLR_START - KERNEL_SIZE) / Size2MiB::SIZE;
+    KASLR_START + random() % slots * Size2MiB::SIZE
+}
+
+fn firmware_random(buf: &mut [u8]) -> bool {
+    boot::get_handle_for_protocol::<Rng>()
+        .and_then(boot::open_protocol_exclusive::<Rng>)
+        .and_then(|mut rng| rng.get_rng(None, buf))
+        .is_ok()
+}
+
+// The kernel's own sources on top of the firmware's, so a missing RNG protocol is no single point of failure
+fn random() -> u64 {
+    let mut seed = [0u8; 32];
+    let seed: &[u8] = if firmware_random(&mut seed) { &seed } else { &[] };
+
+    let bytes = random::entropy(seed);
+    u64::from_le_bytes(bytes[..8].try_into().expect("Impossible"))
+}
+
+// Goes into the kernel's entropy pool, which has other sources to fall back on
+fn rng_seed() -> Region {
+    let mut seed = [0u8; 32];
+    if firmware_random(&mut seed) { leak_bytes(&seed) } else { Region::empty() }
+}
+
+// Kept around for the kernel to symbolise backtraces, the kernel adds its own base to the values
+fn load_symbols(elf: &ElfBytes<LittleEndian>) -> Result<(Region, Region)> {
+    let section = |name: &str| -> Result<Region> {
+        match elf.section_header_by_name(name)? {
+            Some(shdr) => Ok(leak_bytes(elf.section_data(&shdr)?.0)),
+            None       => Ok(Region::empty())
+        }
+    };
+
+    let symbols = (section(".symtab")?, section(".strtab")?);
+    if symbols.0.is_empty() { println!("[!] Kernel has no symbol table"); }
+
+    Ok(symbols)
+}
+
+fn load_kernel(mem: &mut Memory, path: &str, base: u64) -> Result<Kernel> {
+    println!("[+] Loading Kernel {path} at 0x{base:x}");
+
+    let buf = read_file(path)?;
     let elf: ElfBytes<LittleEndian> = ElfBytes::minimal_parse(&buf)?;
 
+    if elf.ehdr.e_type != ET_DYN {
+        return Err(anyhow!("Kernel is not position independent"));
+    }
+
     elf.segments()
         .ok_or(anyhow!("Elf does not contain segments"))?
         .into_iter()
         .filter(|phdr| phdr.p_type == PT_LOAD)
         .try_for_each(|phdr| -> Result<()> {
+            if phdr.p_vaddr + phdr.p_memsz > KERNEL_SIZE {
+                return Err(anyhow!("Segment at 0x{:x} is outside of the kernel region", phdr.p_vaddr));
+            }
+
             let src = elf.segment_data(&phdr)?;
-            let dst = (mem.kernel.start + phdr.p_paddr) as *mut u8;
+            let dst = (mem.kernel.start + phdr.p_vaddr) as *mut u8;
             let size = phdr.p_memsz as usize;
 
+            mem.segments.push(Segment { offset: phdr.p_vaddr, vaddr: base + phdr.p_vaddr, size: phdr.p_memsz, flags: phdr.p_flags });
+
+            // Heap and stack, nothing in them is expected to start out zeroed
+            if phdr.p_filesz == 0 { return Ok(()); }
+
             println!("Copy {} bytes to 0x{:x} -- 0x{:x}", size, dst as u64, dst as usize + size - 1);
 
             unsafe {
@@ -125,7 +264,69 @@ fn load_kernel(mem: &Memory) -> Result<KStart> {
             Ok(())
         })?;
 
-    unsafe { Ok(mem::transmute(elf.ehdr.e_entry)) }
+    relocate(mem, &elf, &buf, base)?;
+    let (symtab, strtab) = load_symbols(&elf)?;
+
+    Ok(
+        Kernel {
+            entry: unsafe { mem::transmute::<u64, KStart>(base + elf.ehdr.e_entry) },
+            symtab,
+            strtab
+        }
+    )
+}
+
+// The image is linked at 0, so every relocation offset is also an offset into the kernel region
+fn relocate(mem: &Memory, elf: &ElfBytes<LittleEndian>, bytes: &[u8], base: u64) -> Result<()> {
+    let relocations = relocation::relative(elf, bytes)?;
+
+    println!("Applying {} relocations", relocations.len());
+
+    for (offset, addend) in relocations {
+        if offset.checked_add(8).is_none_or(|end| end > KERNEL_SIZE) {
+            return Err(anyhow!("Relocation at 0x{offset:x} is outside of the kernel region"));
+        }
+
+        unsafe { ptr::write_unaligned((mem.kernel.start + offset) as *mut u64, base.wrapping_add(addend)); }
+    }
+
+    Ok(())
+}
+
+fn load_initrd(path: Option<&str>) -> Result<Region> {
+    let Some(path) = path else { return Ok(Region::empty()); };
+
+    println!("[+] Loading Initrd {path}");
+
+    let buf = match read_file(path) {
+        Ok(buf) => buf,
+        Err(e)  => {
+            println!("[!] No initrd: {e}");
+            return Ok(Region::empty());
+        }
+    };
+
+    let pages = buf.len().div_ceil(Size4KiB::SIZE as usize);
+    let dst = boot::allocate_pages(AllocateType::AnyPages, INITRD_MEMORY, pages)?.as_ptr();
+
+    println!("Copy {} bytes to 0x{:x} -- 0x{:x}", buf.len(), dst as u64, dst as usize + buf.len() - 1);
+
+    unsafe { ptr::copy(buf.as_ptr(), dst, buf.len()); }
+
+    Ok(Region { start: dst as u64, size: buf.len() as u64 })
+}
+
+// BMP, PNG or QOI, the kernel decodes it and falls back to the initrd without one
+fn load_splash(path: Option<&str>) -> Region {
+    let Some(path) = path else { return Region::empty(); };
+
+    match read_file(path) {
+        Ok(buf) => leak_bytes(&buf),
+        Err(e)  => {
+            println!("[!] No splash image: {e}");
+            Region::empty()
+        }
+    }
 }
 
 fn find_acpi() -> Result<u64> {
@@ -138,57 +339,78 @@ fn find_acpi() -> Result<u64> {
     }).ok_or(anyhow!("ACPI Table not found"))
 }
 
-fn wait_for_key() -> Result<()> {
-    let input_handle = boot::get_handle_for_protocol::<Input>()?;
-    let mut input = boot::open_protocol_exclusive::<Input>(input_handle)?;
-
-    println!("[!] Press any key to proceed...");
-    while input.read_key()?.is_none() {}
-
-    Ok(())
+fn leak_bytes(bytes: &[u8]) -> Region {
+    let bytes = Vec::from(bytes).leak();
+    Region { start: bytes.as_ptr() as u64, size: bytes.len() as u64 }
 }
 
 // TODO: remove
-fn setup_video<'a>() -> Result<Framebuffer<'a>> {
+fn setup_video<'a>((width, height): (usize, usize)) -> Result<Framebuffer<'a>> {
     let gop_handle = boot::get_handle_for_protocol::<GraphicsOutput>()?;
     let mut gop = boot::open_protocol_exclusive::<GraphicsOutput>(gop_handle)?;
 
     let mode = gop.modes()
         .find(|mode| {
             let info = mode.info();
-            info.resolution() == (1920, 1080) &&
-                info.pixel_format() == PixelFormat::Bgr &&
-                info.stride() == 1920
+            info.resolution() == (width, height) && info.pixel_format() == PixelFormat::Bgr
         })
-        .ok_or(anyhow!("No graphic modes available"))?;
+        .ok_or(anyhow!("Video mode {width}x{height} not available"))?;
 
     gop.set_mode(&mode)?;
 
-    let ptr = gop.frame_buffer().as_mut_ptr();
-    unsafe { Ok(mem::transmute(ptr)) }
+    let stride = mode.info().stride();
+    let ptr = gop.frame_buffer().as_mut_ptr() as *mut Pixel;
+    unsafe { Ok(Framebuffer::new(ptr, width, height, stride)) }
 }
 
 fn init() -> Result<()> {
     uefi::helpers::init()?;
     system::with_stdout(|stdout| stdout.clear())?;
 
-    let mem = Memory::build()?;
+    let config = load_config();
+    let entry = menu::select(&config)?;
+
+    let mut mem = Memory::build()?;
     unsafe { Memory::init_page_table()?; }
 
-    let kstart = load_kernel(&mem)?;
+    // Same parsing as in the kernel, which reads the flag again for programs
+    let kaslr = !Cmdline::parse(entry.cmdline.clone().leak()).flag("nokaslr");
+    let kernel = load_kernel(&mut mem, &entry.kernel, kernel_base(kaslr))?;
+    let initrd = load_initrd(entry.initrd.as_deref())?;
     let acpi = find_acpi()?;
-    unsafe { mem.map_kernel(); }
-    wait_for_key()?;
+    unsafe { mem.map_kernel()?; }
 
     println!("[+] Starting Kernel");
 
-    let fb = setup_video()?;
-
-    unsafe {
-        let _ = boot::exit_boot_services(MemoryType::BOOT_SERVICES_DATA);
-    }
-
-    kstart(acpi, mem.free.as_ptr(), mem.free.len(), fb);
+    let cmdline = leak_bytes(entry.cmdline.as_bytes());
+    let seed = rng_seed();
+    let splash = load_splash(entry.splash.as_deref());
+    let fb = setup_video(entry.video)?;
+
+    let info = Box::leak(Box::new(BootInfo {
+        acpi,
+        free_ptr:      ptr::null(),
+        free_size:     0,
+        firmware_ptr:  ptr::null(),
+        firmware_size: 0,
+        framebuffer:   fb,
+        initrd,
+        cmdline,
+        symtab:        kernel.symtab,
+        strtab:        kernel.strtab,
+        seed,
+        splash
+    }));
+
+    let map = unsafe { boot::exit_boot_services(MemoryType::BOOT_SERVICES_DATA) };
+    mem.collect_free(&map);
+
+    info.free_ptr = mem.free.as_ptr();
+    info.free_size = mem.free.len();
+    info.firmware_ptr = mem.firmware.as_ptr();
+    info.firmware_size = mem.firmware.len();
+
+    (kernel.entry)(info);
 
     Ok(())
 }
@@ -0,0 +1,74 @@
+use anyhow::Result;
+use uefi::{boot, println, system, Char16};
+use uefi::proto::console::text::{Input, Key, ScanCode};
+
+use kernel::bootconfig::{Config, Entry};
+
+const TICK_US: usize = 100_000;
+const TICKS_PER_SECOND: u64 = 10;
+
+fn draw(config: &Config, selected: usize, remaining: Option<u64>) -> Result<()> {
+    system::with_stdout(|stdout| stdout.clear())?;
+
+    println!("[+] Select Boot Entry\n");
+
+    for (i, entry) in config.entries.iter().enumerate() {
+        let marker = if i == selected { '>' } else { ' ' };
+        println!(" {marker} {}", entry.title);
+    }
+
+    println!();
+
+    match remaining {
+        Some(seconds) => println!("Booting in {seconds}s, arrows to choose, Enter to boot"),
+        None          => println!("Arrows to choose, Enter to boot")
+    }
+
+    Ok(())
+}
+
+pub fn select(config: &Config) -> Result<&Entry> {
+    if config.entries.len() == 1 && config.timeout == Some(0) {
+        return Ok(&config.entries[0]);
+    }
+
+    let input_handle = boot::get_handle_for_protocol::<Input>()?;
+    let mut input = boot::open_protocol_exclusive::<Input>(input_handle)?;
+
+    let enter = Char16::try_from('\r')?;
+    let mut selected = config.default;
+    let mut ticks = config.timeout.map(|seconds| seconds * TICKS_PER_SECOND);
+
+    draw(config, selected, config.timeout)?;
+
+    loop {
+        if ticks == Some(0) {
+            return Ok(&config.entries[selected]);
+        }
+
+        let Some(key) = input.read_key()? else {
+            boot::stall(TICK_US);
+
+            if let Some(t) = ticks {
+                ticks = Some(t - 1);
+                if t % TICKS_PER_SECOND == 0 {
+                    draw(config, selected, Some(t / TICKS_PER_SECOND))?;
+                }
+            }
+
+            continue;
+        };
+
+        // Any key press means the user is here, stop counting down
+        ticks = None;
+
+        match key {
+            Key::Special(ScanCode::UP)   => selected = selected.saturating_sub(1),
+            Key::Special(ScanCode::DOWN) => selected = (selected + 1).min(config.entries.len() - 1),
+            Key::Printable(c) if c == enter => return Ok(&config.entries[selected]),
+            _ => {}
+        }
+
+        draw(config, selected, None)?;
+    }
+}
@@ -1,5 +0,0 @@
-    .intel_syntax noprefix
-
-    mov rsp, 0xfffffffffffffff0
-    mov rax, 0xffffffffaf000000
-    jmp rax
@@ -0,0 +1,163 @@
+//! System call ABI shared between the kernel and user programs.
+//!
+//! A system call is issued with the `syscall` instruction. The call number goes in `rax` and up to
+//! six arguments in `rdi`, `rsi`, `rdx`, `r10`, `r8` and `r9`. The kernel returns a single value in
+//! `rax`: non-negative on success, or the negated [`Error`] code on failure. Only `rax`, `rcx` and
+//! `r11` are clobbered.
+//!
+//! Nothing in this module depends on the rest of the kernel, so user programs can link against it
+//! directly.
+
+use core::arch::asm;
+
+/// System call numbers.
+pub mod number {
+    /// `write(fd, buf, len) -> written`
+    pub const WRITE:    u64 = 0;
+    /// `read_key() -> char`, blocks until a key producing a character is pressed.
+    pub const READ_KEY: u64 = 1;
+    /// `exit(code) -> !`
+    pub const EXIT:     u64 = 2;
+    /// `sleep(milliseconds)`
+    pub const SLEEP:    u64 = 3;
+    /// `time() -> milliseconds since boot`
+    pub const TIME:     u64 = 4;
+    /// `mmap(addr, len, prot) -> addr`, maps zeroed anonymous memory. `addr` is a hint and may be 0.
+    pub const MMAP:     u64 = 5;
+    /// `munmap(addr, len)`, both arguments must be page aligned.
+    pub const MUNMAP:   u64 = 6;
+
+    pub const COUNT:    usize = 7;
+}
+
+/// Protection flags for [`mmap`].
+pub mod prot {
+    pub const READ:  u64 = 1 << 0;
+    pub const WRITE: u64 = 1 << 1;
+    pub const EXEC:  u64 = 1 << 2;
+}
+
+/// Auxiliary vector entry types placed on the initial stack after `envp`.
+///
+/// On entry `rsp` points at `argc`, followed by the `argv` pointers, a null pointer, the `envp`
+/// pointers, another null pointer and finally `(type, value)` pairs terminated by [`auxv::AT_NULL`].
+pub mod auxv {
+    pub const AT_NULL:   u64 = 0;
+    pub const AT_PHDR:   u64 = 3;
+    pub const AT_PHENT:  u64 = 4;
+    pub const AT_PHNUM:  u64 = 5;
+    pub const AT_PAGESZ: u64 = 6;
+    pub const AT_BASE:   u64 = 7;
+    pub const AT_ENTRY:  u64 = 9;
+    /// Address of 16 random bytes.
+    pub const AT_RANDOM: u64 = 25;
+}
+
+/// The only file descriptor currently understood by [`write`], the kernel console.
+pub const STDOUT: u64 = 1;
+
+pub const PAGE_SIZE: u64 = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(i64)]
+pub enum Error {
+    /// Unknown system call number.
+    NoSys           = 1,
+    /// A pointer argument is outside of user space or not mapped with the required access.
+    BadAddress      = 2,
+    InvalidArgument = 3,
+    NoMemory        = 4,
+    BadDescriptor   = 5
+}
+
+impl Error {
+    pub fn from_code(code: i64) -> Option<Error> {
+        match code {
+            1 => Some(Error::NoSys),
+            2 => Some(Error::BadAddress),
+            3 => Some(Error::InvalidArgument),
+            4 => Some(Error::NoMemory),
+            5 => Some(Error::BadDescriptor),
+            _ => None
+        }
+    }
+
+    pub fn code(self) -> i64 {
+        self as i64
+    }
+}
+
+/// Decodes a raw return value into the success value or an [`Error`].
+pub fn result(ret: i64) -> Result<u64, Error> {
+    if ret >= 0 {
+        Ok(ret as u64)
+    } else {
+        Err(Error::from_code(-ret).unwrap_or(Error::NoSys))
+    }
+}
+
+/// Issues a raw system call.
+///
+/// # Safety
+///
+/// The arguments must satisfy the contract of the given call, pointers in particular.
+pub unsafe fn syscall(number: u64, args: [u64; 6]) -> i64 {
+    let ret: i64;
+
+    asm!(
+        "syscall",
+        inlateout("rax") number as i64 => ret,
+        in("rdi") args[0],
+        in("rsi") args[1],
+        in("rdx") args[2],
+        in("r10") args[3],
+        in("r8") args[4],
+        in("r9") args[5],
+        lateout("rcx") _,
+        lateout("r11") _,
+        options(nostack)
+    );
+
+    ret
+}
+
+pub fn write(fd: u64, buf: &[u8]) -> Result<usize, Error> {
+    let ret = unsafe { syscall(number::WRITE, [fd, buf.as_ptr() as u64, buf.len() as u64, 0, 0, 0]) };
+    result(ret).map(|n| n as usize)
+}
+
+pub fn read_key() -> Result<char, Error> {
+    let ret = unsafe { syscall(number::READ_KEY, [0; 6]) };
+    result(ret).and_then(|c| char::from_u32(c as u32).ok_or(Error::InvalidArgument))
+}
+
+pub fn exit(code: i64) -> ! {
+    unsafe { syscall(number::EXIT, [code as u64, 0, 0, 0, 0, 0]); }
+    unreachable!("exit returned")
+}
+
+pub fn sleep(milliseconds: u64) -> Result<(), Error> {
+    let ret = unsafe { syscall(number::SLEEP, [milliseconds, 0, 0, 0, 0, 0]) };
+    result(ret).map(|_| ())
+}
+
+pub fn time() -> u64 {
+    let ret = unsafe { syscall(number::TIME, [0; 6]) };
+    result(ret).unwrap_or(0)
+}
+
+/// # Safety
+///
+/// A non-zero `addr` may replace nothing that is already mapped; the kernel refuses overlaps.
+pub unsafe fn mmap(addr: u64, len: u64, prot: u64) -> Result<*mut u8, Error> {
+    let ret = syscall(number::MMAP, [addr, len, prot, 0, 0, 0]);
+    result(ret).map(|addr| addr as *mut u8)
+}
+
+/// # Safety
+///
+/// Nothing may reference the unmapped range afterwards.
+pub unsafe fn munmap(addr: *mut u8, len: u64) -> Result<(), Error> {
+    let ret = syscall(number::MUNMAP, [addr as u64, len, 0, 0, 0, 0]);
+    result(ret).map(|_| ())
+}
@@ -1,3 +1,130 @@
+pub mod aml;
 pub mod tables;
 pub mod mapper;
+pub mod numa;
 pub mod pci;
+
+extern crate alloc;
+
+use core::fmt::Write;
+use alloc::vec::Vec;
+use anyhow::anyhow;
+
+use crate::acpi::aml::namespace::{self, Name};
+use crate::acpi::aml::value::{Object, Value};
+use crate::monitor::hexdump;
+use crate::shell::{self, Args, Command, Error};
+
+pub fn register_commands() {
+    shell::register(Command { name: "acpi",  usage: "acpi [signature]", help: "list ACPI tables or dump one", run: acpi });
+    shell::register(Command { name: "lspci", usage: "lspci",            help: "list PCI devices",             run: lspci });
+    shell::register(Command { name: "numa",  usage: "numa",             help: "show NUMA nodes and distances", run: numa });
+    shell::register(Command {
+        name: "aml", usage: "aml [path] | aml eval <path> [args..]", help: "show the AML namespace or evaluate an object", run: aml
+    });
+}
+
+fn acpi(args: &mut Args, out: &mut dyn Write) -> Result<(), Error> {
+    let signature = args.optional_word();
+    args.end()?;
+
+    let acpi = tables::get().ok_or(anyhow!("ACPI is not initialized"))?;
+
+    let Some(signature) = signature else {
+        for table in acpi.raw_tables() {
+            let header = table.header();
+            let length = header.length;
+            let checksum = if table.checksum_valid() { "" } else { " bad checksum" };
+            writeln!(
+                out, "{} 0x{:08x} {length:6} rev {} {} {}{checksum}",
+                table.signature(), table.addr, table.revision(), header.oem_id(), header.oem_table_id()
+            )?;
+        }
+
+        return Ok(());
+    };
+
+    let table = acpi.raw_table(signature).ok_or(anyhow!("No {signature} table"))?;
+    hexdump(out, table.addr, table.bytes())?;
+
+    Ok(())
+}
+
+fn lspci(args: &mut Args, out: &mut dyn Write) -> Result<(), Error> {
+    args.end()?;
+
+    for d in pci::get().ok_or(anyhow!("PCI is not initialized"))?.devices() {
+        writeln!(
+            out, "{:02x}:{:02x}.{} {:04x}:{:04x} class {:02x}{:02x} rev {:02x}",
+            d.bus, d.device, d.function, d.vendor, d.id, d.class >> 24, (d.class >> 16) as u8, d.class as u8
+        )?;
+    }
+
+    Ok(())
+}
+
+fn numa(args: &mut Args, out: &mut dyn Write) -> Result<(), Error> {
+    args.end()?;
+
+    let topology = numa::get().ok_or(anyhow!("No NUMA topology"))?;
+    let nodes = topology.nodes();
+
+    for &node in &nodes {
+        let cpus: Vec<u32> = topology.cpus.iter().filter(|cpu| cpu.node == node).map(|cpu| cpu.apic_id).collect();
+        writeln!(out, "node {node}: apic {cpus:?}")?;
+
+        for m in topology.memory.iter().filter(|m| m.node == node) {
+            let hotplug = if m.hotplug { " hotplug" } else { "" };
+            let nonvolatile = if m.nonvolatile { " nonvolatile" } else { "" };
+            writeln!(out, "  0x{:012x} - 0x{:012x}{hotplug}{nonvolatile}", m.start, m.end - 1)?;
+        }
+    }
+
+    write!(out, "distances:")?;
+    for &to in &nodes { write!(out, " {to:3}")?; }
+    writeln!(out)?;
+
+    for &from in &nodes {
+        write!(out, "{from:10}")?;
+        for &to in &nodes { write!(out, " {:3}", topology.distance(from, to))?; }
+        writeln!(out)?;
+    }
+
+    Ok(())
+}
+
+fn aml(args: &mut Args, out: &mut dyn Write) -> Result<(), Error> {
+    let aml = aml::get().ok_or(anyhow!("AML is not loaded"))?;
+
+    let path = match args.optional_word() {
+        Some("eval") => {
+            let path = args.word("path")?;
+            let mut values = Vec::new();
+            while let Some(value) = args.optional_number()? {
+                values.push(Value::Integer(value));
+            }
+
+            writeln!(out, "{}", aml.evaluate(path, values)?)?;
+            return Ok(());
+        }
+        path => path
+    };
+    args.end()?;
+
+    let root = aml.namespace().path(namespace::ROOT, &Name::parse(path.unwrap_or(namespace::ROOT))?)?;
+    let base = namespace::depth(&root);
+
+    for (path, object) in aml.namespace().subtree(&root) {
+        let indent = 2 * (namespace::depth(path) - base);
+        let name = path.rsplit(['.', '\\']).next().filter(|name| !name.is_empty()).unwrap_or(namespace::ROOT);
+
+        match object {
+            Object::Name(value)    => writeln!(out, "{:indent$}{name} {value}", "")?,
+            Object::Method(method) => writeln!(out, "{:indent$}{name} Method ({} args)", "", method.args)?,
+            Object::Alias(target)  => writeln!(out, "{:indent$}{name} Alias ({target})", "")?,
+            object                 => writeln!(out, "{:indent$}{name} {}", "", object.type_name())?
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,66 @@
+pub mod handler;
+pub mod namespace;
+pub mod value;
+mod interpreter;
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+
+use crate::acpi::aml::handler::{Handler, KernelHandler};
+use crate::acpi::aml::namespace::Namespace;
+use crate::acpi::tables::ACPI;
+
+pub struct Aml<H: Handler> {
+    namespace: Namespace,
+    handler:   H,
+    depth:     usize,
+    // All ones for the integer width, which revision 1 definition blocks cut to 32 bits
+    ones:      u64
+}
+
+impl<H: Handler> Aml<H> {
+    pub fn new(handler: H) -> Aml<H> {
+        Aml { namespace: Namespace::new(), handler, depth: 0, ones: u64::MAX }
+    }
+
+    pub fn namespace(&self) -> &Namespace {
+        &self.namespace
+    }
+}
+
+static mut AML: Option<Aml<KernelHandler>> = None;
+
+impl Aml<KernelHandler> {
+    // The DSDT goes first, the SSDTs add to and refer into its namespace
+    pub fn init_global(acpi: &ACPI) -> Result<()> {
+        info!("Loading AML..");
+
+        let mut aml = Aml::new(KernelHandler::new());
+        let dsdt = acpi.raw_table("DSDT").ok_or(anyhow!("No DSDT"))?;
+        aml.load(dsdt.bytes())?;
+
+        for ssdt in acpi.raw_tables().into_iter().filter(|table| table.signature() == "SSDT") {
+            if let Err(e) = aml.load(ssdt.bytes()) {
+                warn!("SSDT at 0x{:x}: {e}", ssdt.addr);
+            }
+        }
+
+        if aml.namespace.contains("\\_SB_._INI") {
+            if let Err(e) = aml.evaluate("\\_SB._INI", Vec::new()) {
+                warn!("{e}");
+            }
+        }
+
+        info!("{} objects in the namespace", aml.namespace.subtree(namespace::ROOT).count());
+        unsafe { AML = Some(aml); }
+
+        Ok(())
+    }
+}
+
+pub fn get() -> Option<&'static mut Aml<KernelHandler>> {
+    unsafe { AML.as_mut() }
+}
@@ -0,0 +1,114 @@
+use core::time::Duration;
+use acpi::AcpiHandler;
+use anyhow::{anyhow, Result};
+use x86_64::instructions::interrupts;
+
+use crate::acpi::mapper::AcpiMapper;
+use crate::acpi::pci::PCI;
+use crate::hal::{PortIo, Ports};
+use crate::time;
+
+const SPINS_PER_MICRO: u128 = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Space {
+    Memory,
+    Io,
+    PciConfig { bus: u8, device: u8, function: u8 },
+    Other(u8)
+}
+
+// Everything the interpreter needs from the machine, so it can run against a fake one on the host
+pub trait Handler {
+    fn read(&mut self, space: Space, address: u64, bits: u8) -> Result<u64>;
+    fn write(&mut self, space: Space, address: u64, bits: u8, value: u64) -> Result<()>;
+    fn sleep(&mut self, duration: Duration);
+}
+
+pub struct KernelHandler {
+    mapper: AcpiMapper
+}
+
+impl KernelHandler {
+    pub fn new() -> KernelHandler {
+        KernelHandler { mapper: AcpiMapper::new() }
+    }
+}
+
+impl Default for KernelHandler {
+    fn default() -> KernelHandler {
+        KernelHandler::new()
+    }
+}
+
+impl Handler for KernelHandler {
+    fn read(&mut self, space: Space, address: u64, bits: u8) -> Result<u64> {
+        unsafe {
+            match space {
+                Space::Memory => {
+                    let mapping = self.mapper.map_physical_region::<u8>(address as usize, bits as usize / 8);
+                    let ptr = mapping.virtual_start().as_ptr();
+
+                    Ok(match bits {
+                        8  => ptr.read_volatile() as u64,
+                        16 => (ptr as *const u16).read_volatile() as u64,
+                        32 => (ptr as *const u32).read_volatile() as u64,
+                        _  => (ptr as *const u64).read_volatile()
+                    })
+                }
+                Space::Io => Ok(match bits {
+                    8  => Ports.read_u8(address as u16) as u64,
+                    16 => Ports.read_u16(address as u16) as u64,
+                    _  => Ports.read_u32(address as u16) as u64
+                }),
+                Space::PciConfig { bus, device, function } => {
+                    Ok(PCI::read_config(&mut Ports, bus, device, function, address as u8, bits) as u64)
+                }
+                Space::Other(space) => Err(anyhow!("Address space 0x{space:x} is not supported"))
+            }
+        }
+    }
+
+    fn write(&mut self, space: Space, address: u64, bits: u8, value: u64) -> Result<()> {
+        unsafe {
+            match space {
+                Space::Memory => {
+                    let mapping = self.mapper.map_physical_region::<u8>(address as usize, bits as usize / 8);
+                    let ptr = mapping.virtual_start().as_ptr();
+
+                    match bits {
+                        8  => ptr.write_volatile(value as u8),
+                        16 => (ptr as *mut u16).write_volatile(value as u16),
+                        32 => (ptr as *mut u32).write_volatile(value as u32),
+                        _  => (ptr as *mut u64).write_volatile(value)
+                    }
+                }
+                Space::Io => match bits {
+                    8  => Ports.write_u8(address as u16, value as u8),
+                    16 => Ports.write_u16(address as u16, value as u16),
+                    _  => Ports.write_u32(address as u16, value as u32)
+                },
+                Space::PciConfig { bus, device, function } => {
+                    PCI::write_config(&mut Ports, bus, device, function, address as u8, bits, value as u32)
+                }
+                Space::Other(space) => return Err(anyhow!("Address space 0x{space:x} is not supported"))
+            }
+        }
+
+        Ok(())
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        // Ticks only move with the timer interrupt, without it spinning is the best there is
+        if !interrupts::are_enabled() {
+            for _ in 0..duration.as_micros() * SPINS_PER_MICRO { core::hint::spin_loop(); }
+            return;
+        }
+
+        let deadline = time::ticks() + time::ticks_from(duration);
+
+        while time::ticks() < deadline {
+            core::hint::spin_loop();
+        }
+    }
+}
@@ -0,0 +1,1465 @@
+extern crate alloc;
+
+use core::cmp::Ordering;
+use core::mem;
+use core::time::Duration;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use anyhow::{anyhow, Result};
+use log::{debug, info, warn};
+
+use crate::acpi::aml::Aml;
+use crate::acpi::aml::handler::{Handler, Space};
+use crate::acpi::aml::namespace::{self, Name};
+use crate::acpi::aml::value::{BufferField, Field, FieldKind, Method, Object, Region, Source, Update, Value};
+use crate::time;
+
+const MAX_DEPTH:  usize = 32;
+// Firmware polls hardware bits in While loops, a stuck bit should not hang the kernel
+const MAX_LOOPS:  usize = 0x100000;
+const HEADER_LEN: usize = 36;
+// Buffer sizes and package counts come from the firmware, anything this big is a corrupt table rather than real data
+const MAX_BUFFER: usize = 1 << 20;
+
+const EXT_PREFIX:   u8 = 0x5b;
+const DUAL_PREFIX:  u8 = 0x2e;
+const MULTI_PREFIX: u8 = 0x2f;
+const ELSE_OP:      u8 = 0xa1;
+
+#[derive(Clone, Copy)]
+struct Stream {
+    data: &'static [u8],
+    pos:  usize
+}
+
+impl Stream {
+    fn byte(&mut self) -> Result<u8> {
+        let byte = self.peek()?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn peek(&self) -> Result<u8> {
+        self.data.get(self.pos).copied().ok_or(anyhow!("Unexpected end of AML"))
+    }
+
+    fn peek_at(&self, n: usize) -> Option<u8> {
+        self.data.get(self.pos + n).copied()
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'static [u8]> {
+        let bytes = self.data.get(self.pos..self.pos + n).ok_or(anyhow!("Unexpected end of AML"))?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn integer(&mut self, n: usize) -> Result<u64> {
+        Ok(self.bytes(n)?.iter().rev().fold(0, |value, &b| value << 8 | b as u64))
+    }
+
+    // The lead byte's top two bits count the bytes that follow, which hold the higher bits
+    fn raw_length(&mut self) -> Result<usize> {
+        let lead = self.byte()?;
+        let count = (lead >> 6) as usize;
+
+        if count == 0 { return Ok((lead & 0x3f) as usize); }
+
+        let rest = self.integer(count)? as usize;
+        Ok((lead & 0x0f) as usize | rest << 4)
+    }
+
+    // A package length counts its own bytes, so the end is relative to where it starts
+    fn pkg_end(&mut self) -> Result<usize> {
+        let start = self.pos;
+        let end = start + self.raw_length()?;
+
+        if end > self.data.len() { return Err(anyhow!("Package runs past the end of the table")); }
+        Ok(end)
+    }
+
+    // Whatever is left of a package, its length may not cover what was already read from it
+    fn rest(&mut self, end: usize) -> Result<&'static [u8]> {
+        let bytes = self.data.get(self.pos..end).ok_or(anyhow!("Package is shorter than its header"))?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn seg(&mut self) -> Result<[u8; 4]> {
+        let bytes = self.bytes(4)?;
+        if !bytes.iter().all(|&b| b.is_ascii_uppercase() || b.is_ascii_digit() || b == b'_') {
+            return Err(anyhow!("Invalid name segment {bytes:02x?}"));
+        }
+
+        Ok([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    fn name(&mut self) -> Result<Name> {
+        let root = self.peek()? == b'\\';
+        if root { self.pos += 1; }
+
+        let mut parents = 0;
+        while self.peek()? == b'^' {
+            self.pos += 1;
+            parents += 1;
+        }
+
+        let count = match self.peek()? {
+            0x00         => { self.pos += 1; 0 }
+            DUAL_PREFIX  => { self.pos += 1; 2 }
+            MULTI_PREFIX => { self.pos += 1; self.byte()? as usize }
+            _            => 1
+        };
+
+        let segments = (0..count).map(|_| self.seg()).collect::<Result<Vec<_>>>()?;
+        Ok(Name { root, parents, segments })
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let rest = &self.data[self.pos.min(self.data.len())..];
+        let len = rest.iter().position(|&b| b == 0).ok_or(anyhow!("Unterminated string"))?;
+        self.pos += len + 1;
+
+        Ok(rest[..len].iter().map(|&b| b as char).collect())
+    }
+}
+
+fn is_name(byte: u8) -> bool {
+    matches!(byte, b'\\' | b'^' | b'_' | b'A'..=b'Z' | DUAL_PREFIX | MULTI_PREFIX)
+}
+
+// Where a term ends without evaluating it, for the opcodes that carry a package length
+fn extent(data: &'static [u8], start: usize) -> Option<usize> {
+    let mut s = Stream { data, pos: start };
+
+    match s.byte().ok()? {
+        0x10..=0x14 | 0xa0..=0xa2 => {}
+        EXT_PREFIX => match s.byte().ok()? {
+            0x81..=0x87 => {}
+            _           => return None
+        },
+        _ => return None
+    }
+
+    s.pkg_end().ok()
+}
+
+enum Flow {
+    Normal,
+    Return(Value),
+    Break,
+    Continue
+}
+
+#[derive(Clone, Debug)]
+enum Target {
+    None,
+    Debug,
+    Local(usize),
+    Arg(usize),
+    Name(String),
+    Index(Box<Target>, usize)
+}
+
+struct Frame {
+    scope:   String,
+    locals:  [Value; 8],
+    args:    [Value; 7],
+    method:  bool,
+    // Objects a method creates only live until it returns
+    created: Vec<String>
+}
+
+impl Frame {
+    fn new(scope: String, method: bool) -> Frame {
+        Frame {
+            scope,
+            locals: core::array::from_fn(|_| Value::Integer(0)),
+            args:   core::array::from_fn(|_| Value::Integer(0)),
+            method,
+            created: Vec::new()
+        }
+    }
+}
+
+fn access_width(flags: u8) -> u8 {
+    match flags & 0xf {
+        2 => 2,
+        3 => 4,
+        4 => 8,
+        _ => 1
+    }
+}
+
+fn mask(bits: u64) -> u64 {
+    if bits >= 64 { u64::MAX } else { (1 << bits) - 1 }
+}
+
+fn get_bit(bytes: &[u8], bit: u64) -> bool {
+    bytes.get((bit / 8) as usize).is_some_and(|b| b >> (bit % 8) & 1 != 0)
+}
+
+fn set_bit(bytes: &mut [u8], bit: u64, value: bool) {
+    if let Some(b) = bytes.get_mut((bit / 8) as usize) {
+        if value { *b |= 1 << (bit % 8); } else { *b &= !(1 << (bit % 8)); }
+    }
+}
+
+// Narrow fields read as integers and wider ones as buffers
+fn from_bits(bytes: Vec<u8>, bits: u64) -> Value {
+    if bits <= 64 {
+        Value::Integer(bytes.iter().rev().fold(0, |value, &b| value << 8 | b as u64))
+    } else {
+        Value::Buffer(bytes)
+    }
+}
+
+fn compare(a: &Value, b: &Value) -> Result<Ordering> {
+    match a {
+        Value::Integer(a) => Ok(a.cmp(&b.as_integer()?)),
+        Value::String(a)  => Ok(a.as_str().cmp(b.as_string()?.as_str())),
+        Value::Buffer(a)  => Ok(a.as_slice().cmp(b.as_buffer()?.as_slice())),
+        _                 => Err(anyhow!("Cannot compare a {}", a.type_name()))
+    }
+}
+
+fn osi(feature: &str) -> bool {
+    const FEATURES: [&str; 5] = [
+        "Module Device", "Processor Device", "3.0 Thermal Model", "Extended Address Space Descriptor",
+        "Processor Aggregator Device"
+    ];
+
+    // Firmware is tested against Windows, claiming every version gets the best-trodden paths
+    feature.starts_with("Windows ") || FEATURES.contains(&feature)
+}
+
+impl<H: Handler> Aml<H> {
+    // Takes the whole table, header included, since its revision decides the integer width
+    pub fn load(&mut self, table: &'static [u8]) -> Result<()> {
+        if table.len() < HEADER_LEN { return Err(anyhow!("Definition block is truncated")); }
+        if table[8] < 2 { self.ones = u32::MAX as u64; }
+
+        let mut s = Stream { data: table, pos: HEADER_LEN };
+        let mut frame = Frame::new(namespace::ROOT.into(), false);
+
+        self.term_list(&mut s, table.len(), &mut frame)?;
+        Ok(())
+    }
+
+    pub fn evaluate(&mut self, path: &str, args: Vec<Value>) -> Result<Value> {
+        let path = self.namespace.path(namespace::ROOT, &Name::parse(path)?)?;
+
+        match self.namespace.get(&path) {
+            Some(Object::Method(_)) => self.call(path, args),
+            Some(_)                 => self.read_name(&path, &mut Frame::new(namespace::ROOT.into(), false)),
+            None                    => Err(anyhow!("{path} is not defined"))
+        }
+    }
+
+    fn call(&mut self, path: String, args: Vec<Value>) -> Result<Value> {
+        let Some(Object::Method(method)) = self.namespace.get(&path).cloned() else {
+            return Err(anyhow!("{path} is not a method"));
+        };
+
+        if path == "\\_OSI" {
+            let feature = args.first().ok_or(anyhow!("_OSI needs an argument"))?.as_string()?;
+            return Ok(Value::Integer(if osi(&feature) { self.ones } else { 0 }));
+        }
+
+        if self.depth >= MAX_DEPTH { return Err(anyhow!("Method calls nest too deep at {path}")); }
+
+        let mut frame = Frame::new(path.clone(), true);
+        for (slot, arg) in frame.args.iter_mut().zip(args).take(method.args as usize) {
+            *slot = arg;
+        }
+
+        self.depth += 1;
+        let mut s = Stream { data: method.body, pos: 0 };
+        let result = self.term_list(&mut s, method.body.len(), &mut frame);
+        self.depth -= 1;
+
+        for name in frame.created.iter().rev() {
+            self.namespace.remove(name);
+        }
+
+        match result.map_err(|e| anyhow!("{path}: {e}"))? {
+            Flow::Return(value) => Ok(value),
+            _                   => Ok(Value::Integer(0))
+        }
+    }
+
+    fn term_list(&mut self, s: &mut Stream, end: usize, frame: &mut Frame) -> Result<Flow> {
+        while s.pos < end {
+            let start = s.pos;
+
+            match self.term(s, end, frame) {
+                Ok(Flow::Normal) => {}
+                Ok(flow)         => return Ok(flow),
+                // While loading, one bad object should not take the rest of the table with it
+                Err(e) if !frame.method => {
+                    let Some(skip) = extent(s.data, start) else { return Err(e); };
+                    warn!("Skipping AML at 0x{start:x}: {e}");
+                    s.pos = skip;
+                }
+                Err(e) => return Err(e)
+            }
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    fn define(&mut self, frame: &mut Frame, path: String, object: Object) -> Result<()> {
+        if frame.method && !self.namespace.contains(&path) {
+            frame.created.push(path.clone());
+        }
+
+        self.namespace.insert(path, object)
+    }
+
+    fn scoped(&mut self, s: &mut Stream, end: usize, frame: &mut Frame, scope: String) -> Result<Flow> {
+        let outer = mem::replace(&mut frame.scope, scope);
+        let flow = self.term_list(s, end, frame);
+        frame.scope = outer;
+
+        flow
+    }
+
+    fn new_path(&self, s: &mut Stream, frame: &Frame) -> Result<String> {
+        let name = s.name()?;
+        self.namespace.path(&frame.scope, &name)
+    }
+
+    fn term(&mut self, s: &mut Stream, end: usize, frame: &mut Frame) -> Result<Flow> {
+        let op = s.peek()?;
+        let ext = if op == EXT_PREFIX { s.peek_at(1).unwrap_or(0) } else { 0 };
+
+        match (op, ext) {
+            // Alias
+            (0x06, _) => {
+                s.pos += 1;
+                let source = s.name()?;
+                let source = self.namespace.resolve(&frame.scope, &source)?;
+                let alias = self.new_path(s, frame)?;
+                self.define(frame, alias, Object::Alias(source))?;
+            }
+            // Name
+            (0x08, _) => {
+                s.pos += 1;
+                let path = self.new_path(s, frame)?;
+                let value = self.term_arg(s, frame)?;
+                self.define(frame, path, Object::Name(value))?;
+            }
+            // Scope
+            (0x10, _) => {
+                s.pos += 1;
+                let end = s.pkg_end()?;
+                let name = s.name()?;
+                let path = self.namespace.resolve(&frame.scope, &name)?;
+                if !self.namespace.contains(&path) {
+                    self.namespace.insert(path.clone(), Object::Scope)?;
+                }
+
+                return self.scoped(s, end, frame, path);
+            }
+            // Method
+            (0x14, _) => {
+                s.pos += 1;
+                let end = s.pkg_end()?;
+                let path = self.new_path(s, frame)?;
+                let flags = s.byte()?;
+                let method = Method { args: flags & 7, serialized: flags & 8 != 0, body: s.rest(end)? };
+                self.define(frame, path, Object::Method(method))?;
+            }
+            // External
+            (0x15, _) => {
+                s.pos += 1;
+                s.name()?;
+                s.bytes(2)?;
+            }
+            // If, with an optional Else that must belong to this term list
+            (0xa0, _) => {
+                s.pos += 1;
+                let body_end = s.pkg_end()?;
+                let predicate = self.term_arg(s, frame)?.as_integer()? != 0;
+
+                let flow = if predicate {
+                    self.term_list(s, body_end, frame)?
+                } else {
+                    s.pos = body_end;
+                    Flow::Normal
+                };
+
+                if s.pos < end && s.peek()? == ELSE_OP {
+                    s.pos += 1;
+                    let else_end = s.pkg_end()?;
+
+                    if predicate {
+                        s.pos = else_end;
+                    } else {
+                        return self.term_list(s, else_end, frame);
+                    }
+                }
+
+                return Ok(flow);
+            }
+            // A stray Else, its If was taken
+            (ELSE_OP, _) => {
+                s.pos += 1;
+                s.pos = s.pkg_end()?;
+            }
+            // While
+            (0xa2, _) => {
+                s.pos += 1;
+                let body_end = s.pkg_end()?;
+                let start = s.pos;
+
+                let mut iterations = 0;
+
+                loop {
+                    s.pos = start;
+                    if self.term_arg(s, frame)?.as_integer()? == 0 { break; }
+
+                    match self.term_list(s, body_end, frame)? {
+                        Flow::Break         => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        _                   => {}
+                    }
+
+                    iterations += 1;
+                    if iterations == MAX_LOOPS { return Err(anyhow!("While loop did not terminate")); }
+                }
+
+                s.pos = body_end;
+            }
+            // Return
+            (0xa4, _) => {
+                s.pos += 1;
+                let value = if s.pos < end { self.term_arg(s, frame)? } else { Value::Integer(0) };
+                return Ok(Flow::Return(value));
+            }
+            // Break, Continue, Noop, BreakPoint
+            (0xa5, _) => { s.pos += 1; return Ok(Flow::Break); }
+            (0x9f, _) => { s.pos += 1; return Ok(Flow::Continue); }
+            (0xa3, _) | (0xcc, _) => { s.pos += 1; }
+            // Notify
+            (0x86, _) => {
+                s.pos += 1;
+                let object = self.target(s, frame)?;
+                let value = self.term_arg(s, frame)?.as_integer()?;
+                debug!("Notify ({object:?}, 0x{value:x})");
+            }
+            // Mutex
+            (EXT_PREFIX, 0x01) => {
+                s.pos += 2;
+                let path = self.new_path(s, frame)?;
+                s.byte()?;
+                self.define(frame, path, Object::Mutex)?;
+            }
+            // Event
+            (EXT_PREFIX, 0x02) => {
+                s.pos += 2;
+                let path = self.new_path(s, frame)?;
+                self.define(frame, path, Object::Event)?;
+            }
+            // Stall and Sleep
+            (EXT_PREFIX, 0x21) => {
+                s.pos += 2;
+                let micros = self.term_arg(s, frame)?.as_integer()?;
+                self.handler.sleep(Duration::from_micros(micros));
+            }
+            (EXT_PREFIX, 0x22) => {
+                s.pos += 2;
+                let millis = self.term_arg(s, frame)?.as_integer()?;
+                self.handler.sleep(Duration::from_millis(millis));
+            }
+            // Signal, Reset and Release have nothing to do with a single thread of AML
+            (EXT_PREFIX, 0x24) | (EXT_PREFIX, 0x26) | (EXT_PREFIX, 0x27) => {
+                s.pos += 2;
+                self.target(s, frame)?;
+            }
+            // Fatal
+            (EXT_PREFIX, 0x32) => {
+                s.pos += 2;
+                let kind = s.byte()?;
+                let code = s.integer(4)?;
+                let arg = self.term_arg(s, frame)?.as_integer()?;
+                return Err(anyhow!("Fatal (0x{kind:x}, 0x{code:x}, 0x{arg:x})"));
+            }
+            // OperationRegion
+            (EXT_PREFIX, 0x80) => {
+                s.pos += 2;
+                let path = self.new_path(s, frame)?;
+                let space = s.byte()?;
+                let offset = self.term_arg(s, frame)?.as_integer()?;
+                let length = self.term_arg(s, frame)?.as_integer()?;
+                self.define(frame, path, Object::Region(Region { space, offset, length }))?;
+            }
+            // Field
+            (EXT_PREFIX, 0x81) => {
+                s.pos += 2;
+                let end = s.pkg_end()?;
+                let region = s.name()?;
+                let region = self.namespace.resolve(&frame.scope, &region)?;
+                let flags = s.byte()?;
+                self.field_list(s, end, frame, FieldKind::Region(region), flags)?;
+            }
+            // Device, Processor, PowerResource and ThermalZone, scopes with a few bytes of their own
+            (EXT_PREFIX, 0x82..=0x85) => {
+                s.pos += 2;
+                let end = s.pkg_end()?;
+                let path = self.new_path(s, frame)?;
+
+                let object = match ext {
+                    0x82 => Object::Device,
+                    0x83 => { s.bytes(6)?; Object::Processor }
+                    0x84 => { s.bytes(3)?; Object::PowerResource }
+                    _    => Object::ThermalZone
+                };
+
+                self.define(frame, path.clone(), object)?;
+                return self.scoped(s, end, frame, path);
+            }
+            // IndexField
+            (EXT_PREFIX, 0x86) => {
+                s.pos += 2;
+                let end = s.pkg_end()?;
+                let index = s.name()?;
+                let index = self.namespace.resolve(&frame.scope, &index)?;
+                let data = s.name()?;
+                let data = self.namespace.resolve(&frame.scope, &data)?;
+                let flags = s.byte()?;
+                self.field_list(s, end, frame, FieldKind::Index { index, data }, flags)?;
+            }
+            (EXT_PREFIX, 0x87) => return Err(anyhow!("BankField is not supported")),
+            // DataRegion, which would need the table to be looked up by signature
+            (EXT_PREFIX, 0x88) => {
+                s.pos += 2;
+                let path = self.new_path(s, frame)?;
+                for _ in 0..3 { self.term_arg(s, frame)?; }
+                warn!("DataRegion {path} is not supported");
+                self.define(frame, path, Object::Region(Region { space: 0, offset: 0, length: 0 }))?;
+            }
+            // CreateDWordField, CreateWordField, CreateByteField, CreateBitField, CreateQWordField
+            (0x8a..=0x8d, _) | (0x8f, _) => {
+                s.pos += 1;
+                let source = self.source(s, frame)?;
+                let index = self.term_arg(s, frame)?.as_integer()?;
+
+                let (bit_offset, bit_length) = match op {
+                    0x8a => (index * 8, 32),
+                    0x8b => (index * 8, 16),
+                    0x8c => (index * 8, 8),
+                    0x8d => (index, 1),
+                    _    => (index * 8, 64)
+                };
+
+                let path = self.new_path(s, frame)?;
+                self.define(frame, path, Object::BufferField(BufferField { source, bit_offset, bit_length }))?;
+            }
+            // CreateField
+            (EXT_PREFIX, 0x13) => {
+                s.pos += 2;
+                let source = self.source(s, frame)?;
+                let bit_offset = self.term_arg(s, frame)?.as_integer()?;
+                let bit_length = self.term_arg(s, frame)?.as_integer()?;
+                let path = self.new_path(s, frame)?;
+                self.define(frame, path, Object::BufferField(BufferField { source, bit_offset, bit_length }))?;
+            }
+            _ => { self.term_arg(s, frame)?; }
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    fn field_list(&mut self, s: &mut Stream, end: usize, frame: &mut Frame, kind: FieldKind, flags: u8) -> Result<()> {
+        let mut access = access_width(flags);
+        let update = match flags >> 5 & 3 {
+            1 => Update::WriteAsOnes,
+            2 => Update::WriteAsZeros,
+            _ => Update::Preserve
+        };
+
+        let mut offset = 0;
+
+        while s.pos < end {
+            match s.peek()? {
+                // ReservedField
+                0x00 => {
+                    s.pos += 1;
+                    offset += s.raw_length()? as u64;
+                }
+                // AccessField and ExtendedAccessField
+                0x01 | 0x03 => {
+                    let extended = s.byte()? == 0x03;
+                    access = access_width(s.byte()?);
+                    s.bytes(if extended { 2 } else { 1 })?;
+                }
+                // ConnectField only matters for GPIO and serial bus regions
+                0x02 => {
+                    s.pos += 1;
+                    if s.peek()? == 0x11 { self.term_arg(s, frame)?; } else { s.name()?; }
+                }
+                _ => {
+                    let seg = s.seg()?;
+                    let bit_length = s.raw_length()? as u64;
+                    let field = Field { kind: kind.clone(), bit_offset: offset, bit_length, access, update };
+
+                    self.define(frame, namespace::join(&frame.scope, &seg), Object::Field(field))?;
+                    offset += bit_length;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn source(&mut self, s: &mut Stream, frame: &mut Frame) -> Result<Source> {
+        match s.peek()? {
+            op @ 0x60..=0x67 => { s.pos += 1; Ok(Source::Local((op - 0x60) as usize)) }
+            op @ 0x68..=0x6e => { s.pos += 1; Ok(Source::Arg((op - 0x68) as usize)) }
+            op if is_name(op) => {
+                let name = s.name()?;
+                Ok(Source::Name(self.namespace.resolve(&frame.scope, &name)?))
+            }
+            op => Err(anyhow!("Unsupported buffer field source 0x{op:02x}"))
+        }
+    }
+
+    // SuperName and Target, where results go
+    fn target(&mut self, s: &mut Stream, frame: &mut Frame) -> Result<Target> {
+        let op = s.peek()?;
+
+        match op {
+            0x00             => { s.pos += 1; Ok(Target::None) }
+            0x60..=0x67      => { s.pos += 1; Ok(Target::Local((op - 0x60) as usize)) }
+            0x68..=0x6e      => { s.pos += 1; Ok(Target::Arg((op - 0x68) as usize)) }
+            EXT_PREFIX if s.peek_at(1) == Some(0x31) => { s.pos += 2; Ok(Target::Debug) }
+            // Index
+            0x88 => {
+                s.pos += 1;
+                let source = self.target(s, frame)?;
+                let index = self.term_arg(s, frame)?.as_integer()? as usize;
+                self.target(s, frame)?;
+                Ok(Target::Index(Box::new(source), index))
+            }
+            // DerefOf
+            0x83 => {
+                s.pos += 1;
+                match self.term_arg(s, frame)? {
+                    Value::Reference(path) => Ok(Target::Name(path)),
+                    Value::String(path)    => Ok(Target::Name(self.namespace.resolve(&frame.scope, &Name::parse(&path)?)?)),
+                    value                  => Err(anyhow!("Cannot dereference a {}", value.type_name()))
+                }
+            }
+            op if is_name(op) => {
+                let name = s.name()?;
+                Ok(Target::Name(self.namespace.resolve(&frame.scope, &name)?))
+            }
+            op => Err(anyhow!("Unsupported target 0x{op:02x}"))
+        }
+    }
+
+    fn term_arg(&mut self, s: &mut Stream, frame: &mut Frame) -> Result<Value> {
+        let op = s.byte()?;
+
+        let value = match op {
+            0x00 => Value::Integer(0),
+            0x01 => Value::Integer(1),
+            0xff => Value::Integer(self.ones),
+            0x0a => Value::Integer(s.integer(1)?),
+            0x0b => Value::Integer(s.integer(2)?),
+            0x0c => Value::Integer(s.integer(4)?),
+            0x0e => Value::Integer(s.integer(8)?),
+            0x0d => Value::String(s.string()?),
+            // Buffer
+            0x11 => {
+                let end = s.pkg_end()?;
+                let size = self.term_arg(s, frame)?.as_integer()?;
+                let init = s.rest(end)?;
+
+                let size = usize::try_from(size).ok().filter(|&size| size <= MAX_BUFFER).ok_or(anyhow!("Buffer of {size} bytes"))?;
+                let mut bytes = vec![0; size.max(init.len())];
+                bytes[..init.len()].copy_from_slice(init);
+                Value::Buffer(bytes)
+            }
+            // Package and VarPackage
+            0x12 | 0x13 => {
+                let end = s.pkg_end()?;
+                let count = if op == 0x12 { s.byte()? as u64 } else { self.term_arg(s, frame)?.as_integer()? };
+                let count = usize::try_from(count).ok().filter(|&count| count <= MAX_BUFFER).ok_or(anyhow!("Package of {count} elements"))?;
+                let mut elements = Vec::new();
+
+                while s.pos < end {
+                    if is_name(s.peek()?) {
+                        let name = s.name()?;
+                        elements.push(Value::Reference(self.namespace.resolve(&frame.scope, &name)?));
+                    } else {
+                        elements.push(self.term_arg(s, frame)?);
+                    }
+                }
+
+                while elements.len() < count { elements.push(Value::Integer(0)); }
+                Value::Package(elements)
+            }
+            0x60..=0x67 => frame.locals[(op - 0x60) as usize].clone(),
+            0x68..=0x6e => match &frame.args[(op - 0x68) as usize] {
+                Value::Reference(path) if !matches!(self.namespace.get(path), Some(Object::Method(_))) => {
+                    let path = path.clone();
+                    self.read_name(&path, frame)?
+                }
+                value => value.clone()
+            },
+            // Store and CopyObject
+            0x70 | 0x9d => {
+                let value = self.term_arg(s, frame)?;
+                let target = self.target(s, frame)?;
+                self.store(&target, value.clone(), frame)?;
+                value
+            }
+            // RefOf
+            0x71 => match self.target(s, frame)? {
+                Target::Name(path) => Value::Reference(path),
+                target             => self.read_target(&target, frame)?
+            },
+            // Add, Subtract, Multiply, ShiftLeft, ShiftRight, And, Nand, Or, Nor, Xor, Mod
+            0x72 | 0x74 | 0x77 | 0x79..=0x7f | 0x85 => {
+                let a = self.term_arg(s, frame)?.as_integer()?;
+                let b = self.term_arg(s, frame)?.as_integer()?;
+
+                let result = match op {
+                    0x72 => a.wrapping_add(b),
+                    0x74 => a.wrapping_sub(b),
+                    0x77 => a.wrapping_mul(b),
+                    0x79 => if b >= 64 { 0 } else { a << b },
+                    0x7a => if b >= 64 { 0 } else { a >> b },
+                    0x7b => a & b,
+                    0x7c => !(a & b),
+                    0x7d => a | b,
+                    0x7e => !(a | b),
+                    0x7f => a ^ b,
+                    _    => a.checked_rem(b).ok_or(anyhow!("Division by zero"))?
+                };
+
+                self.result(s, frame, Value::Integer(result & self.ones))?
+            }
+            // Concat
+            0x73 => {
+                let a = self.term_arg(s, frame)?;
+                let b = self.term_arg(s, frame)?;
+
+                let result = match &a {
+                    Value::Integer(_) => {
+                        let mut bytes = a.as_buffer()?;
+                        bytes.extend(b.as_integer()?.to_le_bytes());
+                        Value::Buffer(bytes)
+                    }
+                    Value::String(s) => Value::String(s.clone() + &b.as_string()?),
+                    Value::Buffer(bytes) => {
+                        let mut bytes = bytes.clone();
+                        bytes.extend(b.as_buffer()?);
+                        Value::Buffer(bytes)
+                    }
+                    _ => return Err(anyhow!("Cannot concatenate a {}", a.type_name()))
+                };
+
+                self.result(s, frame, result)?
+            }
+            // Increment and Decrement
+            0x75 | 0x76 => {
+                let target = self.target(s, frame)?;
+                let value = self.read_target(&target, frame)?.as_integer()?;
+                let value = if op == 0x75 { value.wrapping_add(1) } else { value.wrapping_sub(1) } & self.ones;
+
+                self.store(&target, Value::Integer(value), frame)?;
+                Value::Integer(value)
+            }
+            // Divide stores the remainder and then the quotient
+            0x78 => {
+                let a = self.term_arg(s, frame)?.as_integer()?;
+                let b = self.term_arg(s, frame)?.as_integer()?;
+                if b == 0 { return Err(anyhow!("Division by zero")); }
+
+                let remainder = self.target(s, frame)?;
+                self.store(&remainder, Value::Integer(a % b), frame)?;
+                self.result(s, frame, Value::Integer(a / b))?
+            }
+            // Not, FindSetLeftBit, FindSetRightBit
+            0x80..=0x82 => {
+                let a = self.term_arg(s, frame)?.as_integer()?;
+
+                let result = match op {
+                    0x80 => !a & self.ones,
+                    0x81 => if a == 0 { 0 } else { 64 - a.leading_zeros() as u64 },
+                    _    => if a == 0 { 0 } else { a.trailing_zeros() as u64 + 1 }
+                };
+
+                self.result(s, frame, Value::Integer(result))?
+            }
+            // DerefOf
+            0x83 => match self.term_arg(s, frame)? {
+                Value::Reference(path) => self.read_name(&path, frame)?,
+                Value::String(path)    => {
+                    let path = self.namespace.resolve(&frame.scope, &Name::parse(&path)?)?;
+                    self.read_name(&path, frame)?
+                }
+                value => value
+            },
+            // ConcatenateResTemplate, dropping the end tag of the first template
+            0x84 => {
+                let mut a = self.term_arg(s, frame)?.as_buffer()?;
+                let b = self.term_arg(s, frame)?.as_buffer()?;
+
+                if a.len() >= 2 && a[a.len() - 2] == 0x79 { a.truncate(a.len() - 2); }
+                a.extend(b);
+                self.result(s, frame, Value::Buffer(a))?
+            }
+            // SizeOf
+            0x87 => {
+                let target = self.target(s, frame)?;
+
+                Value::Integer(match self.read_target(&target, frame)? {
+                    Value::String(s)     => s.len() as u64,
+                    Value::Buffer(b)     => b.len() as u64,
+                    Value::Package(p)    => p.len() as u64,
+                    value                => return Err(anyhow!("SizeOf a {}", value.type_name()))
+                })
+            }
+            // Index, which stands for the element itself
+            0x88 => {
+                let source = self.term_arg(s, frame)?;
+                let index = self.term_arg(s, frame)?.as_integer()? as usize;
+                let element = Aml::<H>::element(&source, index)?;
+                self.result(s, frame, element)?
+            }
+            // Match
+            0x89 => {
+                let Value::Package(elements) = self.term_arg(s, frame)? else {
+                    return Err(anyhow!("Match needs a package"));
+                };
+
+                let op1 = s.byte()?;
+                let a = self.term_arg(s, frame)?;
+                let op2 = s.byte()?;
+                let b = self.term_arg(s, frame)?;
+                let start = self.term_arg(s, frame)?.as_integer()? as usize;
+
+                let matches = |op: u8, element: &Value, operand: &Value| -> bool {
+                    let Ok(ordering) = compare(element, operand) else { return false; };
+
+                    match op {
+                        0 => true,
+                        1 => ordering == Ordering::Equal,
+                        2 => ordering != Ordering::Greater,
+                        3 => ordering == Ordering::Less,
+                        4 => ordering != Ordering::Less,
+                        5 => ordering == Ordering::Greater,
+                        _ => false
+                    }
+                };
+
+                let found = elements.iter().enumerate().skip(start)
+                    .find(|(_, element)| matches(op1, element, &a) && matches(op2, element, &b));
+
+                Value::Integer(found.map_or(self.ones, |(i, _)| i as u64))
+            }
+            // ObjectType
+            0x8e => {
+                let target = self.target(s, frame)?;
+
+                Value::Integer(match &target {
+                    Target::Name(path) => self.namespace.get(path).map_or(0, |object| object.type_code()),
+                    target             => self.read_target(target, frame)?.type_code()
+                })
+            }
+            // LAnd, LOr
+            0x90 | 0x91 => {
+                let a = self.term_arg(s, frame)?.as_integer()? != 0;
+                let b = self.term_arg(s, frame)?.as_integer()? != 0;
+                self.boolean(if op == 0x90 { a && b } else { a || b })
+            }
+            // LNot, which also spells LNotEqual, LLessEqual and LGreaterEqual
+            0x92 => {
+                let a = self.term_arg(s, frame)?.as_integer()? != 0;
+                self.boolean(!a)
+            }
+            // LEqual, LGreater, LLess
+            0x93..=0x95 => {
+                let a = self.term_arg(s, frame)?;
+                let b = self.term_arg(s, frame)?;
+
+                let ordering = compare(&a, &b)?;
+                self.boolean(ordering == [Ordering::Equal, Ordering::Greater, Ordering::Less][(op - 0x93) as usize])
+            }
+            // ToBuffer
+            0x96 => {
+                let value = self.term_arg(s, frame)?.as_buffer()?;
+                self.result(s, frame, Value::Buffer(value))?
+            }
+            // ToDecimalString
+            0x97 => {
+                let value = match self.term_arg(s, frame)? {
+                    Value::Integer(value) => alloc::format!("{value}"),
+                    Value::Buffer(bytes)  => bytes.iter().map(|b| alloc::format!("{b}")).collect::<Vec<_>>().join(","),
+                    value                 => value.as_string()?
+                };
+
+                self.result(s, frame, Value::String(value))?
+            }
+            // ToHexString
+            0x98 => {
+                let value = match self.term_arg(s, frame)? {
+                    Value::Buffer(bytes) => bytes.iter().map(|b| alloc::format!("0x{b:02X}")).collect::<Vec<_>>().join(","),
+                    value                => value.as_string()?
+                };
+
+                self.result(s, frame, Value::String(value))?
+            }
+            // ToInteger, where strings are decimal unless they say otherwise
+            0x99 => {
+                let value = match self.term_arg(s, frame)? {
+                    Value::String(s) if !s.starts_with("0x") && !s.starts_with("0X") => {
+                        s.bytes().take_while(u8::is_ascii_digit).fold(0u64, |v, d| v.wrapping_mul(10).wrapping_add((d - b'0') as u64))
+                    }
+                    value => value.as_integer()?
+                };
+
+                self.result(s, frame, Value::Integer(value))?
+            }
+            // ToString
+            0x9c => {
+                let bytes = self.term_arg(s, frame)?.as_buffer()?;
+                let limit = self.term_arg(s, frame)?.as_integer()? as usize;
+
+                let value = bytes.iter().take(limit).take_while(|&&b| b != 0).map(|&b| b as char).collect();
+                self.result(s, frame, Value::String(value))?
+            }
+            // Mid
+            0x9e => {
+                let source = self.term_arg(s, frame)?;
+                let index = self.term_arg(s, frame)?.as_integer()? as usize;
+                let length = self.term_arg(s, frame)?.as_integer()? as usize;
+
+                let value = match source {
+                    Value::String(s) => Value::String(s.chars().skip(index).take(length).collect()),
+                    value            => Value::Buffer(value.as_buffer()?.into_iter().skip(index).take(length).collect())
+                };
+
+                self.result(s, frame, value)?
+            }
+            EXT_PREFIX => return self.ext_term_arg(s, frame),
+            op if is_name(op) => {
+                s.pos -= 1;
+                let name = s.name()?;
+                let path = self.namespace.resolve(&frame.scope, &name)?;
+
+                match self.namespace.get(&path) {
+                    Some(Object::Method(method)) => {
+                        let count = method.args;
+                        let args = (0..count).map(|_| self.term_arg(s, frame)).collect::<Result<Vec<_>>>()?;
+                        self.call(path, args)?
+                    }
+                    Some(_) => self.read_name(&path, frame)?,
+                    None    => return Err(anyhow!("{path} is not defined"))
+                }
+            }
+            op => return Err(anyhow!("Unsupported opcode 0x{op:02x} at 0x{:x}", s.pos - 1))
+        };
+
+        Ok(value)
+    }
+
+    fn ext_term_arg(&mut self, s: &mut Stream, frame: &mut Frame) -> Result<Value> {
+        let op = s.byte()?;
+
+        let value = match op {
+            // CondRefOf
+            0x12 => {
+                let target = self.target(s, frame)?;
+
+                match target {
+                    Target::Name(path) if self.namespace.contains(&path) => {
+                        let result = self.target(s, frame)?;
+                        self.store(&result, Value::Reference(path), frame)?;
+                        Value::Integer(self.ones)
+                    }
+                    _ => {
+                        self.target(s, frame)?;
+                        Value::Integer(0)
+                    }
+                }
+            }
+            // Acquire and Wait always succeed, nothing else runs AML
+            0x23 => {
+                self.target(s, frame)?;
+                s.bytes(2)?;
+                Value::Integer(0)
+            }
+            0x25 => {
+                self.target(s, frame)?;
+                self.term_arg(s, frame)?;
+                Value::Integer(0)
+            }
+            // FromBCD and ToBCD
+            0x28 => {
+                let mut value = self.term_arg(s, frame)?.as_integer()?;
+                let mut result = 0;
+                let mut scale = 1;
+
+                while value != 0 {
+                    result += (value & 0xf) * scale;
+                    scale *= 10;
+                    value >>= 4;
+                }
+
+                self.result(s, frame, Value::Integer(result))?
+            }
+            0x29 => {
+                let mut value = self.term_arg(s, frame)?.as_integer()?;
+                let mut result = 0;
+                let mut shift = 0;
+
+                while value != 0 && shift < 64 {
+                    result |= (value % 10) << shift;
+                    shift += 4;
+                    value /= 10;
+                }
+
+                self.result(s, frame, Value::Integer(result))?
+            }
+            // Revision
+            0x30 => Value::Integer(2),
+            // Debug as a source reads as nothing
+            0x31 => Value::Integer(0),
+            // Timer, in 100ns units
+            0x33 => Value::Integer((time::uptime().as_nanos() / 100) as u64),
+            0x1f | 0x20 => return Err(anyhow!("Loading tables at runtime is not supported")),
+            op => return Err(anyhow!("Unsupported opcode 0x5b 0x{op:02x} at 0x{:x}", s.pos - 2))
+        };
+
+        Ok(value)
+    }
+
+    fn boolean(&self, value: bool) -> Value {
+        Value::Integer(if value { self.ones } else { 0 })
+    }
+
+    // Most operators end with an optional target for their result
+    fn result(&mut self, s: &mut Stream, frame: &mut Frame, value: Value) -> Result<Value> {
+        let target = self.target(s, frame)?;
+        self.store(&target, value.clone(), frame)?;
+        Ok(value)
+    }
+
+    fn element(source: &Value, index: usize) -> Result<Value> {
+        let element = match source {
+            Value::Package(elements) => elements.get(index).cloned(),
+            Value::Buffer(bytes)     => bytes.get(index).map(|&b| Value::Integer(b as u64)),
+            Value::String(s)         => s.as_bytes().get(index).map(|&b| Value::Integer(b as u64)),
+            value                    => return Err(anyhow!("Cannot index a {}", value.type_name()))
+        };
+
+        element.ok_or(anyhow!("Index {index} is out of bounds"))
+    }
+
+    fn read_target(&mut self, target: &Target, frame: &mut Frame) -> Result<Value> {
+        match target {
+            Target::None | Target::Debug => Ok(Value::Integer(0)),
+            Target::Local(i) => Ok(frame.locals[*i].clone()),
+            Target::Arg(i)   => match &frame.args[*i] {
+                Value::Reference(path) => { let path = path.clone(); self.read_name(&path, frame) }
+                value                  => Ok(value.clone())
+            },
+            Target::Name(path)          => self.read_name(path, frame),
+            Target::Index(source, index) => {
+                let source = self.read_target(source, frame)?;
+                Aml::<H>::element(&source, *index)
+            }
+        }
+    }
+
+    fn store(&mut self, target: &Target, value: Value, frame: &mut Frame) -> Result<()> {
+        match target {
+            Target::None     => {}
+            Target::Debug    => info!("AML: {value}"),
+            Target::Local(i) => frame.locals[*i] = value,
+            Target::Arg(i)   => match &frame.args[*i] {
+                Value::Reference(path) => { let path = path.clone(); self.store_name(&path, value, frame)?; }
+                _                      => frame.args[*i] = value
+            },
+            Target::Name(path) => self.store_name(path, value, frame)?,
+            Target::Index(source, index) => {
+                let container = match self.read_target(source, frame)? {
+                    Value::Package(mut elements) => {
+                        *elements.get_mut(*index).ok_or(anyhow!("Index {index} is out of bounds"))? = value;
+                        Value::Package(elements)
+                    }
+                    Value::Buffer(mut bytes) => {
+                        *bytes.get_mut(*index).ok_or(anyhow!("Index {index} is out of bounds"))? = value.as_integer()? as u8;
+                        Value::Buffer(bytes)
+                    }
+                    value => return Err(anyhow!("Cannot index a {}", value.type_name()))
+                };
+
+                match &**source {
+                    Target::Name(path) => {
+                        if let Some(Object::Name(slot)) = self.namespace.get_mut(path) {
+                            *slot = container;
+                        } else {
+                            self.store_name(path, container, frame)?;
+                        }
+                    }
+                    source => self.store(source, container, frame)?
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_name(&mut self, path: &str, frame: &mut Frame) -> Result<Value> {
+        match self.namespace.get(path).cloned().ok_or(anyhow!("{path} is not defined"))? {
+            Object::Name(value)        => Ok(value),
+            Object::Field(field)       => self.read_field(&field),
+            Object::BufferField(field) => {
+                let bytes = self.buffer(&field.source, frame)?;
+                let mut out = vec![0; field.bit_length.div_ceil(8) as usize];
+
+                for bit in 0..field.bit_length {
+                    set_bit(&mut out, bit, get_bit(&bytes, field.bit_offset + bit));
+                }
+
+                Ok(from_bits(out, field.bit_length))
+            }
+            Object::Method(_) => self.call(path.into(), Vec::new()),
+            _                 => Ok(Value::Reference(path.into()))
+        }
+    }
+
+    fn store_name(&mut self, path: &str, value: Value, frame: &mut Frame) -> Result<()> {
+        match self.namespace.get_mut(path) {
+            // Integers stay integers, anything else takes the new value as it is
+            Some(Object::Name(slot)) => {
+                *slot = match slot {
+                    Value::Integer(_) => Value::Integer(value.as_integer()? & self.ones),
+                    _                 => value
+                };
+            }
+            Some(Object::Field(field)) => {
+                let field = field.clone();
+                self.write_field(&field, &value.as_buffer()?)?;
+            }
+            Some(Object::BufferField(field)) => {
+                let field = field.clone();
+                let bits = value.as_buffer()?;
+                let mut bytes = self.buffer(&field.source, frame)?;
+                if (field.bit_offset + field.bit_length).div_ceil(8) as usize > bytes.len() {
+                    return Err(anyhow!("Buffer field {path} is out of bounds"));
+                }
+
+                for bit in 0..field.bit_length {
+                    set_bit(&mut bytes, field.bit_offset + bit, get_bit(&bits, bit));
+                }
+
+                match field.source {
+                    Source::Name(source) => self.store_name(&source, Value::Buffer(bytes), frame)?,
+                    Source::Local(i)     => frame.locals[i] = Value::Buffer(bytes),
+                    Source::Arg(i)       => self.store(&Target::Arg(i), Value::Buffer(bytes), frame)?
+                }
+            }
+            Some(object) => return Err(anyhow!("Cannot store to {path}, a {}", object.type_name())),
+            None         => return Err(anyhow!("{path} is not defined"))
+        }
+
+        Ok(())
+    }
+
+    fn buffer(&mut self, source: &Source, frame: &mut Frame) -> Result<Vec<u8>> {
+        let value = match source {
+            Source::Name(path) => self.read_name(path, frame)?,
+            Source::Local(i)   => frame.locals[*i].clone(),
+            Source::Arg(i)     => self.read_target(&Target::Arg(*i), frame)?
+        };
+
+        match value {
+            Value::Buffer(bytes) => Ok(bytes),
+            value                => Err(anyhow!("Buffer field source is a {}", value.type_name()))
+        }
+    }
+
+    fn read_field(&mut self, field: &Field) -> Result<Value> {
+        let width = field.access as u64 * 8;
+        let mut out = vec![0; field.bit_length.div_ceil(8) as usize];
+        let mut done = 0;
+
+        while done < field.bit_length {
+            let bit = field.bit_offset + done;
+            let unit = bit / width * width;
+            let shift = bit - unit;
+            let count = (width - shift).min(field.bit_length - done);
+
+            let raw = self.read_unit(field, unit / 8)? >> shift;
+            for i in 0..count {
+                set_bit(&mut out, done + i, raw >> i & 1 != 0);
+            }
+
+            done += count;
+        }
+
+        Ok(from_bits(out, field.bit_length))
+    }
+
+    fn write_field(&mut self, field: &Field, value: &[u8]) -> Result<()> {
+        let width = field.access as u64 * 8;
+        let mut done = 0;
+
+        while done < field.bit_length {
+            let bit = field.bit_offset + done;
+            let unit = bit / width * width;
+            let shift = bit - unit;
+            let count = (width - shift).min(field.bit_length - done);
+            let bits = mask(count) << shift;
+
+            let base = if count == width {
+                0
+            } else {
+                match field.update {
+                    Update::Preserve     => self.read_unit(field, unit / 8)?,
+                    Update::WriteAsOnes  => mask(width),
+                    Update::WriteAsZeros => 0
+                }
+            };
+
+            let mut raw = base & !bits;
+            for i in 0..count {
+                if get_bit(value, done + i) { raw |= 1 << (shift + i); }
+            }
+
+            self.write_unit(field, unit / 8, raw)?;
+            done += count;
+        }
+
+        Ok(())
+    }
+
+    fn read_unit(&mut self, field: &Field, offset: u64) -> Result<u64> {
+        match &field.kind {
+            FieldKind::Region(region) => {
+                let (space, address) = self.locate(region, offset)?;
+                self.handler.read(space, address, field.access * 8)
+            }
+            FieldKind::Index { index, data } => {
+                self.write_named_field(index, offset)?;
+                self.read_named_field(data)
+            }
+        }
+    }
+
+    fn write_unit(&mut self, field: &Field, offset: u64, value: u64) -> Result<()> {
+        match &field.kind {
+            FieldKind::Region(region) => {
+                let (space, address) = self.locate(region, offset)?;
+                self.handler.write(space, address, field.access * 8, value)
+            }
+            FieldKind::Index { index, data } => {
+                self.write_named_field(index, offset)?;
+                self.write_named_field(data, value)
+            }
+        }
+    }
+
+    fn read_named_field(&mut self, path: &str) -> Result<u64> {
+        match self.namespace.get(path).cloned() {
+            Some(Object::Field(field)) => self.read_field(&field)?.as_integer(),
+            _                          => Err(anyhow!("{path} is not a field"))
+        }
+    }
+
+    fn write_named_field(&mut self, path: &str, value: u64) -> Result<()> {
+        match self.namespace.get(path).cloned() {
+            Some(Object::Field(field)) => self.write_field(&field, &value.to_le_bytes()),
+            _                          => Err(anyhow!("{path} is not a field"))
+        }
+    }
+
+    fn locate(&mut self, path: &str, offset: u64) -> Result<(Space, u64)> {
+        let Some(Object::Region(region)) = self.namespace.get(path).cloned() else {
+            return Err(anyhow!("{path} is not an operation region"));
+        };
+
+        let space = match region.space {
+            0 => Space::Memory,
+            1 => Space::Io,
+            2 => self.pci_space(path)?,
+            n => Space::Other(n)
+        };
+
+        Ok((space, region.offset + offset))
+    }
+
+    // PCI_Config regions belong to the device they are declared in, the bus comes from the host bridge
+    fn pci_space(&mut self, region: &str) -> Result<Space> {
+        let device = namespace::parent(region).unwrap_or(namespace::ROOT);
+        let adr = self.optional(&namespace::join(device, b"_ADR"))?.unwrap_or(0);
+
+        let mut bus = 0;
+        let mut scope = Some(device);
+        while let Some(current) = scope {
+            if let Some(bbn) = self.optional(&namespace::join(current, b"_BBN"))? {
+                bus = bbn;
+                break;
+            }
+            scope = namespace::parent(current);
+        }
+
+        Ok(Space::PciConfig { bus: bus as u8, device: (adr >> 16) as u8, function: adr as u8 })
+    }
+
+    fn optional(&mut self, path: &str) -> Result<Option<u64>> {
+        if !self.namespace.contains(path) { return Ok(None); }
+        Ok(Some(self.evaluate(path, Vec::new())?.as_integer()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::collections::BTreeMap;
+
+    #[derive(Default)]
+    struct Mock {
+        bytes: BTreeMap<u64, u8>
+    }
+
+    impl Handler for Mock {
+        fn read(&mut self, _: Space, address: u64, bits: u8) -> Result<u64> {
+            Ok((0..bits as u64 / 8).rev().fold(0, |value, i| value << 8 | *self.bytes.get(&(address + i)).unwrap_or(&0) as u64))
+        }
+
+        fn write(&mut self, _: Space, address: u64, bits: u8, value: u64) -> Result<()> {
+            for i in 0..bits as u64 / 8 {
+                self.bytes.insert(address + i, (value >> (8 * i)) as u8);
+            }
+            Ok(())
+        }
+
+        fn sleep(&mut self, _: Duration) {}
+    }
+
+    fn table(body: &[u8]) -> &'static [u8] {
+        let mut table = vec![0; HEADER_LEN];
+        table[..4].copy_from_slice(b"DSDT");
+        table[8] = 2;
+        table.extend_from_slice(body);
+        table.leak()
+    }
+
+    fn load(body: &[u8]) -> Aml<Mock> {
+        let mut aml = Aml::new(Mock::default());
+        aml.load(table(body)).unwrap();
+        aml
+    }
+
+    #[test_case]
+    fn names_evaluate_to_their_value() {
+        // Name (_S5, Package (0x04) { 0x05, 0x05, Zero, Zero })
+        let mut aml = load(&[0x08, b'_', b'S', b'5', b'_', 0x12, 0x08, 0x04, 0x0a, 0x05, 0x0a, 0x05, 0x00, 0x00]);
+        let s5 = Value::Package(vec![Value::Integer(5), Value::Integer(5), Value::Integer(0), Value::Integer(0)]);
+        assert_eq!(aml.evaluate("\\_S5", Vec::new()).unwrap(), s5);
+    }
+
+    #[test_case]
+    fn methods_loop_over_their_arguments() {
+        // Method (TST, 2) { Local0 = Zero; While (Local0 < Arg1) { Local0++; Arg0 += 2 } Return (Arg0) }
+        let mut aml = load(&[
+            0x14, 0x17, b'T', b'S', b'T', b'_', 0x02,
+            0x70, 0x00, 0x60,
+            0xa2, 0x0b, 0x95, 0x60, 0x69, 0x75, 0x60, 0x72, 0x68, 0x0a, 0x02, 0x68,
+            0xa4, 0x68
+        ]);
+
+        assert_eq!(aml.evaluate("\\TST", vec![Value::Integer(10), Value::Integer(3)]).unwrap(), Value::Integer(16));
+    }
+
+    #[test_case]
+    fn else_runs_when_if_does_not() {
+        // Method (SEL, 1) { If (Arg0 == One) { Return (10) } Else { Return (20) } }
+        let mut aml = load(&[
+            0x14, 0x13, b'S', b'E', b'L', b'_', 0x01,
+            0xa0, 0x07, 0x93, 0x68, 0x01, 0xa4, 0x0a, 0x0a,
+            0xa1, 0x04, 0xa4, 0x0a, 0x14
+        ]);
+
+        assert_eq!(aml.evaluate("\\SEL", vec![Value::Integer(1)]).unwrap(), Value::Integer(10));
+        assert_eq!(aml.evaluate("\\SEL", vec![Value::Integer(2)]).unwrap(), Value::Integer(20));
+    }
+
+    #[test_case]
+    fn fields_preserve_their_neighbours() {
+        // OperationRegion (GPIO, SystemIO, 0x80, 2)
+        // Field (GPIO, ByteAcc, NoLock, Preserve) { Offset (0), , 4, FLG, 4, DAT, 8 }
+        // Method (SET, 1) { FLG = Arg0 }
+        let mut aml = load(&[
+            0x5b, 0x80, b'G', b'P', b'I', b'O', 0x01, 0x0a, 0x80, 0x0a, 0x02,
+            0x5b, 0x81, 0x12, b'G', b'P', b'I', b'O', 0x01, 0x00, 0x04, b'F', b'L', b'G', b'_', 0x04, b'D', b'A', b'T', b'_', 0x08,
+            0x14, 0x0c, b'S', b'E', b'T', b'_', 0x01, 0x70, 0x68, b'F', b'L', b'G', b'_'
+        ]);
+
+        aml.handler.bytes.insert(0x80, 0xa5);
+        aml.handler.bytes.insert(0x81, 0x42);
+
+        aml.evaluate("\\SET", vec![Value::Integer(3)]).unwrap();
+        assert_eq!(aml.handler.bytes[&0x80], 0x35);
+        assert_eq!(aml.evaluate("\\FLG", Vec::new()).unwrap(), Value::Integer(3));
+        assert_eq!(aml.evaluate("\\DAT", Vec::new()).unwrap(), Value::Integer(0x42));
+    }
+
+    #[test_case]
+    fn method_names_go_away_on_return() {
+        // Method (TMP) { Name (XXXX, 5) Return (XXXX) }
+        let mut aml = load(&[
+            0x14, 0x12, b'T', b'M', b'P', b'_', 0x00,
+            0x08, b'X', b'X', b'X', b'X', 0x0a, 0x05,
+            0xa4, b'X', b'X', b'X', b'X'
+        ]);
+
+        assert_eq!(aml.evaluate("\\TMP", Vec::new()).unwrap(), Value::Integer(5));
+        assert!(!aml.namespace().contains("\\TMP_.XXXX"));
+    }
+
+    #[test_case]
+    fn load_skips_unsupported_objects() {
+        // A BankField the interpreter cannot handle, then Name (AFTR, One)
+        let mut aml = load(&[0x5b, 0x87, 0x03, 0x00, 0x00, 0x08, b'A', b'F', b'T', b'R', 0x01]);
+        assert_eq!(aml.evaluate("\\AFTR", Vec::new()).unwrap(), Value::Integer(1));
+    }
+
+    #[test_case]
+    fn short_packages_fail_instead_of_panicking() {
+        // Method (SHRT) with a package length that ends inside its own name
+        let mut aml = Aml::new(Mock::default());
+        assert!(aml.load(table(&[0x14, 0x02, b'S', b'H', b'R', b'T', 0x00])).is_err());
+    }
+
+    #[test_case]
+    fn huge_buffers_and_packages_are_refused() {
+        // Method (BIG) { Return (Buffer (0xffffffff) {}) }
+        let mut aml = load(&[0x14, 0x0e, b'B', b'I', b'G', b'_', 0x00, 0xa4, 0x11, 0x06, 0x0c, 0xff, 0xff, 0xff, 0xff]);
+        assert!(aml.evaluate("\\BIG", Vec::new()).is_err());
+
+        // Method (BIG) { Return (VarPackage (0xffffffff) {}) }
+        let mut aml = load(&[0x14, 0x0e, b'B', b'I', b'G', b'_', 0x00, 0xa4, 0x13, 0x06, 0x0c, 0xff, 0xff, 0xff, 0xff]);
+        assert!(aml.evaluate("\\BIG", Vec::new()).is_err());
+    }
+
+    #[test_case]
+    fn osi_claims_windows() {
+        // Method (OSYS) { If (_OSI ("Windows 2015")) { Return (One) } Return (Zero) }
+        let mut body = vec![0x14, 0x1e, b'O', b'S', b'Y', b'S', 0x00, 0xa0, 0x15, b'_', b'O', b'S', b'I', 0x0d];
+        body.extend_from_slice(b"Windows 2015\0");
+        body.extend_from_slice(&[0xa4, 0x01, 0xa4, 0x00]);
+
+        let mut aml = load(&body);
+        assert_eq!(aml.evaluate("\\OSYS", Vec::new()).unwrap(), Value::Integer(1));
+    }
+}
@@ -0,0 +1,246 @@
+extern crate alloc;
+
+use core::ops::Bound;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use anyhow::{anyhow, Result};
+use log::warn;
+
+use crate::acpi::aml::value::{Method, Object, Value};
+
+pub const ROOT: &str = "\\";
+
+// A parsed NameString, prefixes and all, before it is tied to a scope
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Name {
+    pub root:     bool,
+    pub parents:  usize,
+    pub segments: Vec<[u8; 4]>
+}
+
+impl Name {
+    // Accepts the ASL spelling, short segments are padded with underscores like the compiler does
+    pub fn parse(s: &str) -> Result<Name> {
+        let (root, rest) = match s.strip_prefix('\\') {
+            Some(rest) => (true, rest),
+            None       => (false, s)
+        };
+
+        let parents = rest.bytes().take_while(|&b| b == b'^').count();
+        let rest = &rest[parents..];
+
+        let segments = rest
+            .split('.')
+            .filter(|seg| !seg.is_empty())
+            .map(|seg| {
+                if seg.len() > 4 || !seg.bytes().all(|b| b.is_ascii_uppercase() || b.is_ascii_digit() || b == b'_') {
+                    return Err(anyhow!("Invalid name segment {seg}"));
+                }
+
+                let mut segment = [b'_'; 4];
+                segment[..seg.len()].copy_from_slice(seg.as_bytes());
+                Ok(segment)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Name { root, parents, segments })
+    }
+
+    // Single segments without prefixes are looked up in every enclosing scope
+    pub fn searchable(&self) -> bool {
+        !self.root && self.parents == 0 && self.segments.len() == 1
+    }
+}
+
+pub fn join(scope: &str, segment: &[u8; 4]) -> String {
+    let segment = core::str::from_utf8(segment).unwrap_or("____");
+
+    if scope == ROOT {
+        alloc::format!("{ROOT}{segment}")
+    } else {
+        alloc::format!("{scope}.{segment}")
+    }
+}
+
+pub fn parent(path: &str) -> Option<&str> {
+    if path == ROOT { return None; }
+
+    match path.rfind('.') {
+        Some(i) => Some(&path[..i]),
+        None    => Some(ROOT)
+    }
+}
+
+pub fn depth(path: &str) -> usize {
+    if path == ROOT { 0 } else { path.matches('.').count() + 1 }
+}
+
+pub struct Namespace {
+    objects: BTreeMap<String, Object>
+}
+
+impl Namespace {
+    // The predefined root scopes and objects every definition block may assume
+    pub fn new() -> Namespace {
+        let mut objects = BTreeMap::new();
+
+        objects.insert(ROOT.into(), Object::Scope);
+        for scope in ["\\_GPE", "\\_PR_", "\\_SB_", "\\_SI_", "\\_TZ_"] {
+            objects.insert(scope.into(), Object::Scope);
+        }
+
+        objects.insert("\\_GL_".into(), Object::Mutex);
+        objects.insert("\\_OS_".into(), Object::Name(Value::String("Microsoft Windows NT".into())));
+        objects.insert("\\_REV".into(), Object::Name(Value::Integer(2)));
+        // Native, see Interpreter::call
+        objects.insert("\\_OSI".into(), Object::Method(Method { args: 1, serialized: false, body: &[] }));
+
+        Namespace { objects }
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.objects.contains_key(path)
+    }
+
+    pub fn get(&self, path: &str) -> Option<&Object> {
+        match self.objects.get(path)? {
+            Object::Alias(target) => self.objects.get(target),
+            object                => Some(object)
+        }
+    }
+
+    pub fn get_mut(&mut self, path: &str) -> Option<&mut Object> {
+        let path = match self.objects.get(path)? {
+            Object::Alias(target) => target.clone(),
+            _                     => String::from(path)
+        };
+
+        self.objects.get_mut(&path)
+    }
+
+    // Firmware redefining a name is common enough that the first definition just wins
+    pub fn insert(&mut self, path: String, object: Object) -> Result<()> {
+        let parent = parent(&path).ok_or(anyhow!("Cannot redefine the root"))?;
+        if !self.objects.contains_key(parent) {
+            return Err(anyhow!("Scope {parent} does not exist for {path}"));
+        }
+
+        match self.objects.get(&path) {
+            Some(existing) if existing.is_scope() && object.is_scope() => {}
+            Some(_) => warn!("{path} is already defined"),
+            None    => { self.objects.insert(path, object); }
+        }
+
+        Ok(())
+    }
+
+    pub fn remove(&mut self, path: &str) {
+        self.objects.remove(path);
+    }
+
+    pub fn resolve(&self, scope: &str, name: &Name) -> Result<String> {
+        if name.searchable() {
+            let mut scope = Some(scope);
+
+            while let Some(current) = scope {
+                let path = join(current, &name.segments[0]);
+                if self.objects.contains_key(&path) { return Ok(path); }
+                scope = parent(current);
+            }
+        }
+
+        self.path(scope, name)
+    }
+
+    // Where a name lands without the search rules, which is what new objects use
+    pub fn path(&self, scope: &str, name: &Name) -> Result<String> {
+        let mut path = String::from(if name.root { ROOT } else { scope });
+
+        for _ in 0..name.parents {
+            path = parent(&path).ok_or(anyhow!("Name goes above the root"))?.into();
+        }
+
+        for segment in &name.segments {
+            path = join(&path, segment);
+        }
+
+        Ok(path)
+    }
+
+    // The object at path followed by everything below it, in order
+    pub fn subtree<'a>(&'a self, path: &'a str) -> impl Iterator<Item = (&'a String, &'a Object)> + 'a {
+        self.objects
+            .range::<str, _>((Bound::Included(path), Bound::Unbounded))
+            .take_while(move |(key, _)| {
+                *key == path || path == ROOT || key.strip_prefix(path).is_some_and(|rest| rest.starts_with('.'))
+            })
+    }
+}
+
+impl Default for Namespace {
+    fn default() -> Namespace {
+        Namespace::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(s: &str) -> Name {
+        Name::parse(s).unwrap()
+    }
+
+    #[test_case]
+    fn parse_pads_and_splits() {
+        assert_eq!(name("\\_SB.PCI0").segments, [*b"_SB_", *b"PCI0"]);
+        assert!(name("\\_SB").root);
+        assert_eq!(name("^^FOO").parents, 2);
+        assert!(Name::parse("\\toolong").is_err());
+    }
+
+    #[test_case]
+    fn paths_join_and_split() {
+        assert_eq!(join(ROOT, b"_SB_"), "\\_SB_");
+        assert_eq!(join("\\_SB_", b"PCI0"), "\\_SB_.PCI0");
+        assert_eq!(parent("\\_SB_.PCI0"), Some("\\_SB_"));
+        assert_eq!(parent("\\_SB_"), Some(ROOT));
+        assert_eq!(parent(ROOT), None);
+        assert_eq!(depth("\\_SB_.PCI0"), 2);
+    }
+
+    #[test_case]
+    fn search_walks_up_enclosing_scopes() {
+        let mut ns = Namespace::new();
+        ns.insert("\\_SB_.PCI0".into(), Object::Device).unwrap();
+        ns.insert("\\_SB_.PCI0.LPCB".into(), Object::Device).unwrap();
+        ns.insert("\\_SB_.FOO_".into(), Object::Name(Value::Integer(1))).unwrap();
+
+        assert_eq!(ns.resolve("\\_SB_.PCI0.LPCB", &name("FOO")).unwrap(), "\\_SB_.FOO_");
+        assert_eq!(ns.resolve("\\_SB_.PCI0.LPCB", &name("BAR")).unwrap(), "\\_SB_.PCI0.LPCB.BAR_");
+        assert_eq!(ns.resolve("\\_SB_.PCI0.LPCB", &name("^^FOO")).unwrap(), "\\_SB_.FOO_");
+        assert_eq!(ns.resolve("\\_SB_.PCI0", &name("\\_REV")).unwrap(), "\\_REV");
+    }
+
+    #[test_case]
+    fn insert_needs_a_parent_and_keeps_the_first_definition() {
+        let mut ns = Namespace::new();
+        assert!(ns.insert("\\_SB_.PCI0.LPCB".into(), Object::Device).is_err());
+
+        ns.insert("\\VAL_".into(), Object::Name(Value::Integer(1))).unwrap();
+        ns.insert("\\VAL_".into(), Object::Name(Value::Integer(2))).unwrap();
+        assert!(matches!(ns.get("\\VAL_"), Some(Object::Name(Value::Integer(1)))));
+    }
+
+    #[test_case]
+    fn subtree_stays_below_the_path() {
+        let mut ns = Namespace::new();
+        ns.insert("\\_SB_.PCI0".into(), Object::Device).unwrap();
+        ns.insert("\\_SB_.PCI0.LPCB".into(), Object::Device).unwrap();
+        ns.insert("\\_SB_.PCI1".into(), Object::Device).unwrap();
+
+        let paths: Vec<&str> = ns.subtree("\\_SB_.PCI0").map(|(path, _)| path.as_str()).collect();
+        assert_eq!(paths, ["\\_SB_.PCI0", "\\_SB_.PCI0.LPCB"]);
+    }
+}
@@ -0,0 +1,215 @@
+extern crate alloc;
+
+use core::fmt::{self, Display, Formatter};
+use alloc::string::String;
+use alloc::vec::Vec;
+use anyhow::{anyhow, Result};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Integer(u64),
+    String(String),
+    Buffer(Vec<u8>),
+    Package(Vec<Value>),
+    // An absolute namespace path, produced by RefOf and by naming non-data objects
+    Reference(String)
+}
+
+impl Value {
+    pub fn as_integer(&self) -> Result<u64> {
+        match self {
+            Value::Integer(value) => Ok(*value),
+            Value::Buffer(bytes)  => {
+                let mut value = [0; 8];
+                let len = bytes.len().min(8);
+                value[..len].copy_from_slice(&bytes[..len]);
+                Ok(u64::from_le_bytes(value))
+            }
+            // Implicit string conversion is always hexadecimal
+            Value::String(s) => {
+                let digits = s.trim_start_matches("0x").trim_start_matches("0X");
+                let len = digits.find(|c: char| !c.is_ascii_hexdigit()).unwrap_or(digits.len());
+                Ok(u64::from_str_radix(&digits[..len.min(16)], 16).unwrap_or(0))
+            }
+            _ => Err(anyhow!("{} is not an integer", self.type_name()))
+        }
+    }
+
+    pub fn as_buffer(&self) -> Result<Vec<u8>> {
+        match self {
+            Value::Integer(value) => Ok(value.to_le_bytes().into()),
+            Value::Buffer(bytes)  => Ok(bytes.clone()),
+            Value::String(s)      => {
+                let mut bytes: Vec<u8> = s.bytes().collect();
+                bytes.push(0);
+                Ok(bytes)
+            }
+            _ => Err(anyhow!("{} is not a buffer", self.type_name()))
+        }
+    }
+
+    pub fn as_string(&self) -> Result<String> {
+        match self {
+            Value::Integer(value) => Ok(alloc::format!("{value:016X}")),
+            Value::String(s)      => Ok(s.clone()),
+            Value::Buffer(bytes)  => Ok(bytes.iter().map(|b| alloc::format!("{b:02X}")).collect::<Vec<_>>().join(" ")),
+            _                     => Err(anyhow!("{} is not a string", self.type_name()))
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Integer(_)   => "Integer",
+            Value::String(_)    => "String",
+            Value::Buffer(_)    => "Buffer",
+            Value::Package(_)   => "Package",
+            Value::Reference(_) => "Reference"
+        }
+    }
+
+    // The ObjectType operator's numbering
+    pub fn type_code(&self) -> u64 {
+        match self {
+            Value::Integer(_)   => 1,
+            Value::String(_)    => 2,
+            Value::Buffer(_)    => 3,
+            Value::Package(_)   => 4,
+            Value::Reference(_) => 0
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Value::Integer(value) => write!(f, "0x{value:x}"),
+            Value::String(s)      => write!(f, "{s:?}"),
+            Value::Buffer(bytes)  => {
+                write!(f, "Buffer ({}) {{", bytes.len())?;
+                for (i, byte) in bytes.iter().enumerate() {
+                    write!(f, "{}0x{byte:02x}", if i == 0 { " " } else { ", " })?;
+                }
+                write!(f, " }}")
+            }
+            Value::Package(elements) => {
+                write!(f, "Package ({}) {{", elements.len())?;
+                for (i, element) in elements.iter().enumerate() {
+                    write!(f, "{}{element}", if i == 0 { " " } else { ", " })?;
+                }
+                write!(f, " }}")
+            }
+            Value::Reference(path) => write!(f, "RefOf ({path})")
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Update {
+    Preserve,
+    WriteAsOnes,
+    WriteAsZeros
+}
+
+#[derive(Clone, Debug)]
+pub enum FieldKind {
+    Region(String),
+    // Each access selects the offset through the index field, then goes through the data field
+    Index { index: String, data: String }
+}
+
+#[derive(Clone, Debug)]
+pub struct Field {
+    pub kind:       FieldKind,
+    pub bit_offset: u64,
+    pub bit_length: u64,
+    // Access width in bytes
+    pub access:     u8,
+    pub update:     Update
+}
+
+#[derive(Clone, Debug)]
+pub enum Source {
+    Name(String),
+    Local(usize),
+    Arg(usize)
+}
+
+#[derive(Clone, Debug)]
+pub struct BufferField {
+    pub source:     Source,
+    pub bit_offset: u64,
+    pub bit_length: u64
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Region {
+    pub space:  u8,
+    pub offset: u64,
+    pub length: u64
+}
+
+#[derive(Clone, Debug)]
+pub struct Method {
+    pub args:       u8,
+    pub serialized: bool,
+    pub body:       &'static [u8]
+}
+
+#[derive(Clone, Debug)]
+pub enum Object {
+    Scope,
+    Device,
+    Processor,
+    PowerResource,
+    ThermalZone,
+    Name(Value),
+    Method(Method),
+    Region(Region),
+    Field(Field),
+    BufferField(BufferField),
+    Mutex,
+    Event,
+    Alias(String)
+}
+
+impl Object {
+    // Scope-like objects may be opened again by later definition blocks
+    pub fn is_scope(&self) -> bool {
+        matches!(self, Object::Scope | Object::Device | Object::Processor | Object::PowerResource | Object::ThermalZone)
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Object::Scope          => "Scope",
+            Object::Device         => "Device",
+            Object::Processor      => "Processor",
+            Object::PowerResource  => "PowerResource",
+            Object::ThermalZone    => "ThermalZone",
+            Object::Name(value)    => value.type_name(),
+            Object::Method(_)      => "Method",
+            Object::Region(_)      => "OperationRegion",
+            Object::Field(_)       => "FieldUnit",
+            Object::BufferField(_) => "BufferField",
+            Object::Mutex          => "Mutex",
+            Object::Event          => "Event",
+            Object::Alias(_)       => "Alias"
+        }
+    }
+
+    pub fn type_code(&self) -> u64 {
+        match self {
+            Object::Name(value)    => value.type_code(),
+            Object::Field(_)       => 5,
+            Object::Device         => 6,
+            Object::Event          => 7,
+            Object::Method(_)      => 8,
+            Object::Mutex          => 9,
+            Object::Region(_)      => 10,
+            Object::PowerResource  => 11,
+            Object::Processor      => 12,
+            Object::ThermalZone    => 13,
+            Object::BufferField(_) => 14,
+            _                      => 0
+        }
+    }
+}
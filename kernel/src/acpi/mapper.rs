@@ -1,40 +1,289 @@
-use core::cell::RefCell;
-use core::ptr::NonNull;
+extern crate alloc;
+
+use core::ptr::{self, NonNull};
 use acpi::{AcpiHandler, PhysicalMapping};
+use alloc::vec::Vec;
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use x86_64::addr;
+use x86_64::structures::paging::{PageSize, PageTableFlags, Size2MiB, Size4KiB};
 
-use crate::memory;
-use crate::memory::MemoryPool;
+use crate::acpi::pci::PCI_START;
+use crate::bootinfo::{FirmwareKind, FirmwareRegion};
+use crate::memory::{self, MemoryPool, KASLR_END};
 
-#[derive(Clone)]
-pub struct AcpiMapper {
-    top: RefCell<u64>
+// Small mappings use 4KiB pages in the first gigabyte of the window and large ones 2MiB pages in the
+// rest, so a page directory entry never has to switch between the two
+const SMALL_START: u64 = KASLR_END;
+const LARGE_START: u64 = KASLR_END + 0x40000000;
+const LARGE_END:   u64 = PCI_START;
+
+struct Window {
+    next:  u64,
+    end:   u64,
+    // Released ranges as (start, size), reused before the window grows
+    holes: Vec<(u64, u64)>
+}
+
+impl Window {
+    const fn new(start: u64, end: u64) -> Window {
+        Window { next: start, end, holes: Vec::new() }
+    }
+
+    fn take(&mut self, size: u64) -> Option<u64> {
+        if let Some(i) = self.holes.iter().position(|&(_, len)| len >= size) {
+            let (start, len) = self.holes[i];
+
+            if len == size {
+                self.holes.swap_remove(i);
+            } else {
+                self.holes[i] = (start + size, len - size);
+            }
+
+            return Some(start);
+        }
+
+        if self.next + size > self.end { return None; }
+
+        let start = self.next;
+        self.next += size;
+        Some(start)
+    }
+
+    // Neighbouring holes merge, so the space they make up together can serve a larger request
+    fn give(&mut self, start: u64, size: u64) {
+        let (mut start, mut end) = (start, start + size);
+
+        while let Some(i) = self.holes.iter().position(|&(s, len)| s + len == start || s == end) {
+            let (s, len) = self.holes.swap_remove(i);
+            start = start.min(s);
+            end = end.max(s + len);
+        }
+
+        if end == self.next {
+            self.next = start;
+        } else {
+            self.holes.push((start, end - start));
+        }
+    }
+}
+
+struct Mapping {
+    phys:  u64,
+    size:  u64,
+    virt:  u64,
+    refs:  usize,
+    large: bool
+}
+
+struct Mappings {
+    small: Window,
+    large: Window,
+    live:  Vec<Mapping>
 }
 
+impl Mappings {
+    // A live mapping covering the whole range is shared instead of mapping the pages again
+    fn acquire(&mut self, start: u64, end: u64) -> Option<u64> {
+        let mapping = self.live.iter_mut().find(|m| m.phys <= start && end <= m.phys + m.size)?;
+        mapping.refs += 1;
+
+        Some(mapping.virt + (start - mapping.phys))
+    }
+
+    // Hands the mapping back once its last user is gone
+    fn release(&mut self, virt: u64) -> Option<Mapping> {
+        let i = self.live.iter().position(|m| m.virt <= virt && virt < m.virt + m.size)?;
+
+        self.live[i].refs -= 1;
+        if self.live[i].refs > 0 { return None; }
+
+        Some(self.live.swap_remove(i))
+    }
+
+    // Whole 2MiB pages in the middle of a large range, 4KiB pages on its unaligned edges, so nothing outside
+    // the range gets mapped with flags that were worked out for the range alone
+    unsafe fn map(&mut self, start: u64, end: u64) -> Result<u64> {
+        let flags = flags(start, end);
+        let (first, last) = (addr::align_up(start, Size2MiB::SIZE), addr::align_down(end, Size2MiB::SIZE));
+
+        let virt = if first < last {
+            let pool = MemoryPool::align(start, end);
+            let base = self.large.take(pool.size()).ok_or(anyhow!("ACPI mapping window is full"))?;
+            let virt = |phys| base + (phys - pool.start);
+
+            memory::map_pages(start, virt(start), first - start, flags)?;
+            memory::map_flags(MemoryPool { start: first, end: last }, virt(first), flags)?;
+            memory::map_pages(last, virt(last), end - last, flags)?;
+
+            virt(start)
+        } else {
+            let virt = self.small.take(end - start).ok_or(anyhow!("ACPI mapping window is full"))?;
+            memory::map_pages(start, virt, end - start, flags)?;
+
+            virt
+        };
+
+        self.live.push(Mapping { phys: start, size: end - start, virt, refs: 1, large: first < last });
+
+        Ok(virt)
+    }
+
+    unsafe fn unmap(&mut self, mapping: Mapping) -> Result<()> {
+        let (start, end) = (mapping.virt, mapping.virt + mapping.size);
+
+        if mapping.large {
+            let (first, last) = (addr::align_up(start, Size2MiB::SIZE), addr::align_down(end, Size2MiB::SIZE));
+
+            memory::unmap_pages(start, first - start)?;
+            memory::unmap(first, ((last - first) / Size2MiB::SIZE) as usize);
+            memory::unmap_pages(last, end - last)?;
+
+            if start < first { memory::release_table(start); }
+            if last < end { memory::release_table(last); }
+
+            let pool = MemoryPool::align(start, end);
+            self.large.give(pool.start, pool.size());
+        } else {
+            memory::unmap_pages(start, mapping.size)?;
+            self.small.give(start, mapping.size);
+        }
+
+        Ok(())
+    }
+}
+
+static mut MAPPINGS: Mappings = Mappings {
+    small: Window::new(SMALL_START, LARGE_START),
+    large: Window::new(LARGE_START, LARGE_END),
+    live:  Vec::new()
+};
+
+static mut FIRMWARE: &[FirmwareRegion] = &[];
+
+pub fn init(firmware: &'static [FirmwareRegion]) {
+    let total = |kind| firmware.iter().filter(|r| r.kind == kind).map(|r| r.end - r.start).sum::<u64>() / 1024;
+    info!("{} KiB of ACPI tables, {} KiB of ACPI NVS", total(FirmwareKind::AcpiReclaimable), total(FirmwareKind::AcpiNvs));
+
+    unsafe { FIRMWARE = firmware; }
+}
+
+// Tables and NVS are ordinary memory, anything else that comes through here is a device register
+fn flags(start: u64, end: u64) -> PageTableFlags {
+    let flags = PageTableFlags::WRITABLE | memory::no_execute();
+
+    if unsafe { FIRMWARE }.iter().any(|region| region.contains(start, end)) {
+        flags
+    } else {
+        flags | PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH
+    }
+}
+
+#[derive(Clone)]
+pub struct AcpiMapper;
+
 impl AcpiMapper {
     pub fn new() -> AcpiMapper {
-        AcpiMapper { top: RefCell::new(0xfffffffe_00000000) }
+        AcpiMapper
+    }
+}
+
+impl Default for AcpiMapper {
+    fn default() -> AcpiMapper {
+        AcpiMapper::new()
     }
 }
 
 impl AcpiHandler for AcpiMapper {
     unsafe fn map_physical_region<T>(&self, physical_address: usize, size: usize) -> PhysicalMapping<Self, T> {
-        let pool = MemoryPool::align(physical_address as u64, physical_address as u64 + size as u64);
-        let offset = physical_address as u64 - pool.start;
-        let mapped_size = pool.size();
-
-        let virt = *self.top.borrow();
-        *self.top.borrow_mut() += mapped_size;
+        let phys = physical_address as u64;
+        let start = addr::align_down(phys, Size4KiB::SIZE);
+        let end = addr::align_up(phys + size.max(1) as u64, Size4KiB::SIZE);
+        let mappings = &mut *ptr::addr_of_mut!(MAPPINGS);
 
-        memory::map(pool, virt);
+        let virt = match mappings.acquire(start, end) {
+            Some(virt) => virt,
+            None       => mappings.map(start, end).unwrap_or_else(|e| panic!("Mapping ACPI region 0x{phys:x}: {e}"))
+        };
 
         PhysicalMapping::new(
             physical_address,
-            NonNull::new((virt + offset) as *mut T).expect("Impossible"),
-            mapped_size as usize,
-            mapped_size as usize,
-            self.clone()
+            NonNull::new((virt + (phys - start)) as *mut T).expect("Impossible"),
+            size,
+            (end - start) as usize,
+            AcpiMapper
         )
     }
 
-    fn unmap_physical_region<T>(_region: &PhysicalMapping<Self, T>) {}
+    fn unmap_physical_region<T>(region: &PhysicalMapping<Self, T>) {
+        let virt = region.virtual_start().as_ptr() as u64;
+
+        unsafe {
+            let mappings = &mut *ptr::addr_of_mut!(MAPPINGS);
+            let Some(mapping) = mappings.release(virt) else { return; };
+
+            if let Err(e) = mappings.unmap(mapping) {
+                warn!("Unmapping ACPI region at 0x{virt:x}: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mappings() -> Mappings {
+        Mappings { small: Window::new(0x1000, 0x10000), large: Window::new(0x10000, 0x20000), live: Vec::new() }
+    }
+
+    #[test_case]
+    fn window_reuses_released_space() {
+        let mut window = Window::new(0x1000, 0x5000);
+        let a = window.take(0x1000).unwrap();
+        let b = window.take(0x2000).unwrap();
+        assert_eq!((a, b), (0x1000, 0x2000));
+
+        window.give(a, 0x1000);
+        assert_eq!(window.take(0x1000), Some(0x1000));
+        assert_eq!(window.take(0x2000), None);
+
+        window.give(b, 0x2000);
+        assert_eq!(window.take(0x3000), Some(0x2000));
+    }
+
+    #[test_case]
+    fn window_merges_neighbouring_holes() {
+        let mut window = Window::new(0x1000, 0x5000);
+        let a = window.take(0x1000).unwrap();
+        let b = window.take(0x1000).unwrap();
+        let c = window.take(0x1000).unwrap();
+
+        window.give(b, 0x1000);
+        window.give(a, 0x1000);
+        assert_eq!(window.take(0x2000), Some(0x1000));
+
+        window.give(a, 0x2000);
+        window.give(c, 0x1000);
+        assert_eq!((window.next, window.holes.len()), (0x1000, 0));
+    }
+
+    #[test_case]
+    fn covered_ranges_share_a_mapping() {
+        let mut mappings = mappings();
+        mappings.live.push(Mapping { phys: 0x7000, size: 0x3000, virt: 0x1000, refs: 1, large: false });
+
+        assert_eq!(mappings.acquire(0x8000, 0x9000), Some(0x2000));
+        assert_eq!(mappings.acquire(0x9000, 0xb000), None);
+
+        assert!(mappings.release(0x2000).is_none());
+        assert_eq!(mappings.release(0x1000).map(|m| m.phys), Some(0x7000));
+        assert!(mappings.live.is_empty());
+    }
+
+    #[test_case]
+    fn unknown_addresses_are_not_released() {
+        let mut mappings = mappings();
+        assert!(mappings.release(0x1000).is_none());
+    }
 }
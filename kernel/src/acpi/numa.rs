@@ -0,0 +1,251 @@
+extern crate alloc;
+
+use alloc::vec::Vec;
+use anyhow::{anyhow, Result};
+use log::info;
+
+use crate::acpi::tables::ACPI;
+use crate::{cmdline, cpu};
+use crate::memory::frames;
+
+const SRAT_ENTRIES: usize = 48;
+const SLIT_ENTRIES: usize = 44;
+
+// What SLIT calls the distance from a node to itself, everything else is relative to it
+pub const LOCAL:  u8 = 10;
+pub const REMOTE: u8 = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cpu {
+    pub apic_id: u32,
+    pub node:    u32
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Memory {
+    pub start:       u64,
+    pub end:         u64,
+    pub node:        u32,
+    pub hotplug:     bool,
+    pub nonvolatile: bool
+}
+
+pub struct Topology {
+    pub cpus:   Vec<Cpu>,
+    pub memory: Vec<Memory>,
+    // Row-major matrix indexed by proximity domain, empty without a SLIT
+    localities: usize,
+    distances:  Vec<u8>
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+impl Topology {
+    pub fn parse(srat: &[u8], slit: Option<&[u8]>) -> Result<Topology> {
+        let mut cpus = Vec::new();
+        let mut memory = Vec::new();
+        let mut rest = srat.get(SRAT_ENTRIES..).ok_or(anyhow!("SRAT is truncated"))?;
+
+        while rest.len() >= 2 {
+            let (kind, len) = (rest[0], rest[1] as usize);
+            if len < 2 || len > rest.len() { return Err(anyhow!("SRAT entry of length {len} is malformed")); }
+            let entry = &rest[..len];
+
+            match kind {
+                // Enabled Processor Local APIC, the domain is split over two fields
+                0 if len >= 16 && u32_at(entry, 4) & 1 != 0 => {
+                    let node = entry[2] as u32 | (entry[9] as u32) << 8 | (entry[10] as u32) << 16 | (entry[11] as u32) << 24;
+                    cpus.push(Cpu { apic_id: entry[3] as u32, node });
+                }
+                // Enabled Memory
+                1 if len >= 40 && u32_at(entry, 28) & 1 != 0 => {
+                    let flags = u32_at(entry, 28);
+                    let start = u64_at(entry, 8);
+                    let end = start.checked_add(u64_at(entry, 16)).ok_or(anyhow!("SRAT memory range at 0x{start:x} overflows"))?;
+
+                    memory.push(Memory {
+                        start,
+                        end,
+                        node:        u32_at(entry, 2),
+                        hotplug:     flags & 2 != 0,
+                        nonvolatile: flags & 4 != 0
+                    });
+                }
+                // Enabled Processor Local x2APIC
+                2 if len >= 24 && u32_at(entry, 12) & 1 != 0 => {
+                    cpus.push(Cpu { apic_id: u32_at(entry, 8), node: u32_at(entry, 4) });
+                }
+                _ => {}
+            }
+
+            rest = &rest[len..];
+        }
+
+        let (localities, distances) = match slit {
+            Some(slit) => {
+                let count = slit.get(36..SLIT_ENTRIES).map(|bytes| u64_at(bytes, 0)).ok_or(anyhow!("SLIT is truncated"))?;
+                let count = usize::try_from(count).map_err(|_| anyhow!("SLIT has {count} localities"))?;
+                let end = count.checked_mul(count).and_then(|size| size.checked_add(SLIT_ENTRIES));
+                let matrix = end.and_then(|end| slit.get(SLIT_ENTRIES..end)).ok_or(anyhow!("SLIT is truncated"))?;
+                (count, matrix.into())
+            }
+            None => (0, Vec::new())
+        };
+
+        Ok(Topology { cpus, memory, localities, distances })
+    }
+
+    pub fn nodes(&self) -> Vec<u32> {
+        let mut nodes: Vec<u32> = self.cpus.iter().map(|cpu| cpu.node).chain(self.memory.iter().map(|m| m.node)).collect();
+        nodes.sort_unstable();
+        nodes.dedup();
+        nodes
+    }
+
+    pub fn node_of(&self, addr: u64) -> Option<u32> {
+        self.memory.iter().find(|m| m.start <= addr && addr < m.end).map(|m| m.node)
+    }
+
+    pub fn node_of_cpu(&self, apic_id: u32) -> Option<u32> {
+        self.cpus.iter().find(|cpu| cpu.apic_id == apic_id).map(|cpu| cpu.node)
+    }
+
+    // Without a SLIT every other node counts as one hop away
+    pub fn distance(&self, from: u32, to: u32) -> u8 {
+        let (from, to) = (from as usize, to as usize);
+
+        if from < self.localities && to < self.localities {
+            self.distances[from * self.localities + to]
+        } else if from == to {
+            LOCAL
+        } else {
+            REMOTE
+        }
+    }
+
+    // The node of the processor running this code
+    pub fn current_node(&self) -> u32 {
+        self.node_of_cpu(cpu::apic_id()).unwrap_or(0)
+    }
+}
+
+static mut TOPOLOGY: Option<Topology> = None;
+
+impl Topology {
+    pub fn init_global(acpi: &ACPI) -> Result<()> {
+        let srat = acpi.raw_table("SRAT").ok_or(anyhow!("No SRAT, memory is treated as a single node"))?;
+        let slit = acpi.raw_table("SLIT");
+        let mut topology = Topology::parse(srat.bytes(), slit.map(|slit| slit.bytes()))?;
+
+        // There is no SMP bring-up yet, so all no-smp does is hide the other processors from the topology
+        if cmdline::no_smp() {
+            let boot = cpu::apic_id();
+            let hidden = topology.cpus.iter().filter(|cpu| cpu.apic_id != boot).count();
+            topology.cpus.retain(|cpu| cpu.apic_id == boot);
+            info!("no-smp: {hidden} application processors left out of the topology");
+        }
+
+        let node = topology.current_node();
+        info!("{} NUMA nodes, booted on node {node}", topology.nodes().len());
+
+        // Memory outside every range is someone else's, probably a hot-plug window, so it goes last
+        frames::prefer(|pool| topology.node_of(pool.start).map_or(u8::MAX, |to| topology.distance(node, to)));
+
+        unsafe { TOPOLOGY = Some(topology); }
+
+        Ok(())
+    }
+}
+
+pub fn get() -> Option<&'static Topology> {
+    unsafe { TOPOLOGY.as_ref() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn srat() -> Vec<u8> {
+        let mut srat = vec![0; SRAT_ENTRIES];
+
+        // Local APIC 0 in domain 0, local APIC 1 in domain 1, and a disabled one
+        srat.extend_from_slice(&[0, 16, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        srat.extend_from_slice(&[0, 16, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        srat.extend_from_slice(&[0, 16, 1, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        for (node, start, len, flags) in [(0u32, 0u64, 0x8000_0000u64, 1u32), (1, 0x8000_0000, 0x8000_0000, 3)] {
+            let mut entry = vec![0; 40];
+            entry[0] = 1;
+            entry[1] = 40;
+            entry[2..6].copy_from_slice(&node.to_le_bytes());
+            entry[8..16].copy_from_slice(&start.to_le_bytes());
+            entry[16..24].copy_from_slice(&len.to_le_bytes());
+            entry[28..32].copy_from_slice(&flags.to_le_bytes());
+            srat.extend(entry);
+        }
+
+        srat
+    }
+
+    #[test_case]
+    fn srat_assigns_cpus_and_memory_to_nodes() {
+        let topology = Topology::parse(&srat(), None).unwrap();
+
+        assert_eq!(topology.cpus, [Cpu { apic_id: 0, node: 0 }, Cpu { apic_id: 1, node: 1 }]);
+        assert_eq!(topology.nodes(), [0, 1]);
+        assert_eq!(topology.node_of(0x1000), Some(0));
+        assert_eq!(topology.node_of(0x9000_0000), Some(1));
+        assert_eq!(topology.node_of(0x1_0000_0000), None);
+        assert!(topology.memory[1].hotplug);
+        assert_eq!(topology.distance(0, 1), REMOTE);
+    }
+
+    #[test_case]
+    fn slit_gives_distances() {
+        let mut slit = vec![0; 36];
+        slit.extend_from_slice(&2u64.to_le_bytes());
+        slit.extend_from_slice(&[10, 21, 21, 10]);
+
+        let topology = Topology::parse(&srat(), Some(&slit)).unwrap();
+        assert_eq!(topology.distance(0, 0), 10);
+        assert_eq!(topology.distance(1, 0), 21);
+    }
+
+    #[test_case]
+    fn malformed_srat_entries_fail() {
+        let mut srat = vec![0; SRAT_ENTRIES];
+        srat.extend_from_slice(&[1, 40, 0, 0]);
+        assert!(Topology::parse(&srat, None).is_err());
+    }
+
+    #[test_case]
+    fn malformed_slits_fail() {
+        assert!(Topology::parse(&srat(), Some(&[0; 40])).is_err());
+
+        let mut slit = vec![0; 36];
+        slit.extend_from_slice(&u64::MAX.to_le_bytes());
+        slit.extend_from_slice(&[10]);
+        assert!(Topology::parse(&srat(), Some(&slit)).is_err());
+    }
+
+    #[test_case]
+    fn overflowing_memory_ranges_fail() {
+        let mut srat = vec![0; SRAT_ENTRIES];
+        let mut entry = vec![0; 40];
+        entry[0] = 1;
+        entry[1] = 40;
+        entry[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+        entry[16..24].copy_from_slice(&2u64.to_le_bytes());
+        entry[28] = 1;
+        srat.extend(entry);
+
+        assert!(Topology::parse(&srat, None).is_err());
+    }
+}
@@ -1,25 +1,60 @@
+extern crate alloc;
+
 use acpi::mcfg::Mcfg;
+use alloc::vec::Vec;
 use anyhow::{anyhow, Result};
+use log::{debug, info};
 use x86_64::structures::paging::{PageSize, Size2MiB};
 
-use crate::{memory, println};
+use crate::{cmdline, memory};
+use crate::hal::{Direct, Mmio, PortIo, Ports};
 use crate::memory::MemoryPool;
 use crate::acpi::tables::ACPI;
 
 pub const PCI_START: u64 = 0xffffffff_00000000;
 
-pub struct PCI;
+const CONFIG_ADDRESS: u16 = 0xcf8;
+const CONFIG_DATA: u16 = 0xcfc;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Device {
+    pub bus:      u8,
+    pub device:   u8,
+    pub function: u8,
+    pub vendor:   u16,
+    pub id:       u16,
+    // Class, subclass, programming interface and revision, as in config register 0x08
+    pub class:    u32
+}
+
+pub struct PCI {
+    devices: Vec<Device>
+}
+
+static mut DEVICES: Option<PCI> = None;
 
 impl PCI {
+    pub fn init_global(acpi: &ACPI) -> Result<()> {
+        let pci = PCI::enumerate(acpi)?;
+        unsafe { DEVICES = Some(pci); }
+
+        Ok(())
+    }
+
     pub fn enumerate(acpi: &ACPI) -> Result<PCI> {
-        println!("[PCI] Enumerating Bus..");
+        if cmdline::pci_legacy() {
+            return PCI::enumerate_legacy();
+        }
 
+        info!("Enumerating Bus..");
+
+        let mut devices = Vec::new();
         let mcfg = acpi.tables.find_table::<Mcfg>().map_err(|e| anyhow!("{e:?}"))?;
         for entry in mcfg.entries() {
             let base = entry.base_address;
             let seggroup = entry.pci_segment_group;
 
-            println!("0x{:x}: SEGGROUP {} BUS {} - {}", base, seggroup, entry.bus_number_start, entry.bus_number_end);
+            debug!("0x{:x}: SEGGROUP {} BUS {} - {}", base, seggroup, entry.bus_number_start, entry.bus_number_end);
 
             for bus in entry.bus_number_start..=entry.bus_number_end {
                 let bus_start = base + bus as u64 * 256 * 4096;
@@ -28,12 +63,13 @@ impl PCI {
 
                 for device in 0..32 {
                     for function in 0..8 {
-                        let a = PCI::addr(PCI_START, bus % 2, device, function);
-                        let vid = unsafe { *(a as *const u16) };
-                        let pid = unsafe { *(a as *const u16).add(1) };
+                        let (vid, pid) = PCI::read_ecam(&Direct, PCI_START, bus % 2, device, function);
                         if vid == 0xffff { continue; }
 
-                        println!("BUS {bus} DEV {device} FUNC {function}: VID 0x{vid:x} PID 0x{pid:x}");
+                        debug!("BUS {bus} DEV {device} FUNC {function}: VID 0x{vid:x} PID 0x{pid:x}");
+
+                        let class = unsafe { Direct.read_u32(PCI::addr(PCI_START, bus % 2, device, function) + 8) };
+                        devices.push(Device { bus, device, function, vendor: vid, id: pid, class });
                     }
                 }
 
@@ -41,12 +77,139 @@ impl PCI {
             }
         }
 
-        println!("[PCI] Success");
+        info!("Success");
 
-        Ok(PCI)
+        Ok(PCI { devices })
+    }
+
+    fn enumerate_legacy() -> Result<PCI> {
+        info!("Enumerating Bus (legacy)..");
+
+        let mut devices = Vec::new();
+
+        for bus in 0..=255 {
+            for device in 0..32 {
+                for function in 0..8 {
+                    let id = PCI::read_legacy(&mut Ports, bus, device, function, 0);
+                    let (vid, pid) = (id as u16, (id >> 16) as u16);
+                    if vid == 0xffff { continue; }
+
+                    debug!("BUS {bus} DEV {device} FUNC {function}: VID 0x{vid:x} PID 0x{pid:x}");
+
+                    let class = PCI::read_legacy(&mut Ports, bus, device, function, 8);
+                    devices.push(Device { bus, device, function, vendor: vid, id: pid, class });
+                }
+            }
+        }
+
+        info!("Success");
+
+        Ok(PCI { devices })
+    }
+
+    pub fn devices(&self) -> &[Device] {
+        &self.devices
+    }
+
+    fn read_legacy(io: &mut impl PortIo, bus: u8, device: u8, function: u8, offset: u8) -> u32 {
+        PCI::read_config(io, bus, device, function, offset & 0xfc, 32)
+    }
+
+    // Narrower accesses go through the matching byte lanes of the data port
+    pub fn read_config(io: &mut impl PortIo, bus: u8, device: u8, function: u8, offset: u8, bits: u8) -> u32 {
+        let data = CONFIG_DATA + (offset & 3) as u16;
+
+        unsafe {
+            io.write_u32(CONFIG_ADDRESS, PCI::config_address(bus, device, function, offset));
+
+            match bits {
+                8  => io.read_u8(data) as u32,
+                16 => io.read_u16(data) as u32,
+                _  => io.read_u32(data)
+            }
+        }
+    }
+
+    pub fn write_config(io: &mut impl PortIo, bus: u8, device: u8, function: u8, offset: u8, bits: u8, value: u32) {
+        let data = CONFIG_DATA + (offset & 3) as u16;
+
+        unsafe {
+            io.write_u32(CONFIG_ADDRESS, PCI::config_address(bus, device, function, offset));
+
+            match bits {
+                8  => io.write_u8(data, value as u8),
+                16 => io.write_u16(data, value as u16),
+                _  => io.write_u32(data, value)
+            }
+        }
+    }
+
+    fn config_address(bus: u8, device: u8, function: u8, offset: u8) -> u32 {
+        0x80000000 | (bus as u32) << 16 | (device as u32) << 11 | (function as u32) << 8 | (offset & 0xfc) as u32
+    }
+
+    fn read_ecam(mmio: &impl Mmio, base: u64, bus: u8, device: u8, function: u8) -> (u16, u16) {
+        let a = PCI::addr(base, bus, device, function);
+        unsafe { (mmio.read_u16(a), mmio.read_u16(a + 2)) }
     }
 
     fn addr(base: u64, bus: u8, device: u8, function: u8) -> u64 {
         base + ((bus as u64) << 20 | (device as u64) << 15 | (function as u64) << 12)
     }
 }
+
+pub fn get() -> Option<&'static PCI> {
+    unsafe { DEVICES.as_ref() }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use super::*;
+    use alloc::vec;
+    use crate::hal::mock::{MockMmio, MockPorts};
+
+    #[test_case]
+    fn addr_packs_bus_device_function() {
+        assert_eq!(PCI::addr(0, 0, 0, 0), 0);
+        assert_eq!(PCI::addr(0, 1, 2, 3), 1 << 20 | 2 << 15 | 3 << 12);
+        assert_eq!(PCI::addr(0x1000_0000, 255, 31, 7), 0x1000_0000 + 0x0fff_f000);
+    }
+
+    #[test_case]
+    fn addr_stays_within_one_bus() {
+        let first = PCI::addr(PCI_START, 1, 0, 0);
+        let last = PCI::addr(PCI_START, 1, 31, 7);
+        assert_eq!(last - first + 4096, 256 * 4096);
+    }
+
+    #[test_case]
+    fn legacy_selects_register_then_reads_data() {
+        let mut io = MockPorts::default();
+        io.queue(CONFIG_DATA, &[0x1234_8086]);
+
+        assert_eq!(PCI::read_legacy(&mut io, 2, 3, 1, 0x0b), 0x1234_8086);
+        assert_eq!(io.writes, [(CONFIG_ADDRESS, 0x8000_0000 | 2 << 16 | 3 << 11 | 1 << 8 | 0x08)]);
+    }
+
+    #[test_case]
+    fn narrow_config_writes_use_byte_lanes() {
+        let mut io = MockPorts::default();
+        PCI::write_config(&mut io, 0, 1, 0, 0x42, 8, 0x80);
+
+        assert_eq!(io.writes, [(CONFIG_ADDRESS, 0x8000_0000 | 1 << 11 | 0x40), (CONFIG_DATA + 2, 0x80)]);
+    }
+
+    #[test_case]
+    fn ecam_reads_vendor_and_device() {
+        let base = 0x1000_0000;
+        let mut bytes = vec![0xff; 2 << 15];
+        bytes[1 << 15..(1 << 15) + 4].copy_from_slice(&[0x86, 0x80, 0x34, 0x12]);
+        let mmio = MockMmio { base, bytes };
+
+        assert_eq!(PCI::read_ecam(&mmio, base, 0, 1, 0), (0x8086, 0x1234));
+        assert_eq!(PCI::read_ecam(&mmio, base, 0, 0, 0).0, 0xffff);
+        assert_eq!(PCI::read_ecam(&mmio, base, 1, 0, 0).0, 0xffff);
+    }
+}
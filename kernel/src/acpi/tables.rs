@@ -1,22 +1,107 @@
+extern crate alloc;
+
+use core::{mem, slice};
 use acpi::AcpiTables;
+use acpi::sdt::SdtHeader;
+use alloc::vec::Vec;
 use anyhow::{anyhow, Result};
+use log::info;
 
-use crate::println;
 use crate::acpi::mapper::AcpiMapper;
 
 pub struct ACPI {
-    pub tables: AcpiTables<AcpiMapper>
+    pub tables: AcpiTables<AcpiMapper>,
+    rsdp:       u64
+}
+
+// A table as it sits in physical memory, which is identity mapped
+#[derive(Clone, Copy)]
+pub struct RawTable {
+    pub addr: u64
 }
 
+impl RawTable {
+    pub fn signature(&self) -> &'static str {
+        let bytes = unsafe { slice::from_raw_parts(self.addr as *const u8, 4) };
+        core::str::from_utf8(bytes).unwrap_or("????")
+    }
+
+    pub fn header(&self) -> &'static SdtHeader {
+        unsafe { &*(self.addr as *const SdtHeader) }
+    }
+
+    pub fn bytes(&self) -> &'static [u8] {
+        unsafe { slice::from_raw_parts(self.addr as *const u8, self.header().length as usize) }
+    }
+
+    pub fn revision(&self) -> u8 {
+        self.header().revision
+    }
+
+    // Every byte of a table, header included, adds up to zero
+    pub fn checksum_valid(&self) -> bool {
+        self.bytes().iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+    }
+}
+
+static mut TABLES: Option<ACPI> = None;
+
 impl ACPI {
+    pub fn init_global(addr: u64) -> Result<()> {
+        let acpi = ACPI::parse(addr)?;
+        unsafe { TABLES = Some(acpi); }
+
+        Ok(())
+    }
+
     pub fn parse(addr: u64) -> Result<ACPI> {
-        println!("[ACPI] Parsing Tables..");
+        info!("Parsing Tables..");
 
         let mapper = AcpiMapper::new();
         let tables = unsafe { AcpiTables::from_rsdp(mapper, addr as usize).map_err(|e| anyhow!("{e:?}"))? };
 
-        println!("[ACPI] Success");
+        info!("Success");
+
+        Ok(ACPI { tables, rsdp: addr })
+    }
+
+    // Every table the XSDT (or RSDT) points at, plus the DSDT which only the FADT knows about
+    pub fn raw_tables(&self) -> Vec<RawTable> {
+        let rsdp = self.rsdp as *const u8;
+
+        unsafe {
+            let revision = rsdp.add(15).read();
+            let (root, entry_size) = match revision {
+                0 => ((rsdp.add(16) as *const u32).read_unaligned() as u64, 4),
+                _ => ((rsdp.add(24) as *const u64).read_unaligned(), 8)
+            };
 
-        Ok(ACPI { tables })
+            let root = RawTable { addr: root };
+            let entries = &root.bytes()[mem::size_of::<SdtHeader>()..];
+
+            let mut tables: Vec<RawTable> = entries
+                .chunks_exact(entry_size)
+                .map(|entry| {
+                    let mut addr = [0; 8];
+                    addr[..entry_size].copy_from_slice(entry);
+                    RawTable { addr: u64::from_le_bytes(addr) }
+                })
+                .collect();
+
+            if let Ok(dsdt) = self.tables.dsdt() {
+                tables.push(RawTable { addr: (dsdt.address - mem::size_of::<SdtHeader>()) as u64 });
+            }
+
+            tables.insert(0, root);
+            tables
+        }
     }
+
+    pub fn raw_table(&self, signature: &str) -> Option<RawTable> {
+        self.raw_tables().into_iter().find(|table| table.signature().eq_ignore_ascii_case(signature))
+    }
+}
+
+pub fn get() -> Option<&'static ACPI> {
+    unsafe { TABLES.as_ref() }
 }
@@ -38,3 +38,38 @@ unsafe impl GlobalAlloc for Allocator {
 
 #[global_allocator]
 static ALLOCATOR: Allocator = Allocator { top: Cell::new(ptr::addr_of!(HEAP_BEGIN) as *mut u8) };
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+
+    #[test_case]
+    fn boxes_are_distinct() {
+        let a = Box::new(1u64);
+        let b = Box::new(2u64);
+
+        assert_ne!(&*a as *const u64, &*b as *const u64);
+        assert_eq!(*a + *b, 3);
+    }
+
+    #[test_case]
+    fn vec_survives_growth() {
+        let v = (0..10_000u64).collect::<Vec<_>>();
+        assert_eq!(v.iter().sum::<u64>(), 10_000 * 9_999 / 2);
+    }
+
+    #[test_case]
+    fn large_alignment_is_honoured() {
+        #[repr(align(4096))]
+        struct Page([u8; 4096]);
+
+        let _misalign = Box::new(0u8);
+        let page = Box::new(Page([0; 4096]));
+
+        assert_eq!(&*page as *const Page as usize % 4096, 0);
+        assert_eq!(page.0[4095], 0);
+    }
+}
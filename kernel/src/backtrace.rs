@@ -0,0 +1,65 @@
+use core::arch::asm;
+use core::ops::Range;
+use core::ptr;
+
+use crate::symbols::Symbol;
+use crate::{gdt, println, process};
+
+const MAX_FRAMES: usize = 64;
+
+extern "C" {
+    #[link_name = "_stack_bottom"]
+    static STACK_BOTTOM: u8;
+
+    #[link_name = "_stack_top"]
+    static STACK_TOP: u8;
+}
+
+// Every stack the kernel may be running on, a frame pointer outside of these ends the walk
+fn stack_of(addr: u64) -> Option<Range<u64>> {
+    let boot = ptr::addr_of!(STACK_BOTTOM) as u64..ptr::addr_of!(STACK_TOP) as u64;
+    let process = process::current().map(|process| process.kernel_stack());
+
+    [Some(boot), Some(gdt::double_fault_stack()), process]
+        .into_iter()
+        .flatten()
+        .find(|stack| stack.contains(&addr))
+}
+
+#[inline(always)]
+pub fn frame_pointer() -> u64 {
+    let rbp: u64;
+    unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)); }
+    rbp
+}
+
+// Follows the saved rbp chain, each frame holds the caller's rbp followed by the return address
+pub fn walk(mut rbp: u64, mut f: impl FnMut(u64)) {
+    for _ in 0..MAX_FRAMES {
+        let Some(stack) = stack_of(rbp) else { return; };
+        if !rbp.is_multiple_of(8) || rbp + 16 > stack.end { return; }
+
+        let (next, ret) = unsafe { (*(rbp as *const u64), *((rbp + 8) as *const u64)) };
+        if ret == 0 { return; }
+
+        f(ret);
+
+        // Frames only grow towards the top of a stack, unless an exception moved us to another one
+        if stack.contains(&next) && next <= rbp { return; }
+        rbp = next;
+    }
+}
+
+pub fn print() {
+    print_from(frame_pointer());
+}
+
+pub fn print_from(rbp: u64) {
+    println!("Backtrace:");
+
+    let mut i = 0;
+    walk(rbp, |addr| {
+        println!("{i:>3}: {}", Symbol(addr));
+        i += 1;
+    });
+}
@@ -0,0 +1,144 @@
+extern crate alloc;
+
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use anyhow::{anyhow, Result};
+
+/*
+
+# Global options come before the first entry
+timeout = 5
+default = 0
+
+[entry]
+title   = AOS
+kernel  = \kernel.elf
+initrd  = \initrd.cpio
+cmdline = log=info
+video   = 1920x1080
+splash  = \splash.qoi
+
+*/
+
+pub struct Entry {
+    pub title:   String,
+    pub kernel:  String,
+    pub initrd:  Option<String>,
+    pub cmdline: String,
+    pub video:   (usize, usize),
+    pub splash:  Option<String>
+}
+
+pub struct Config {
+    pub timeout: Option<u64>,
+    pub default: usize,
+    pub entries: Vec<Entry>
+}
+
+impl Entry {
+    fn new(title: &str) -> Entry {
+        Entry {
+            title:   title.to_string(),
+            kernel:  "\\kernel.elf".to_string(),
+            initrd:  Some("\\initrd.cpio".to_string()),
+            cmdline: String::new(),
+            video:   (1920, 1080),
+            splash:  None
+        }
+    }
+}
+
+// '#' starts a comment at the beginning of a line or after whitespace, so cmdline = color=#ff8800 keeps its value
+fn strip_comment(line: &str) -> &str {
+    let bytes = line.as_bytes();
+    let start = (0..bytes.len()).find(|&i| bytes[i] == b'#' && (i == 0 || bytes[i - 1].is_ascii_whitespace()));
+
+    &line[..start.unwrap_or(line.len())]
+}
+
+impl Config {
+    pub fn fallback() -> Config {
+        Config {
+            timeout: Some(0),
+            default: 0,
+            entries: vec![Entry::new("AOS")]
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Config> {
+        let mut config = Config { timeout: None, default: 0, entries: Vec::new() };
+
+        for (i, line) in text.lines().enumerate() {
+            let line = strip_comment(line.trim()).trim();
+            if line.is_empty() { continue; }
+
+            let err = |msg: &str| anyhow!("boot.cfg:{}: {msg}", i + 1);
+
+            if line == "[entry]" {
+                let title = alloc::format!("Entry {}", config.entries.len());
+                config.entries.push(Entry::new(&title));
+                continue;
+            }
+
+            let (key, value) = line.split_once('=').ok_or(err("expected key = value"))?;
+            let (key, value) = (key.trim(), value.trim());
+
+            let Some(entry) = config.entries.last_mut() else {
+                match key {
+                    "timeout" => config.timeout = Some(value.parse().map_err(|_| err("bad timeout"))?),
+                    "default" => config.default = value.parse().map_err(|_| err("bad default"))?,
+                    _         => return Err(err("unknown global option"))
+                }
+
+                continue;
+            };
+
+            match key {
+                "title"   => entry.title = value.to_string(),
+                "kernel"  => entry.kernel = value.to_string(),
+                "initrd"  => entry.initrd = if value.is_empty() { None } else { Some(value.to_string()) },
+                "cmdline" => entry.cmdline = value.to_string(),
+                "splash"  => entry.splash = if value.is_empty() { None } else { Some(value.to_string()) },
+                "video"   => {
+                    let (w, h) = value.split_once('x').ok_or(err("video expects WIDTHxHEIGHT"))?;
+                    entry.video = (
+                        w.trim().parse().map_err(|_| err("bad video width"))?,
+                        h.trim().parse().map_err(|_| err("bad video height"))?
+                    );
+                }
+                _ => return Err(err("unknown entry option"))
+            }
+        }
+
+        if config.entries.is_empty() {
+            return Err(anyhow!("boot.cfg: no entries"));
+        }
+
+        if config.default >= config.entries.len() {
+            return Err(anyhow!("boot.cfg: default entry {} does not exist", config.default));
+        }
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn comments_start_lines_or_follow_whitespace() {
+        let config = Config::parse("# boot.cfg\ntimeout = 3 # seconds\n[entry]\ncmdline = color=#ff8800 log=info #trailing\n").unwrap();
+
+        assert_eq!(config.timeout, Some(3));
+        assert_eq!(config.entries[0].cmdline, "color=#ff8800 log=info");
+    }
+
+    #[test_case]
+    fn entries_need_known_options() {
+        assert!(Config::parse("[entry]\nkernel = \\kernel.elf\n").is_ok());
+        assert!(Config::parse("[entry]\nvideo = big\n").is_err());
+        assert!(Config::parse("timeout = 1\n").is_err());
+    }
+}
@@ -0,0 +1,92 @@
+use core::slice;
+
+use crate::drivers::video::framebuffer::Framebuffer;
+use crate::memory::MemoryPool;
+
+// Everything the loader hands over to the kernel, it lives in loader data that is never reclaimed
+
+#[repr(C)]
+pub struct BootInfo {
+    pub acpi:          u64,
+    pub free_ptr:      *const MemoryPool,
+    pub free_size:     usize,
+    pub firmware_ptr:  *const FirmwareRegion,
+    pub firmware_size: usize,
+    pub framebuffer:   Framebuffer<'static>,
+    pub initrd:        Region,
+    pub cmdline:       Region,
+    pub symtab:        Region,
+    pub strtab:        Region,
+    // From EFI_RNG_PROTOCOL, empty when the firmware has none
+    pub seed:          Region,
+    // Boot splash image from the ESP, empty when boot.cfg names none
+    pub splash:        Region
+}
+
+impl BootInfo {
+    pub fn cmdline(&self) -> &'static str {
+        core::str::from_utf8(unsafe { self.cmdline.as_slice() }).unwrap_or("")
+    }
+
+    pub fn seed(&self) -> &'static [u8] {
+        unsafe { self.seed.as_slice() }
+    }
+
+    pub fn splash(&self) -> &'static [u8] {
+        unsafe { self.splash.as_slice() }
+    }
+
+    pub fn firmware(&self) -> &'static [FirmwareRegion] {
+        if self.firmware_ptr.is_null() { return &[]; }
+        unsafe { slice::from_raw_parts(self.firmware_ptr, self.firmware_size) }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Region {
+    pub start: u64,
+    pub size:  u64
+}
+
+impl Region {
+    pub const fn empty() -> Region {
+        Region { start: 0, size: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// # Safety
+    ///
+    /// The region must be memory the bootloader handed over, mapped and never written to afterwards.
+    pub unsafe fn as_slice(&self) -> &'static [u8] {
+        if self.is_empty() { return &[]; }
+        slice::from_raw_parts(self.start as *const u8, self.size as usize)
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FirmwareKind {
+    // Holds the ACPI tables, free to reuse once nothing points into them anymore
+    AcpiReclaimable,
+    // Saved across sleep states by the firmware, never to be touched otherwise
+    AcpiNvs
+}
+
+// Memory the firmware keeps for itself, as opposed to MMIO holes that are not memory at all
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct FirmwareRegion {
+    pub start: u64,
+    pub end:   u64,
+    pub kind:  FirmwareKind
+}
+
+impl FirmwareRegion {
+    pub fn contains(&self, start: u64, end: u64) -> bool {
+        self.start <= start && end <= self.end
+    }
+}
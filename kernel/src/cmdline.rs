@@ -0,0 +1,223 @@
+extern crate alloc;
+
+use core::str::FromStr;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use log::{info, warn};
+
+use crate::drivers::console::Output;
+use crate::drivers::video::fonts;
+
+/*
+
+Options are separated by whitespace and are either flags or key=value pairs, the last occurrence wins
+
+log=<level>[,<module>:<level>..]       log verbosity, info by default, e.g. log=warn,acpi::pci:debug
+console=<fb|serial|both>                where kernel messages go, fb by default
+font=<sf-pro|cylburn>                   console font
+font_size=<points>                      console font scale
+color=<rrggbb>                          console text colour
+no-smp[=<on|off>]                       leave application processors out of the NUMA topology
+nokaslr[=<on|off>]                      fixed kernel and program load addresses
+pci=legacy                              use port I/O configuration access instead of MCFG
+verbose[=<on|off>]                      boot messages instead of the splash screen
+
+*/
+
+pub const KNOWN: &[&str] = &["log", "console", "font", "font_size", "color", "no-smp", "nokaslr", "pci", "verbose"];
+const FLAGS:     &[&str] = &["no-smp", "nokaslr", "verbose"];
+
+#[derive(Clone, Copy)]
+pub struct Param {
+    pub key:   &'static str,
+    pub value: Option<&'static str>
+}
+
+pub struct Cmdline {
+    raw:    &'static str,
+    params: Vec<Param>
+}
+
+static mut CMDLINE: Option<Cmdline> = None;
+// Checked once in init, before there is anywhere to log to, and reported once the logger is up
+static mut INVALID: Vec<String> = Vec::new();
+
+// Present without a value means on
+fn boolean(value: Option<&str>) -> Option<bool> {
+    match value {
+        None | Some("1" | "on" | "yes" | "true") => Some(true),
+        Some("0" | "off" | "no" | "false")       => Some(false),
+        Some(_)                                  => None
+    }
+}
+
+impl Cmdline {
+    pub fn parse(raw: &'static str) -> Cmdline {
+        let params = raw.split_whitespace()
+            .map(|token| {
+                match token.split_once('=') {
+                    Some((key, value)) => Param { key, value: Some(value) },
+                    None               => Param { key: token, value: None }
+                }
+            })
+            .collect();
+
+        Cmdline { raw, params }
+    }
+
+    pub fn raw(&self) -> &'static str {
+        self.raw
+    }
+
+    pub fn params(&self) -> &[Param] {
+        &self.params
+    }
+
+    fn last(&self, key: &str) -> Option<&Param> {
+        self.params.iter().rev().find(|param| param.key == key)
+    }
+
+    // An invalid value counts as off
+    pub fn flag(&self, key: &str) -> bool {
+        self.last(key).is_some_and(|param| boolean(param.value).unwrap_or(false))
+    }
+
+    pub fn value(&self, key: &str) -> Option<&'static str> {
+        self.last(key).and_then(|param| param.value)
+    }
+
+    pub fn get<T: FromStr>(&self, key: &str) -> Option<T> {
+        self.value(key)?.parse().ok()
+    }
+
+    // Values the accessors above would quietly ignore, only the occurrence that wins is looked at
+    pub fn invalid(&self) -> Vec<String> {
+        let flags = FLAGS.iter()
+            .filter_map(|&key| self.last(key))
+            .filter(|param| boolean(param.value).is_none());
+        let numbers = self.last("font_size").filter(|param| param.value.is_some_and(|value| value.parse::<f32>().is_err()));
+
+        flags.chain(numbers)
+            .map(|param| format!("Ignoring invalid value {}={}", param.key, param.value.unwrap_or_default()))
+            .collect()
+    }
+
+    pub fn unknown(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.params.iter()
+            .map(|param| param.key)
+            .filter(|key| !KNOWN.contains(key))
+    }
+}
+
+pub fn init(raw: &'static str) {
+    let cmdline = Cmdline::parse(raw);
+
+    unsafe {
+        INVALID = cmdline.invalid();
+        CMDLINE = Some(cmdline);
+    }
+}
+
+pub fn get() -> &'static Cmdline {
+    static EMPTY: Cmdline = Cmdline { raw: "", params: Vec::new() };
+    unsafe { CMDLINE.as_ref().unwrap_or(&EMPTY) }
+}
+
+// Called once the logger is up, since parsing happens before anything can be logged
+pub fn report() {
+    let cmdline = get();
+
+    if !cmdline.raw().is_empty() {
+        info!("{}", cmdline.raw());
+    }
+
+    for key in cmdline.unknown() {
+        warn!("Unknown option {key}");
+    }
+
+    for message in unsafe { INVALID.drain(..) } {
+        warn!("{message}");
+    }
+}
+
+pub fn log_filter() -> &'static str {
+    get().value("log").unwrap_or("info")
+}
+
+pub fn console() -> Output {
+    match get().value("console") {
+        Some("serial") => Output::Serial,
+        Some("both")   => Output::Both,
+        _              => Output::Framebuffer
+    }
+}
+
+pub fn font() -> &'static [u8] {
+    match get().value("font") {
+        Some("cylburn") => fonts::CYLBURN,
+        _               => fonts::SF_PRO
+    }
+}
+
+pub fn font_size() -> f32 {
+    get().get("font_size").filter(|size: &f32| *size > 0.0).unwrap_or(30.0)
+}
+
+pub fn color() -> (f32, f32, f32) {
+    let rgb = get().value("color")
+        .and_then(|hex| u32::from_str_radix(hex.trim_start_matches('#'), 16).ok())
+        .unwrap_or(0xffffff);
+
+    ((rgb >> 16 & 0xff) as f32, (rgb >> 8 & 0xff) as f32, (rgb & 0xff) as f32)
+}
+
+pub fn no_smp() -> bool {
+    get().flag("no-smp")
+}
+
+// The bootloader parses its own copy to place the kernel, the loader asks for programs
+pub fn kaslr() -> bool {
+    !get().flag("nokaslr")
+}
+
+pub fn pci_legacy() -> bool {
+    get().value("pci") == Some("legacy")
+}
+
+pub fn verbose() -> bool {
+    get().flag("verbose")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn flags_take_boolean_values() {
+        let cmdline = Cmdline::parse("no-smp=0 verbose a=on b=off c=maybe");
+
+        assert!(!cmdline.flag("no-smp"));
+        assert!(cmdline.flag("verbose"));
+        assert!(cmdline.flag("a"));
+        assert!(!cmdline.flag("b"));
+        assert!(!cmdline.flag("c"));
+        assert!(!cmdline.flag("missing"));
+    }
+
+    #[test_case]
+    fn last_occurrence_wins() {
+        let cmdline = Cmdline::parse("no-smp no-smp=off font_size=12 font_size=14");
+
+        assert!(!cmdline.flag("no-smp"));
+        assert_eq!(cmdline.get::<f32>("font_size"), Some(14.0));
+    }
+
+    #[test_case]
+    fn invalid_values_are_found_up_front() {
+        let cmdline = Cmdline::parse("verbose=maybe nokaslr=what nokaslr font_size=big color=#ff8800");
+
+        assert_eq!(cmdline.invalid(), ["Ignoring invalid value verbose=maybe", "Ignoring invalid value font_size=big"]);
+        assert!(Cmdline::parse("verbose no-smp=off font_size=12").invalid().is_empty());
+    }
+}
@@ -0,0 +1,465 @@
+use core::arch::global_asm;
+use core::arch::x86_64::__cpuid_count;
+use core::fmt::{self, Display, Formatter, Write};
+use core::ptr;
+use anyhow::anyhow;
+use log::info;
+use x86_64::registers::control::{Cr4, Cr4Flags};
+
+use crate::shell::{self, Args, Command, Error};
+
+const THERM_STATUS:       u32 = 0x19c;
+const TEMPERATURE_TARGET: u32 = 0x1a2;
+
+pub trait Cpuid {
+    // eax, ebx, ecx, edx
+    fn cpuid(&self, leaf: u32, subleaf: u32) -> [u32; 4];
+}
+
+pub struct Native;
+
+impl Cpuid for Native {
+    fn cpuid(&self, leaf: u32, subleaf: u32) -> [u32; 4] {
+        let r = __cpuid_count(leaf, subleaf);
+        [r.eax, r.ebx, r.ecx, r.edx]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Feature {
+    Tsc, Msr, Pae, Pse, Pge, Pat, Apic, Fxsr, Sse, Sse2, Sse3, Ssse3, Sse41, Sse42,
+    Avx, Avx2, Avx512f, Xsave, X2apic, Pcid, Invpcid, Smep, Smap, Umip, Fsgsbase,
+    Rdrand, Rdseed, TscDeadline, InvariantTsc, Syscall, Nx, Pages1g, Rdtscp, LongMode,
+    Hypervisor, ThermalSensor, PackageThermal
+}
+
+impl Feature {
+    pub const ALL: [Feature; 37] = [
+        Feature::Tsc, Feature::Msr, Feature::Pae, Feature::Pse, Feature::Pge, Feature::Pat, Feature::Apic,
+        Feature::Fxsr, Feature::Sse, Feature::Sse2, Feature::Sse3, Feature::Ssse3, Feature::Sse41, Feature::Sse42,
+        Feature::Avx, Feature::Avx2, Feature::Avx512f, Feature::Xsave, Feature::X2apic, Feature::Pcid,
+        Feature::Invpcid, Feature::Smep, Feature::Smap, Feature::Umip, Feature::Fsgsbase, Feature::Rdrand,
+        Feature::Rdseed, Feature::TscDeadline, Feature::InvariantTsc, Feature::Syscall, Feature::Nx,
+        Feature::Pages1g, Feature::Rdtscp, Feature::LongMode, Feature::Hypervisor, Feature::ThermalSensor,
+        Feature::PackageThermal
+    ];
+
+    // What the kernel cannot run without: 2MiB pages, NX mappings, syscall/sysret and the x86_64 baseline
+    pub const REQUIRED: [Feature; 10] = [
+        Feature::Tsc, Feature::Msr, Feature::Pae, Feature::Pse, Feature::Fxsr, Feature::Sse, Feature::Sse2,
+        Feature::Syscall, Feature::Nx, Feature::LongMode
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Feature::Tsc            => "tsc",
+            Feature::Msr            => "msr",
+            Feature::Pae            => "pae",
+            Feature::Pse            => "pse",
+            Feature::Pge            => "pge",
+            Feature::Pat            => "pat",
+            Feature::Apic           => "apic",
+            Feature::Fxsr           => "fxsr",
+            Feature::Sse            => "sse",
+            Feature::Sse2           => "sse2",
+            Feature::Sse3           => "sse3",
+            Feature::Ssse3          => "ssse3",
+            Feature::Sse41          => "sse4.1",
+            Feature::Sse42          => "sse4.2",
+            Feature::Avx            => "avx",
+            Feature::Avx2           => "avx2",
+            Feature::Avx512f        => "avx512f",
+            Feature::Xsave          => "xsave",
+            Feature::X2apic         => "x2apic",
+            Feature::Pcid           => "pcid",
+            Feature::Invpcid        => "invpcid",
+            Feature::Smep           => "smep",
+            Feature::Smap           => "smap",
+            Feature::Umip           => "umip",
+            Feature::Fsgsbase       => "fsgsbase",
+            Feature::Rdrand         => "rdrand",
+            Feature::Rdseed         => "rdseed",
+            Feature::TscDeadline    => "tsc-deadline",
+            Feature::InvariantTsc   => "invariant-tsc",
+            Feature::Syscall        => "syscall",
+            Feature::Nx             => "nx",
+            Feature::Pages1g        => "1g-pages",
+            Feature::Rdtscp         => "rdtscp",
+            Feature::LongMode       => "lm",
+            Feature::Hypervisor     => "hypervisor",
+            Feature::ThermalSensor  => "dts",
+            Feature::PackageThermal => "pts"
+        }
+    }
+
+    // Leaf, register index into eax/ebx/ecx/edx, and bit
+    fn location(&self) -> (u32, usize, u32) {
+        match self {
+            Feature::Tsc            => (1, 3, 4),
+            Feature::Msr            => (1, 3, 5),
+            Feature::Pae            => (1, 3, 6),
+            Feature::Pse            => (1, 3, 3),
+            Feature::Pge            => (1, 3, 13),
+            Feature::Pat            => (1, 3, 16),
+            Feature::Apic           => (1, 3, 9),
+            Feature::Fxsr           => (1, 3, 24),
+            Feature::Sse            => (1, 3, 25),
+            Feature::Sse2           => (1, 3, 26),
+            Feature::Sse3           => (1, 2, 0),
+            Feature::Ssse3          => (1, 2, 9),
+            Feature::Sse41          => (1, 2, 19),
+            Feature::Sse42          => (1, 2, 20),
+            Feature::Pcid           => (1, 2, 17),
+            Feature::X2apic         => (1, 2, 21),
+            Feature::TscDeadline    => (1, 2, 24),
+            Feature::Xsave          => (1, 2, 26),
+            Feature::Avx            => (1, 2, 28),
+            Feature::Rdrand         => (1, 2, 30),
+            Feature::Hypervisor     => (1, 2, 31),
+            Feature::ThermalSensor  => (6, 0, 0),
+            Feature::PackageThermal => (6, 0, 6),
+            Feature::Fsgsbase       => (7, 1, 0),
+            Feature::Avx2           => (7, 1, 5),
+            Feature::Smep           => (7, 1, 7),
+            Feature::Invpcid        => (7, 1, 10),
+            Feature::Avx512f        => (7, 1, 16),
+            Feature::Rdseed         => (7, 1, 18),
+            Feature::Smap           => (7, 1, 20),
+            Feature::Umip           => (7, 2, 2),
+            Feature::Syscall        => (0x80000001, 3, 11),
+            Feature::Nx             => (0x80000001, 3, 20),
+            Feature::Pages1g        => (0x80000001, 3, 26),
+            Feature::Rdtscp         => (0x80000001, 3, 27),
+            Feature::LongMode       => (0x80000001, 3, 29),
+            Feature::InvariantTsc   => (0x80000007, 3, 8)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Features(u64);
+
+impl Features {
+    pub fn contains(&self, feature: Feature) -> bool {
+        self.0 & 1 << feature as u64 != 0
+    }
+
+    pub fn insert(&mut self, feature: Feature) {
+        self.0 |= 1 << feature as u64;
+    }
+
+    pub fn missing(&self, features: &[Feature]) -> Features {
+        let mut missing = Features::default();
+        for &feature in features.iter().filter(|&&f| !self.contains(f)) {
+            missing.insert(feature);
+        }
+        missing
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl Display for Features {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for (i, feature) in Feature::ALL.iter().filter(|&&feature| self.contains(feature)).enumerate() {
+            write!(f, "{}{}", if i == 0 { "" } else { " " }, feature.name())?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Info {
+    vendor:       [u8; 12],
+    brand:        [u8; 48],
+    pub family:   u32,
+    pub model:    u32,
+    pub stepping: u32,
+    pub features: Features
+}
+
+impl Info {
+    pub fn decode(cpuid: &impl Cpuid) -> Info {
+        let [max_leaf, b, c, d] = cpuid.cpuid(0, 0);
+        let max_extended = cpuid.cpuid(0x80000000, 0)[0];
+
+        let mut vendor = [0; 12];
+        for (chunk, reg) in vendor.chunks_exact_mut(4).zip([b, d, c]) {
+            chunk.copy_from_slice(&reg.to_le_bytes());
+        }
+
+        let mut brand = [0; 48];
+        if max_extended >= 0x80000004 {
+            for (i, leaf) in (0x80000002..=0x80000004).enumerate() {
+                for (j, reg) in cpuid.cpuid(leaf, 0).iter().enumerate() {
+                    brand[i * 16 + j * 4..][..4].copy_from_slice(&reg.to_le_bytes());
+                }
+            }
+        }
+
+        // Extended family and model only count for the families that overflowed the base fields
+        let signature = cpuid.cpuid(1, 0)[0];
+        let base_family = signature >> 8 & 0xf;
+        let base_model = signature >> 4 & 0xf;
+
+        let family = if base_family == 0xf { base_family + (signature >> 20 & 0xff) } else { base_family };
+        let model = if base_family == 0x6 || base_family == 0xf { (signature >> 16 & 0xf) << 4 | base_model } else { base_model };
+
+        let mut features = Features::default();
+        for feature in Feature::ALL {
+            let (leaf, reg, bit) = feature.location();
+            let max = if leaf >= 0x80000000 { max_extended } else { max_leaf };
+
+            if leaf <= max && cpuid.cpuid(leaf, 0)[reg] >> bit & 1 != 0 {
+                features.insert(feature);
+            }
+        }
+
+        Info { vendor, brand, family, model, stepping: signature & 0xf, features }
+    }
+
+    pub fn vendor(&self) -> &str {
+        core::str::from_utf8(&self.vendor).unwrap_or("unknown")
+    }
+
+    pub fn brand(&self) -> &str {
+        let len = self.brand.iter().position(|&b| b == 0).unwrap_or(self.brand.len());
+        core::str::from_utf8(&self.brand[..len]).unwrap_or("").trim()
+    }
+
+    pub fn has(&self, feature: Feature) -> bool {
+        self.features.contains(feature)
+    }
+}
+
+static mut INFO: Option<Info> = None;
+
+// Refuses to go on without the required features, then turns on the ones that cost nothing
+pub fn init() {
+    let info = Info::decode(&Native);
+
+    let missing = info.features.missing(&Feature::REQUIRED);
+    if !missing.is_empty() {
+        panic!("{} {} is missing required CPU features: {missing}", info.vendor(), info.brand());
+    }
+
+    info!("{} family 0x{:x} model 0x{:x} stepping {}", info.brand(), info.family, info.model, info.stepping);
+
+    // SMAP stays off, system calls read user memory directly. FSGSBASE would let user space change
+    // bases the kernel does not save
+    let mut flags = Cr4Flags::empty();
+    if info.has(Feature::Pge)  { flags |= Cr4Flags::PAGE_GLOBAL; }
+    if info.has(Feature::Smep) { flags |= Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION; }
+    if info.has(Feature::Umip) { flags |= Cr4Flags::USER_MODE_INSTRUCTION_PREVENTION; }
+    // Every address space uses PCID 0, so CR3 writes flush the same as before
+    if info.has(Feature::Pcid) { flags |= Cr4Flags::PCID; }
+
+    unsafe {
+        Cr4::update(|cr4| cr4.insert(flags));
+        INFO = Some(info);
+    }
+}
+
+pub fn get() -> &'static Info {
+    unsafe { INFO.as_ref().expect("CPU is not initialized") }
+}
+
+pub fn has(feature: Feature) -> bool {
+    features().contains(feature)
+}
+
+// None at all before init
+pub fn features() -> Features {
+    unsafe { INFO.as_ref().map_or(Features::default(), |info| info.features) }
+}
+
+pub fn apic_id() -> u32 {
+    if has(Feature::X2apic) && Native.cpuid(0, 0)[0] >= 0xb {
+        Native.cpuid(0xb, 0)[3]
+    } else {
+        Native.cpuid(1, 0)[1] >> 24
+    }
+}
+
+// rdmsr and wrmsr with the register in edi, the #GP handler sends a fault on either instruction to
+// msr_fixup, which returns false instead
+global_asm!(r#"
+    .section .text.msr, "ax"
+
+    .global msr_read_checked
+msr_read_checked:
+    mov ecx, edi
+    .global msr_read_at
+msr_read_at:
+    rdmsr
+    mov [rsi], eax
+    mov [rsi + 4], edx
+    mov eax, 1
+    ret
+
+    .global msr_write_checked
+msr_write_checked:
+    mov ecx, edi
+    mov eax, esi
+    mov rdx, rsi
+    shr rdx, 32
+    .global msr_write_at
+msr_write_at:
+    wrmsr
+    mov eax, 1
+    ret
+
+    .global msr_fixup
+msr_fixup:
+    xor eax, eax
+    ret
+"#);
+
+extern "sysv64" {
+    fn msr_read_checked(msr: u32, value: *mut u64) -> bool;
+    fn msr_write_checked(msr: u32, value: u64) -> bool;
+}
+
+extern "C" {
+    #[link_name = "msr_read_at"]
+    static MSR_READ_AT: u8;
+
+    #[link_name = "msr_write_at"]
+    static MSR_WRITE_AT: u8;
+
+    #[link_name = "msr_fixup"]
+    static MSR_FIXUP: u8;
+}
+
+// None if the register does not exist on this CPU
+pub fn read_msr(msr: u32) -> Option<u64> {
+    let mut value = 0;
+    unsafe { msr_read_checked(msr, &mut value) }.then_some(value)
+}
+
+/// # Safety
+///
+/// The write goes through if the CPU accepts it, so the value must not break anything the kernel relies on.
+pub unsafe fn write_msr(msr: u32, value: u64) -> bool {
+    msr_write_checked(msr, value)
+}
+
+// Where the #GP handler resumes if the fault came from one of the guarded instructions
+pub fn fixup(rip: u64) -> Option<u64> {
+    let guarded = [ptr::addr_of!(MSR_READ_AT) as u64, ptr::addr_of!(MSR_WRITE_AT) as u64];
+    guarded.contains(&rip).then_some(ptr::addr_of!(MSR_FIXUP) as u64)
+}
+
+// Degrees Celsius from the digital thermal sensor, which reads as the distance below TjMax
+pub fn temperature() -> Option<u32> {
+    let info = unsafe { INFO.as_ref()? };
+    if info.vendor() != "GenuineIntel" || !info.has(Feature::ThermalSensor) { return None; }
+
+    // Both MSRs are model specific and may be missing under a hypervisor, TjMax then falls back to 100 as well
+    let status = read_msr(THERM_STATUS)?;
+    if status & 1 << 31 == 0 { return None; }
+
+    let tjmax = match read_msr(TEMPERATURE_TARGET).map_or(0, |target| (target >> 16) as u32 & 0xff) {
+        0     => 100,
+        tjmax => tjmax
+    };
+
+    Some(tjmax.saturating_sub((status >> 16) as u32 & 0x7f))
+}
+
+pub fn register_commands() {
+    shell::register(Command { name: "cpu", usage: "cpu", help: "show the processor and its features", run: cpu });
+}
+
+fn cpu(args: &mut Args, out: &mut dyn Write) -> Result<(), Error> {
+    args.end()?;
+
+    let info = unsafe { INFO.as_ref().ok_or(anyhow!("CPU is not initialized"))? };
+    writeln!(out, "{} {}", info.vendor(), info.brand())?;
+    writeln!(out, "family 0x{:x} model 0x{:x} stepping {} apic {}", info.family, info.model, info.stepping, apic_id())?;
+    writeln!(out, "{}", info.features)?;
+
+    if let Some(celsius) = temperature() {
+        writeln!(out, "{celsius} C")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use super::*;
+    use alloc::collections::BTreeMap;
+    use alloc::string::ToString;
+
+    struct Dump(BTreeMap<u32, [u32; 4]>);
+
+    impl Cpuid for Dump {
+        fn cpuid(&self, leaf: u32, _: u32) -> [u32; 4] {
+            self.0.get(&leaf).copied().unwrap_or([0; 4])
+        }
+    }
+
+    fn regs(s: &[u8; 16]) -> [u32; 4] {
+        core::array::from_fn(|i| u32::from_le_bytes(s[i * 4..][..4].try_into().unwrap()))
+    }
+
+    // A Skylake client part, trimmed to the leaves that are decoded
+    fn skylake() -> Dump {
+        Dump(BTreeMap::from([
+            (0x0,        [0x16, 0x756e6547, 0x6c65746e, 0x49656e69]),
+            (0x1,        [0x000506e3, 0x00100800, 0x7ffafbbf, 0xbfebfbff]),
+            (0x6,        [0x000027f7, 0x2, 0x9, 0x0]),
+            (0x7,        [0x0, 0x029c6fbf, 0x0, 0x0]),
+            (0x80000000, [0x80000008, 0, 0, 0]),
+            (0x80000001, [0x0, 0x0, 0x121, 0x2c100800]),
+            (0x80000002, regs(b"Intel(R) Core(TM")),
+            (0x80000003, regs(b") i7-6700 CPU @ ")),
+            (0x80000004, regs(b"3.40GHz\0\0\0\0\0\0\0\0\0")),
+            (0x80000007, [0, 0, 0, 0x100])
+        ]))
+    }
+
+    #[test_case]
+    fn decodes_vendor_brand_and_signature() {
+        let info = Info::decode(&skylake());
+
+        assert_eq!(info.vendor(), "GenuineIntel");
+        assert_eq!(info.brand(), "Intel(R) Core(TM) i7-6700 CPU @ 3.40GHz");
+        assert_eq!((info.family, info.model, info.stepping), (6, 0x5e, 3));
+    }
+
+    #[test_case]
+    fn decodes_features() {
+        let info = Info::decode(&skylake());
+
+        for feature in [Feature::Sse42, Feature::Avx2, Feature::Smep, Feature::Smap, Feature::Pages1g, Feature::InvariantTsc] {
+            assert!(info.has(feature), "{}", feature.name());
+        }
+        assert!(!info.has(Feature::Avx512f));
+        assert!(info.features.missing(&Feature::REQUIRED).is_empty());
+    }
+
+    #[test_case]
+    fn leaves_beyond_the_maximum_are_ignored() {
+        let mut dump = skylake();
+        dump.0.insert(0x0, [0x1, 0x756e6547, 0x6c65746e, 0x49656e69]);
+        dump.0.insert(0x80000000, [0x80000001, 0, 0, 0]);
+
+        let info = Info::decode(&dump);
+        assert!(!info.has(Feature::Smep));
+        assert!(!info.has(Feature::InvariantTsc));
+        assert_eq!(info.brand(), "");
+    }
+
+    #[test_case]
+    fn missing_features_are_named() {
+        let mut features = Features::default();
+        features.insert(Feature::Sse);
+
+        assert_eq!(features.missing(&[Feature::Sse, Feature::Nx, Feature::Syscall]).to_string(), "syscall nx");
+    }
+}
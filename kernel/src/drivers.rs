@@ -1,2 +1,4 @@
+pub mod console;
 pub mod keyboard;
+pub mod serial;
 pub mod video;
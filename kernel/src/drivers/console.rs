@@ -0,0 +1,65 @@
+use core::fmt::{self, Arguments, Write};
+
+use crate::drivers::serial::{Serial, COM1};
+use crate::drivers::video;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Output {
+    Framebuffer,
+    Serial,
+    Both
+}
+
+static mut OUTPUT: Output = Output::Framebuffer;
+static mut SERIAL: Serial = Serial::new(COM1);
+
+pub fn init(output: Output) {
+    unsafe {
+        if output != Output::Framebuffer {
+            SERIAL.init();
+        }
+
+        OUTPUT = output;
+    }
+}
+
+pub fn output() -> Output {
+    unsafe { OUTPUT }
+}
+
+pub fn serial() -> &'static mut Serial {
+    unsafe { &mut SERIAL }
+}
+
+pub fn _print(args: Arguments) {
+    let output = output();
+
+    if output != Output::Serial {
+        video::_print(args);
+    }
+
+    if output != Output::Framebuffer {
+        let _ = serial().write_fmt(args);
+    }
+}
+
+// For code that formats into a Write, wherever the console currently goes
+pub struct Console;
+
+impl Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        _print(format_args!("{s}"));
+        Ok(())
+    }
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::drivers::console::_print(core::format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::drivers::console::_print(core::format_args!("{}{}", core::format_args!($($arg)*), "\n")));
+}
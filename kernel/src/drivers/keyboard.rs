@@ -1,40 +1,113 @@
+use core::pin::Pin;
+use core::task::{Context, Poll};
 use anyhow::{anyhow, Result};
+use log::warn;
+use crossbeam_queue::ArrayQueue;
+use futures_util::Stream;
+use futures_util::task::AtomicWaker;
 use pc_keyboard::{ScancodeSet, ScancodeSet1, EventDecoder, HandleControl, DecodedKey};
 use pc_keyboard::layouts::Us104Key;
-use x86_64::instructions::port::Port;
 
-pub struct Keyboard {
+use crate::hal::{PortIo, Ports};
+
+const QUEUE_SIZE: usize = 128;
+
+static mut SCANCODES: Option<ArrayQueue<u8>> = None;
+static WAKER: AtomicWaker = AtomicWaker::new();
+static mut KEYBOARD: Option<Keyboard> = None;
+
+pub fn init() {
+    unsafe {
+        SCANCODES = Some(ArrayQueue::new(QUEUE_SIZE));
+    }
+}
+
+// Every reader goes through the one decoder, several would split the scancodes and the modifier state between them
+pub fn get() -> &'static mut Keyboard {
+    unsafe { KEYBOARD.get_or_insert_with(Keyboard::new) }
+}
+
+pub fn push_scancode(scancode: u8) {
+    let Some(queue) = (unsafe { SCANCODES.as_ref() }) else { return; };
+
+    // Printing from here could deadlock the console, so overflowing input is dropped silently
+    if queue.push(scancode).is_ok() {
+        WAKER.wake();
+    }
+}
+
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+
+pub struct Keyboard<P: PortIo = Ports> {
     scancode_set:  ScancodeSet1,
     event_decoder: EventDecoder<Us104Key>,
-    status_port:   Port<u8>,
-    data_port:     Port<u8>
+    io:            P
 }
 
 impl Keyboard {
     pub fn new() -> Keyboard {
+        Keyboard::with_io(Ports)
+    }
+}
+
+impl Default for Keyboard {
+    fn default() -> Keyboard {
+        Keyboard::new()
+    }
+}
+
+impl<P: PortIo> Keyboard<P> {
+    pub fn with_io(io: P) -> Keyboard<P> {
         Keyboard {
             scancode_set:  ScancodeSet1::new(),
             event_decoder: EventDecoder::new(Us104Key, HandleControl::Ignore),
-            status_port:   Port::new(0x64),
-            data_port:     Port::new(0x60)
+            io
         }
     }
 
-    pub fn read_char(&mut self) -> Result<Option<char>> {
-        let data = unsafe {
-            let status = self.status_port.read();
-            if status & 1 == 0 { return Ok(None); }
+    fn read_scancode(&mut self) -> Option<u8> {
+        // Once the interrupt handler owns the controller, the data port must not be polled
+        if let Some(queue) = unsafe { SCANCODES.as_ref() } {
+            return queue.pop();
+        }
 
-            self.data_port.read()
-        };
+        unsafe {
+            let status = self.io.read_u8(STATUS_PORT);
+            if status & 1 == 0 { return None; }
+
+            Some(self.io.read_u8(DATA_PORT))
+        }
+    }
 
+    fn decode(&mut self, data: u8) -> Result<Option<DecodedKey>> {
         let Some(event) = self.scancode_set.advance_state(data).map_err(|e| anyhow!("{e:?}"))? else {
             return Ok(None);
         };
-        
+
+        Ok(self.event_decoder.process_keyevent(event))
+    }
+
+    fn next_decoded(&mut self) -> Option<DecodedKey> {
+        while let Some(data) = self.read_scancode() {
+            match self.decode(data) {
+                Ok(Some(key)) => return Some(key),
+                Ok(None)      => {}
+                Err(e)        => warn!("{e}")
+            }
+        }
+
+        None
+    }
+
+    pub fn read_key(&mut self) -> Result<Option<DecodedKey>> {
+        let Some(data) = self.read_scancode() else { return Ok(None); };
+        self.decode(data)
+    }
+
+    pub fn read_char(&mut self) -> Result<Option<char>> {
         Ok(
-            self.event_decoder
-                .process_keyevent(event)
+            self.read_key()?
                 .and_then(|x| {
                     match x {
                         DecodedKey::Unicode(x) => Some(x),
@@ -44,3 +117,63 @@ impl Keyboard {
         )
     }
 }
+
+impl<P: PortIo + Unpin> Stream for Keyboard<P> {
+    type Item = DecodedKey;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<DecodedKey>> {
+        let this = self.get_mut();
+
+        if let Some(key) = this.next_decoded() {
+            return Poll::Ready(Some(key));
+        }
+
+        WAKER.register(cx.waker());
+
+        // Check again in case a scancode arrived before the waker was registered
+        match this.next_decoded() {
+            Some(key) => {
+                WAKER.take();
+                Poll::Ready(Some(key))
+            }
+            None => Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::mock::MockPorts;
+
+    fn keyboard(scancodes: &[u32]) -> Keyboard<MockPorts> {
+        let mut io = MockPorts::default();
+        io.queue(STATUS_PORT, &[1].repeat(scancodes.len()));
+        io.queue(DATA_PORT, scancodes);
+        Keyboard::with_io(io)
+    }
+
+    fn chars(keyboard: &mut Keyboard<MockPorts>) -> [Option<char>; 4] {
+        core::array::from_fn(|_| keyboard.read_char().unwrap())
+    }
+
+    #[test_case]
+    fn decodes_make_codes() {
+        // A down, A up, B down, B up
+        let mut kb = keyboard(&[0x1e, 0x9e, 0x30, 0xb0]);
+        assert_eq!(chars(&mut kb), [Some('a'), None, Some('b'), None]);
+    }
+
+    #[test_case]
+    fn shift_selects_upper_case() {
+        // Left shift down, A down, A up, left shift up
+        let mut kb = keyboard(&[0x2a, 0x1e, 0x9e, 0xaa]);
+        assert_eq!(chars(&mut kb), [None, Some('A'), None, None]);
+    }
+
+    #[test_case]
+    fn empty_controller_yields_nothing() {
+        let mut kb = keyboard(&[]);
+        assert_eq!(kb.read_key().unwrap(), None);
+    }
+}
@@ -0,0 +1,129 @@
+use core::fmt::{self, Write};
+
+use crate::hal::{PortIo, Ports};
+
+pub const COM1: u16 = 0x3f8;
+
+const DATA: u16 = 0;
+const INT_ENABLE: u16 = 1;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+
+const LINE_STATUS_THR_EMPTY: u8 = 1 << 5;
+const LINE_STATUS_DATA_READY: u8 = 1 << 0;
+
+pub struct Serial<P: PortIo = Ports> {
+    base: u16,
+    io:   P
+}
+
+impl Serial {
+    pub const fn new(base: u16) -> Serial {
+        Serial { base, io: Ports }
+    }
+}
+
+impl<P: PortIo> Serial<P> {
+    pub fn with_io(base: u16, io: P) -> Serial<P> {
+        Serial { base, io }
+    }
+
+    pub fn io(&self) -> &P {
+        &self.io
+    }
+
+    pub fn init(&mut self) {
+        unsafe {
+            self.io.write_u8(self.base + INT_ENABLE, 0x00);
+
+            // Divisor 1 gives 115200 baud
+            self.io.write_u8(self.base + LINE_CONTROL, 0x80);
+            self.io.write_u8(self.base + DATA, 0x01);
+            self.io.write_u8(self.base + INT_ENABLE, 0x00);
+
+            // 8N1, FIFO enabled and cleared, DTR/RTS/OUT2 set
+            self.io.write_u8(self.base + LINE_CONTROL, 0x03);
+            self.io.write_u8(self.base + FIFO_CONTROL, 0xc7);
+            self.io.write_u8(self.base + MODEM_CONTROL, 0x0b);
+        }
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        unsafe {
+            while self.io.read_u8(self.base + LINE_STATUS) & LINE_STATUS_THR_EMPTY == 0 {
+                core::hint::spin_loop();
+            }
+
+            self.io.write_u8(self.base + DATA, byte);
+        }
+    }
+
+    pub fn read_byte(&mut self) -> Option<u8> {
+        unsafe {
+            if self.io.read_u8(self.base + LINE_STATUS) & LINE_STATUS_DATA_READY == 0 { return None; }
+            Some(self.io.read_u8(self.base + DATA))
+        }
+    }
+}
+
+impl<P: PortIo> Write for Serial<P> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if byte == b'\n' { self.write_byte(b'\r'); }
+            self.write_byte(byte);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::mock::MockPorts;
+
+    fn serial() -> Serial<MockPorts> {
+        let mut io = MockPorts::default();
+        io.fallback.insert(COM1 + LINE_STATUS, LINE_STATUS_THR_EMPTY as u32);
+        Serial::with_io(COM1, io)
+    }
+
+    #[test_case]
+    fn newlines_become_crlf() {
+        let mut serial = serial();
+        serial.write_str("a\nb").unwrap();
+
+        assert_eq!(serial.io().written(COM1 + DATA), b"a\r\nb".map(u32::from));
+    }
+
+    #[test_case]
+    fn waits_for_transmitter() {
+        let mut serial = serial();
+        serial.io.queue(COM1 + LINE_STATUS, &[0, 0]);
+        serial.write_byte(b'x');
+
+        assert!(serial.io().reads[&(COM1 + LINE_STATUS)].is_empty());
+        assert_eq!(serial.io().written(COM1 + DATA), [b'x' as u32]);
+    }
+
+    #[test_case]
+    fn read_needs_data_ready() {
+        let mut serial = serial();
+        assert_eq!(serial.read_byte(), None);
+
+        serial.io.queue(COM1 + LINE_STATUS, &[LINE_STATUS_DATA_READY as u32]);
+        serial.io.queue(COM1 + DATA, &[b'k' as u32]);
+        assert_eq!(serial.read_byte(), Some(b'k'));
+    }
+
+    #[test_case]
+    fn init_sets_115200_8n1() {
+        let mut serial = serial();
+        serial.init();
+
+        assert_eq!(serial.io().written(COM1 + LINE_CONTROL), [0x80, 0x03]);
+        assert_eq!(serial.io().written(COM1 + DATA), [0x01]);
+    }
+}
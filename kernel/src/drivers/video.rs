@@ -1,6 +1,9 @@
 pub mod framebuffer;
+pub mod graphics;
+pub mod image;
 pub mod printer;
 pub mod fonts;
+pub mod splash;
 
 use core::fmt::{Arguments, Write};
 
@@ -17,20 +20,15 @@ impl Printer<'static> {
     }
 }
 
+pub fn printer() -> Option<&'static mut Printer<'static>> {
+    unsafe { PRINTER.as_mut() }
+}
+
 pub fn _print(args: Arguments) {
     unsafe {
-        let printer = PRINTER.as_mut().unwrap();
+        // Nothing can be shown before the printer exists, or over the splash screen
+        let Some(printer) = PRINTER.as_mut() else { return; };
+        if splash::active() { return; }
         printer.write_fmt(args).unwrap();
     }
 }
-
-#[macro_export]
-macro_rules! print {
-    ($($arg:tt)*) => ($crate::drivers::video::_print(core::format_args!($($arg)*)));
-}
-
-#[macro_export]
-macro_rules! println {
-    () => ($crate::print!("\n"));
-    ($($arg:tt)*) => ($crate::drivers::video::_print(core::format_args!("{}{}", core::format_args!($($arg)*), "\n")));
-}
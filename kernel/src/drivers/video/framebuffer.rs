@@ -1,12 +1,83 @@
-pub const HRES: usize = 1920;
-pub const VRES: usize = 1080;
+use core::marker::PhantomData;
+use core::{mem, ptr, slice};
+
+use crate::fpu;
 
 #[repr(C, align(4))]
-#[derive(Clone, Copy, PartialOrd, PartialEq, Eq, Ord)]
+#[derive(Clone, Copy, Debug, PartialOrd, PartialEq, Eq, Ord)]
 pub struct Pixel {
     pub blue:  u8,
     pub green: u8,
     pub red:   u8
 }
 
-pub type Framebuffer<'a> = &'a mut [[Pixel; HRES]; VRES];
+#[repr(C)]
+pub struct Framebuffer<'a> {
+    base:       *mut Pixel,
+    pub width:  usize,
+    pub height: usize,
+    pub stride: usize,
+    _marker:    PhantomData<&'a mut Pixel>
+}
+
+impl<'a> Framebuffer<'a> {
+    /// # Safety
+    ///
+    /// `base` must point to `stride * height` pixels that stay mapped and are written by nothing else for `'a`.
+    pub unsafe fn new(base: *mut Pixel, width: usize, height: usize, stride: usize) -> Framebuffer<'a> {
+        Framebuffer { base, width, height, stride, _marker: PhantomData }
+    }
+
+    pub fn empty() -> Framebuffer<'a> {
+        Framebuffer { base: ptr::null_mut(), width: 0, height: 0, stride: 0, _marker: PhantomData }
+    }
+
+    pub fn take(&mut self) -> Framebuffer<'a> {
+        mem::replace(self, Framebuffer::empty())
+    }
+
+    pub fn row(&self, y: usize) -> &[Pixel] {
+        assert!(y < self.height, "Row {y} out of bounds");
+        unsafe { slice::from_raw_parts(self.base.add(y * self.stride), self.width) }
+    }
+
+    pub fn row_mut(&mut self, y: usize) -> &mut [Pixel] {
+        assert!(y < self.height, "Row {y} out of bounds");
+        unsafe { slice::from_raw_parts_mut(self.base.add(y * self.stride), self.width) }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<Pixel> {
+        if x >= self.width || y >= self.height { return None; }
+        Some(self.row(y)[x])
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, pixel: Pixel) {
+        if x >= self.width || y >= self.height { return; }
+        self.row_mut(y)[x] = pixel;
+    }
+
+    // Clipped to the screen, so callers can pass rectangles that hang off the edges
+    pub fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, pixel: Pixel) {
+        let x_end = x.saturating_add(width).min(self.width);
+        let y_end = y.saturating_add(height).min(self.height);
+        if x >= x_end { return; }
+
+        for y in y..y_end {
+            self.row_mut(y)[x..x_end].fill(pixel);
+        }
+    }
+
+    pub fn scroll_up(&mut self, rows: usize, background: Pixel) {
+        let rows = rows.min(self.height);
+        if rows == 0 { return; }
+
+        // Rows never overlap each other, the stride is at least the width
+        let fpu = fpu::begin();
+        for y in rows..self.height {
+            unsafe { fpu.copy(self.base.add((y - rows) * self.stride), self.base.add(y * self.stride), self.width); }
+        }
+        drop(fpu);
+
+        self.fill(0, self.height - rows, self.width, rows, background);
+    }
+}
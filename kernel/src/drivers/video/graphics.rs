@@ -0,0 +1,505 @@
+extern crate alloc;
+
+use core::mem;
+use alloc::vec;
+use alloc::vec::Vec;
+use anyhow::{anyhow, Result};
+
+use crate::drivers::video::framebuffer::{Framebuffer, Pixel};
+use crate::fpu;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rgba {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8
+}
+
+impl Rgba {
+    pub const fn new(r: u8, g: u8, b: u8, a: u8) -> Rgba {
+        Rgba { r, g, b, a }
+    }
+
+    pub const fn opaque(r: u8, g: u8, b: u8) -> Rgba {
+        Rgba { r, g, b, a: 255 }
+    }
+
+    // Source over, the framebuffer itself has no alpha
+    pub fn over(self, dst: Pixel) -> Pixel {
+        let mix = |src: u8, dst: u8| ((src as u32 * self.a as u32 + dst as u32 * (255 - self.a as u32) + 127) / 255) as u8;
+
+        match self.a {
+            255 => Pixel { red: self.r, green: self.g, blue: self.b },
+            0   => dst,
+            _   => Pixel { red: mix(self.r, dst.red), green: mix(self.g, dst.green), blue: mix(self.b, dst.blue) }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x:      isize,
+    pub y:      isize,
+    pub width:  usize,
+    pub height: usize
+}
+
+impl Rect {
+    pub const fn new(x: isize, y: isize, width: usize, height: usize) -> Rect {
+        Rect { x, y, width, height }
+    }
+
+    // The smallest rectangle holding both corners, inclusive
+    fn spanning((x0, y0): (isize, isize), (x1, y1): (isize, isize)) -> Rect {
+        Rect::new(x0.min(x1), y0.min(y1), x0.abs_diff(x1).saturating_add(1), y0.abs_diff(y1).saturating_add(1))
+    }
+
+    // Saturating, a rectangle reaching past isize::MAX is cut off there
+    pub fn right(&self) -> isize {
+        self.x.saturating_add_unsigned(self.width)
+    }
+
+    pub fn bottom(&self) -> isize {
+        self.y.saturating_add_unsigned(self.height)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    pub fn contains(&self, x: isize, y: isize) -> bool {
+        self.x <= x && x < self.right() && self.y <= y && y < self.bottom()
+    }
+
+    pub fn intersect(&self, other: &Rect) -> Rect {
+        let (x, y) = (self.x.max(other.x), self.y.max(other.y));
+        let (right, bottom) = (self.right().min(other.right()), self.bottom().min(other.bottom()));
+
+        if right <= x || bottom <= y { return Rect::new(x, y, 0, 0); }
+        Rect::new(x, y, (right - x) as usize, (bottom - y) as usize)
+    }
+
+    // Bounding box of the two, empty rectangles do not stretch it
+    pub fn union(&self, other: &Rect) -> Rect {
+        if self.is_empty() { return *other; }
+        if other.is_empty() { return *self; }
+
+        let (x, y) = (self.x.min(other.x), self.y.min(other.y));
+        Rect::new(x, y, self.right().max(other.right()).abs_diff(x), self.bottom().max(other.bottom()).abs_diff(y))
+    }
+}
+
+pub struct Image<'a> {
+    pub width:  usize,
+    pub height: usize,
+    pub pixels: &'a [Rgba]
+}
+
+impl<'a> Image<'a> {
+    pub fn new(width: usize, height: usize, pixels: &'a [Rgba]) -> Result<Image<'a>> {
+        if width.checked_mul(height) != Some(pixels.len()) {
+            return Err(anyhow!("Image is {width}x{height} but has {} pixels", pixels.len()));
+        }
+
+        Ok(Image { width, height, pixels })
+    }
+}
+
+// Draws onto a framebuffer, never outside the clip rectangle, and remembers what it touched
+pub struct Canvas<'a, 'b> {
+    fb:    &'b mut Framebuffer<'a>,
+    clip:  Rect,
+    dirty: Rect
+}
+
+impl<'a, 'b> Canvas<'a, 'b> {
+    pub fn new(fb: &'b mut Framebuffer<'a>) -> Canvas<'a, 'b> {
+        let screen = Rect::new(0, 0, fb.width, fb.height);
+        Canvas { fb, clip: screen, dirty: Rect::new(0, 0, 0, 0) }
+    }
+
+    pub fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.fb.width, self.fb.height)
+    }
+
+    pub fn clip(&self) -> Rect {
+        self.clip
+    }
+
+    // Cut down to the screen, returns the previous one so it can be put back
+    pub fn set_clip(&mut self, clip: Rect) -> Rect {
+        let clip = clip.intersect(&self.bounds());
+        mem::replace(&mut self.clip, clip)
+    }
+
+    pub fn dirty(&self) -> Rect {
+        self.dirty
+    }
+
+    fn damage(&mut self, rect: Rect) {
+        self.dirty = self.dirty.union(&rect.intersect(&self.clip));
+    }
+
+    fn plot(&mut self, x: isize, y: isize, color: Rgba) {
+        if !self.clip.contains(x, y) { return; }
+
+        let (x, y) = (x as usize, y as usize);
+        let old = self.fb.row(y)[x];
+        self.fb.row_mut(y)[x] = color.over(old);
+    }
+
+    fn span(&mut self, x0: isize, x1: isize, y: isize, color: Rgba) {
+        if x1 < x0 { return; }
+
+        let span = Rect::new(x0, y, x1.abs_diff(x0).saturating_add(1), 1).intersect(&self.clip);
+        if span.is_empty() { return; }
+
+        let row = &mut self.fb.row_mut(span.y as usize)[span.x as usize..span.right() as usize];
+        for pixel in row { *pixel = color.over(*pixel); }
+    }
+
+    pub fn pixel(&mut self, x: isize, y: isize, color: Rgba) {
+        self.plot(x, y, color);
+        self.damage(Rect::new(x, y, 1, 1));
+    }
+
+    // Cohen-Sutherland, the part of the line inside the clip rectangle or nothing. The ends are moved in
+    // 128-bit arithmetic, coordinates from anywhere in isize do not fit a product of two differences
+    fn clip_line(&self, from: (isize, isize), to: (isize, isize)) -> Option<((isize, isize), (isize, isize))> {
+        if self.clip.is_empty() { return None; }
+
+        let (left, top) = (self.clip.x as i128, self.clip.y as i128);
+        let (right, bottom) = (self.clip.right() as i128 - 1, self.clip.bottom() as i128 - 1);
+        let outside = |(x, y): (i128, i128)| (x < left) as u8 | ((x > right) as u8) << 1 | ((y < top) as u8) << 2 | ((y > bottom) as u8) << 3;
+
+        let mut ends = [(from.0 as i128, from.1 as i128), (to.0 as i128, to.1 as i128)];
+
+        loop {
+            let codes = ends.map(outside);
+            if codes[0] | codes[1] == 0 { break; }
+            if codes[0] & codes[1] != 0 { return None; }
+
+            // The end outside moves onto the edge it crosses, staying on the line
+            let i = if codes[0] != 0 { 0 } else { 1 };
+            let ((x0, y0), (x1, y1)) = (ends[i], ends[1 - i]);
+            let along_x = |x: i128| (x, y0 + mul_div(y1 - y0, x - x0, x1 - x0));
+            let along_y = |y: i128| (x0 + mul_div(x1 - x0, y - y0, y1 - y0), y);
+
+            ends[i] = match codes[i] {
+                code if code & 1 != 0 => along_x(left),
+                code if code & 2 != 0 => along_x(right),
+                code if code & 4 != 0 => along_y(top),
+                _                     => along_y(bottom)
+            };
+        }
+
+        let [(x0, y0), (x1, y1)] = ends;
+        Some(((x0 as isize, y0 as isize), (x1 as isize, y1 as isize)))
+    }
+
+    // Bresenham, both ends included
+    pub fn line(&mut self, from: (isize, isize), to: (isize, isize), color: Rgba) {
+        let Some(((mut x, mut y), (x1, y1))) = self.clip_line(from, to) else { return; };
+        self.damage(Rect::spanning((x, y), (x1, y1)));
+
+        let (dx, dy) = ((x1 - x).abs(), -(y1 - y).abs());
+        let (sx, sy) = ((x1 - x).signum(), (y1 - y).signum());
+        let mut error = dx + dy;
+
+        loop {
+            self.plot(x, y, color);
+            if x == x1 && y == y1 { break; }
+
+            let e2 = 2 * error;
+            if e2 >= dy { error += dy; x += sx; }
+            if e2 <= dx { error += dx; y += sy; }
+        }
+    }
+
+    pub fn rect(&mut self, rect: Rect, color: Rgba) {
+        if rect.is_empty() { return; }
+        self.damage(rect);
+
+        let (right, bottom) = (rect.right() - 1, rect.bottom() - 1);
+        self.span(rect.x, right, rect.y, color);
+        if bottom > rect.y { self.span(rect.x, right, bottom, color); }
+
+        // Only the rows inside the clip, the sides of a huge rectangle are mostly off screen
+        for y in rect.y.saturating_add(1).max(self.clip.y)..bottom.min(self.clip.bottom()) {
+            self.plot(rect.x, y, color);
+            if right > rect.x { self.plot(right, y, color); }
+        }
+    }
+
+    pub fn fill_rect(&mut self, rect: Rect, color: Rgba) {
+        let area = rect.intersect(&self.clip);
+        if area.is_empty() { return; }
+        self.damage(area);
+
+        if color.a == 255 {
+            self.fb.fill(area.x as usize, area.y as usize, area.width, area.height, color.over(Pixel { red: 0, green: 0, blue: 0 }));
+            return;
+        }
+
+        for y in area.y..area.bottom() {
+            self.span(area.x, area.right() - 1, y, color);
+        }
+    }
+
+    // The bounding box of a circle cut down to the clip, none when the two do not meet
+    fn circle_bounds(&self, (cx, cy): (isize, isize), radius: usize) -> Option<Rect> {
+        let (cx, cy, radius) = (cx as i128, cy as i128, radius as i128);
+        let clip = self.clip;
+
+        let (left, top) = ((cx - radius).max(clip.x as i128), (cy - radius).max(clip.y as i128));
+        let (right, bottom) = ((cx + radius + 1).min(clip.right() as i128), (cy + radius + 1).min(clip.bottom() as i128));
+        if right <= left || bottom <= top { return None; }
+
+        Some(Rect::new(left as isize, top as isize, (right - left) as usize, (bottom - top) as usize))
+    }
+
+    // Spans of a circle may reach far past the screen, they are cut to just outside the clip first
+    fn circle_span(&mut self, cx: isize, (x0, x1): (i128, i128), y: isize, color: Rgba) {
+        let clamp = |x: i128| (cx as i128 + x).clamp(self.clip.x as i128 - 1, self.clip.right() as i128) as isize;
+        self.span(clamp(x0), clamp(x1), y, color);
+    }
+
+    // Row by row and only over the rows in the clip, so the size of the circle does not matter
+    pub fn circle(&mut self, (cx, cy): (isize, isize), radius: usize, color: Rgba) {
+        let Some(area) = self.circle_bounds((cx, cy), radius) else { return; };
+        self.damage(area);
+
+        for y in area.y..area.bottom() {
+            let dy = (y as i128 - cy as i128).unsigned_abs();
+            let Some(outer) = half_width(radius, dy) else { continue; };
+
+            // Pixels of the disc with a neighbour outside it, further out in the row or in the next row out
+            let inner = half_width(radius, dy + 1).map_or(0, |width| (width + 1).min(outer));
+            let (outer, inner) = (outer as i128, inner as i128);
+
+            if inner == 0 {
+                self.circle_span(cx, (-outer, outer), y, color);
+            } else {
+                self.circle_span(cx, (-outer, -inner), y, color);
+                self.circle_span(cx, (inner, outer), y, color);
+            }
+        }
+    }
+
+    pub fn fill_circle(&mut self, (cx, cy): (isize, isize), radius: usize, color: Rgba) {
+        let Some(area) = self.circle_bounds((cx, cy), radius) else { return; };
+        self.damage(area);
+
+        for y in area.y..area.bottom() {
+            let dy = (y as i128 - cy as i128).unsigned_abs();
+            let Some(width) = half_width(radius, dy) else { continue; };
+
+            self.circle_span(cx, (-(width as i128), width as i128), y, color);
+        }
+    }
+
+    pub fn blit(&mut self, image: &Image, x: isize, y: isize) {
+        let area = Rect::new(x, y, image.width, image.height).intersect(&self.clip);
+        if area.is_empty() { return; }
+        self.damage(area);
+
+        for row in area.y..area.bottom() {
+            let src = &image.pixels[row.abs_diff(y) * image.width..][..image.width];
+            let dst = self.fb.row_mut(row as usize);
+
+            for col in area.x..area.right() {
+                let pixel = &mut dst[col as usize];
+                *pixel = src[col.abs_diff(x)].over(*pixel);
+            }
+        }
+    }
+}
+
+// How far a disc reaches either side of its centre in a row dy away, none past its edge. A pixel is inside when
+// x² + dy² <= r² + r, which rounds like the midpoint algorithm and fits a u128 for any usize radius
+fn half_width(radius: usize, dy: u128) -> Option<u128> {
+    let radius = radius as u128;
+    (radius * radius + radius).checked_sub(dy.checked_mul(dy)?).map(u128::isqrt)
+}
+
+// a * b / c rounded towards zero, for |b| <= |c| so the result is no larger than a
+fn mul_div(a: i128, b: i128, c: i128) -> i128 {
+    let magnitude = (a.unsigned_abs() * b.unsigned_abs() / c.unsigned_abs()) as i128;
+    if (a < 0) ^ (b < 0) ^ (c < 0) { -magnitude } else { magnitude }
+}
+
+// Drawing goes here first, only what changed is copied to the screen, so it never shows half a frame
+pub struct BackBuffer {
+    pixels: Vec<Pixel>,
+    width:  usize,
+    height: usize,
+    dirty:  Rect
+}
+
+impl BackBuffer {
+    pub fn new(width: usize, height: usize) -> BackBuffer {
+        let pixels = vec![Pixel { red: 0, green: 0, blue: 0 }; width * height];
+        BackBuffer { pixels, width, height, dirty: Rect::new(0, 0, 0, 0) }
+    }
+
+    pub fn dirty(&self) -> Rect {
+        self.dirty
+    }
+
+    pub fn draw<R>(&mut self, f: impl FnOnce(&mut Canvas) -> R) -> R {
+        let mut fb = unsafe { Framebuffer::new(self.pixels.as_mut_ptr(), self.width, self.height, self.width) };
+        let mut canvas = Canvas::new(&mut fb);
+        let result = f(&mut canvas);
+
+        self.dirty = self.dirty.union(&canvas.dirty());
+        result
+    }
+
+    pub fn flush(&mut self, front: &mut Framebuffer) {
+        let screen = Rect::new(0, 0, front.width.min(self.width), front.height.min(self.height));
+        let area = mem::replace(&mut self.dirty, Rect::new(0, 0, 0, 0)).intersect(&screen);
+        if area.is_empty() { return; }
+
+        let (x, width) = (area.x as usize, area.width);
+        let fpu = fpu::begin();
+
+        for y in area.y as usize..area.bottom() as usize {
+            let src = &self.pixels[y * self.width + x..][..width];
+            let dst = &mut front.row_mut(y)[x..x + width];
+            unsafe { fpu.copy(dst.as_mut_ptr(), src.as_ptr(), width); }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Rgba = Rgba::opaque(255, 255, 255);
+    const BLACK: Pixel = Pixel { red: 0, green: 0, blue: 0 };
+
+    fn lit(fb: &Framebuffer) -> Vec<(usize, usize)> {
+        (0..fb.height).flat_map(|y| (0..fb.width).map(move |x| (x, y))).filter(|&(x, y)| fb.get(x, y) != Some(BLACK)).collect()
+    }
+
+    #[test_case]
+    fn rects_intersect_and_unite() {
+        let a = Rect::new(0, 0, 10, 10);
+        let b = Rect::new(5, -5, 10, 10);
+
+        assert_eq!(a.intersect(&b), Rect::new(5, 0, 5, 5));
+        assert!(a.intersect(&Rect::new(20, 20, 5, 5)).is_empty());
+        assert_eq!(a.union(&b), Rect::new(0, -5, 15, 15));
+        assert_eq!(Rect::new(3, 3, 0, 0).union(&a), a);
+    }
+
+    #[test_case]
+    fn lines_cover_both_ends_and_clip() {
+        let mut pixels = vec![BLACK; 8 * 8];
+        let mut fb = unsafe { Framebuffer::new(pixels.as_mut_ptr(), 8, 8, 8) };
+        let mut canvas = Canvas::new(&mut fb);
+
+        canvas.line((0, 0), (3, 3), WHITE);
+        canvas.line((6, -4), (6, 10), WHITE);
+        assert_eq!(canvas.dirty(), Rect::new(0, 0, 7, 8));
+
+        let lit = lit(&fb);
+        assert!([(0, 0), (1, 1), (2, 2), (3, 3), (6, 0), (6, 7)].iter().all(|p| lit.contains(p)));
+        assert_eq!(lit.len(), 4 + 8);
+    }
+
+    #[test_case]
+    fn far_away_lines_are_clipped_first() {
+        let mut pixels = vec![BLACK; 8 * 8];
+        let mut fb = unsafe { Framebuffer::new(pixels.as_mut_ptr(), 8, 8, 8) };
+        let mut canvas = Canvas::new(&mut fb);
+
+        canvas.line((isize::MIN, isize::MIN), (isize::MAX, isize::MAX), WHITE);
+        canvas.line((isize::MIN, 3), (isize::MAX, 3), WHITE);
+        canvas.line((-100, 50), (50, -100), WHITE);
+        assert_eq!(canvas.dirty(), Rect::new(0, 0, 8, 8));
+
+        let lit = lit(&fb);
+        assert!((0..8).all(|i| lit.contains(&(i, i)) && lit.contains(&(i, 3))));
+        assert_eq!(lit.len(), 8 + 7);
+    }
+
+    #[test_case]
+    fn images_must_match_their_pixels() {
+        let image = [Rgba::new(0, 0, 0, 0); 2];
+
+        assert!(Image::new(2, 1, &image).is_ok());
+        assert!(Image::new(3, 1, &image).is_err());
+        assert!(Image::new(usize::MAX, 2, &image).is_err());
+    }
+
+    #[test_case]
+    fn fills_stay_inside_the_clip() {
+        let mut pixels = vec![BLACK; 8 * 8];
+        let mut fb = unsafe { Framebuffer::new(pixels.as_mut_ptr(), 8, 8, 8) };
+        let mut canvas = Canvas::new(&mut fb);
+
+        canvas.set_clip(Rect::new(2, 2, 4, 4));
+        canvas.fill_rect(Rect::new(0, 0, 8, 8), WHITE);
+        canvas.fill_circle((4, 4), 10, WHITE);
+
+        assert_eq!(lit(&fb).len(), 16);
+        assert!(lit(&fb).iter().all(|&(x, y)| (2..6).contains(&x) && (2..6).contains(&y)));
+    }
+
+    #[test_case]
+    fn circles_are_symmetric() {
+        let mut pixels = vec![BLACK; 11 * 11];
+        let mut fb = unsafe { Framebuffer::new(pixels.as_mut_ptr(), 11, 11, 11) };
+        Canvas::new(&mut fb).circle((5, 5), 4, WHITE);
+
+        let lit = lit(&fb);
+        assert!(lit.contains(&(9, 5)) && lit.contains(&(5, 1)) && !lit.contains(&(5, 5)));
+        assert!(lit.iter().all(|&(x, y)| lit.contains(&(10 - x, y)) && lit.contains(&(y, x))));
+    }
+
+    #[test_case]
+    fn huge_shapes_are_clipped_without_overflowing() {
+        let mut pixels = vec![BLACK; 8 * 8];
+        let mut fb = unsafe { Framebuffer::new(pixels.as_mut_ptr(), 8, 8, 8) };
+        let mut canvas = Canvas::new(&mut fb);
+        let image = [WHITE; 1];
+
+        canvas.rect(Rect::new(isize::MIN, isize::MIN, usize::MAX, usize::MAX), WHITE);
+        canvas.fill_rect(Rect::new(isize::MAX - 1, 0, usize::MAX, 4), WHITE);
+        canvas.blit(&Image::new(1, 1, &image).unwrap(), isize::MIN, 0);
+        canvas.circle((isize::MIN, isize::MAX), usize::MAX, WHITE);
+        assert!(lit(&fb).is_empty());
+
+        Canvas::new(&mut fb).fill_circle((4, 4), usize::MAX, WHITE);
+        assert_eq!(lit(&fb).len(), 64);
+    }
+
+    #[test_case]
+    fn blits_blend_by_alpha() {
+        let mut pixels = vec![Pixel { red: 0, green: 0, blue: 200 }; 4];
+        let mut fb = unsafe { Framebuffer::new(pixels.as_mut_ptr(), 2, 2, 2) };
+        let image = [Rgba::new(255, 0, 0, 128), Rgba::new(0, 0, 0, 0)];
+
+        Canvas::new(&mut fb).blit(&Image::new(2, 1, &image).unwrap(), 0, 1);
+
+        assert_eq!(fb.get(0, 0), Some(Pixel { red: 0, green: 0, blue: 200 }));
+        assert_eq!(fb.get(0, 1), Some(Pixel { red: 128, green: 0, blue: 100 }));
+        assert_eq!(fb.get(1, 1), Some(Pixel { red: 0, green: 0, blue: 200 }));
+    }
+
+    #[test_case]
+    fn back_buffer_flushes_only_what_changed() {
+        let mut screen = vec![BLACK; 8 * 8];
+        let mut front = unsafe { Framebuffer::new(screen.as_mut_ptr(), 8, 8, 8) };
+        let mut back = BackBuffer::new(8, 8);
+
+        back.draw(|canvas| canvas.fill_rect(Rect::new(0, 0, 8, 8), WHITE));
+        back.dirty = Rect::new(1, 1, 2, 2);
+        back.flush(&mut front);
+
+        assert_eq!(lit(&front), [(1, 1), (2, 1), (1, 2), (2, 2)]);
+        assert!(back.dirty().is_empty());
+    }
+}
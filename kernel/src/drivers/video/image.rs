@@ -0,0 +1,96 @@
+pub mod bmp;
+pub mod png;
+pub mod qoi;
+mod inflate;
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use anyhow::{anyhow, Result};
+
+use crate::drivers::video::graphics::{Image, Rgba};
+
+// 4096x4096, anything larger is not a boot splash
+const MAX_PIXELS: usize = 1 << 24;
+
+pub struct Bitmap {
+    pub width:  usize,
+    pub height: usize,
+    pub pixels: Vec<Rgba>
+}
+
+impl Bitmap {
+    fn new(width: usize, height: usize) -> Result<Bitmap> {
+        let count = width.checked_mul(height).filter(|&count| count > 0 && count <= MAX_PIXELS);
+        let count = count.ok_or(anyhow!("Image size {width}x{height} is not supported"))?;
+
+        let mut pixels = Vec::new();
+        pixels.try_reserve_exact(count).map_err(|_| anyhow!("Out of memory for a {width}x{height} image"))?;
+        pixels.resize(count, Rgba::new(0, 0, 0, 0));
+
+        Ok(Bitmap { width, height, pixels })
+    }
+
+    pub fn image(&self) -> Result<Image<'_>> {
+        Image::new(self.width, self.height, &self.pixels)
+    }
+
+    // Nearest neighbour to the largest size that fits, keeping the aspect ratio
+    pub fn fit(&self, width: usize, height: usize) -> Result<Bitmap> {
+        let (w, h) = if self.width * height <= self.height * width {
+            ((self.width * height / self.height).max(1), height)
+        } else {
+            (width, (self.height * width / self.width).max(1))
+        };
+
+        let mut scaled = Bitmap::new(w, h)?;
+        for y in 0..h {
+            let row = &self.pixels[y * self.height / h * self.width..][..self.width];
+
+            for x in 0..w {
+                scaled.pixels[y * w + x] = row[x * self.width / w];
+            }
+        }
+
+        Ok(scaled)
+    }
+}
+
+// Picks the decoder by the magic number in front
+pub fn decode(bytes: &[u8]) -> Result<Bitmap> {
+    if bytes.starts_with(png::MAGIC) {
+        png::decode(bytes)
+    } else if bytes.starts_with(qoi::MAGIC) {
+        qoi::decode(bytes)
+    } else if bytes.starts_with(bmp::MAGIC) {
+        bmp::decode(bytes)
+    } else {
+        Err(anyhow!("Unknown image format"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn fit_keeps_the_aspect_ratio() {
+        let mut bitmap = Bitmap::new(4, 2).unwrap();
+        bitmap.pixels[3] = Rgba::opaque(255, 0, 0);
+
+        let scaled = bitmap.fit(100, 100).unwrap();
+        assert_eq!((scaled.width, scaled.height), (100, 50));
+        assert_eq!(scaled.pixels[99], Rgba::opaque(255, 0, 0));
+        assert_eq!(scaled.pixels[74], Rgba::new(0, 0, 0, 0));
+
+        let scaled = bitmap.fit(2, 10).unwrap();
+        assert_eq!((scaled.width, scaled.height), (2, 1));
+    }
+
+    #[test_case]
+    fn unknown_formats_are_rejected() {
+        assert!(decode(b"GIF89a").is_err());
+        assert!(Bitmap::new(0, 10).is_err());
+        assert!(Bitmap::new(1 << 13, 1 << 13).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+use anyhow::{anyhow, Result};
+
+use crate::drivers::video::graphics::Rgba;
+use crate::drivers::video::image::Bitmap;
+
+pub const MAGIC: &[u8] = b"BM";
+
+const FILE_HEADER:       usize = 14;
+const BI_RGB:            u32 = 0;
+const BI_BITFIELDS:      u32 = 3;
+const BI_ALPHABITFIELDS: u32 = 6;
+
+fn u16_at(bytes: &[u8], offset: usize) -> Result<u16> {
+    let field = bytes.get(offset..offset + 2).ok_or(anyhow!("BMP header is truncated"))?;
+    Ok(u16::from_le_bytes(field.try_into().unwrap()))
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Result<u32> {
+    let field = bytes.get(offset..offset + 4).ok_or(anyhow!("BMP header is truncated"))?;
+    Ok(u32::from_le_bytes(field.try_into().unwrap()))
+}
+
+// Scales whatever the mask selects to 8 bits
+fn channel(value: u32, mask: u32) -> u8 {
+    if mask == 0 { return 0; }
+
+    let bits = mask.count_ones();
+    let value = ((value & mask) >> mask.trailing_zeros()) as u64;
+    (value * 255 / ((1u64 << bits) - 1)) as u8
+}
+
+// Uncompressed 1, 4, 8, 24 and 32 bits per pixel, with or without bit fields
+pub fn decode(bytes: &[u8]) -> Result<Bitmap> {
+    let data = u32_at(bytes, 10)? as usize;
+    let header = u32_at(bytes, FILE_HEADER)? as usize;
+    let width = u32_at(bytes, 18)? as i32;
+    let height = u32_at(bytes, 22)? as i32;
+    let bpp = u16_at(bytes, 28)? as usize;
+    let compression = u32_at(bytes, 30)?;
+
+    let masks = match (compression, bpp) {
+        (BI_RGB, 32) => [0xff0000, 0xff00, 0xff, 0],
+        (BI_BITFIELDS | BI_ALPHABITFIELDS, 16 | 32) => {
+            // Version 3 headers put the masks right after themselves, later versions inside
+            let alpha = compression == BI_ALPHABITFIELDS || header >= 56;
+            [u32_at(bytes, 54)?, u32_at(bytes, 58)?, u32_at(bytes, 62)?, if alpha { u32_at(bytes, 66)? } else { 0 }]
+        }
+        (BI_RGB, 1 | 4 | 8 | 24) => [0; 4],
+        _ => return Err(anyhow!("BMP with compression {compression} at {bpp} bits per pixel is not supported"))
+    };
+
+    let palette = bytes.get(FILE_HEADER + header..).ok_or(anyhow!("BMP header is truncated"))?;
+    let (w, h) = (width.unsigned_abs() as usize, height.unsigned_abs() as usize);
+    // Checks the dimensions before anything is sized by them
+    let mut bitmap = Bitmap::new(w, h)?;
+
+    let stride = w.checked_mul(bpp).map(|bits| bits.div_ceil(32) * 4);
+    let size = stride.and_then(|stride| stride.checked_mul(h)).ok_or(anyhow!("BMP of {w}x{h} at {bpp} bits per pixel is too large"))?;
+    let stride = size / h;
+    let pixels = bytes.get(data..).filter(|pixels| pixels.len() >= size).ok_or(anyhow!("BMP pixel data is truncated"))?;
+
+    for y in 0..h {
+        // Bottom-up unless the height is negative
+        let row = &pixels[if height > 0 { h - 1 - y } else { y } * stride..][..stride];
+
+        for x in 0..w {
+            let pixel = match bpp {
+                1 | 4 | 8 => {
+                    let bit = x * bpp;
+                    let i = (row[bit / 8] >> (8 - bpp - bit % 8)) as usize & ((1 << bpp) - 1);
+                    let entry = palette.get(i * 4..i * 4 + 3).ok_or(anyhow!("BMP palette index {i} is out of range"))?;
+                    Rgba::opaque(entry[2], entry[1], entry[0])
+                }
+                24 => Rgba::opaque(row[x * 3 + 2], row[x * 3 + 1], row[x * 3]),
+                _  => {
+                    let value = if bpp == 16 {
+                        u16::from_le_bytes([row[x * 2], row[x * 2 + 1]]) as u32
+                    } else {
+                        u32::from_le_bytes(row[x * 4..x * 4 + 4].try_into().unwrap())
+                    };
+
+                    let alpha = if masks[3] == 0 { 255 } else { channel(value, masks[3]) };
+                    Rgba::new(channel(value, masks[0]), channel(value, masks[1]), channel(value, masks[2]), alpha)
+                }
+            };
+
+            bitmap.pixels[y * w + x] = pixel;
+        }
+    }
+
+    Ok(bitmap)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn bmp(width: i32, height: i32, bpp: u16, compression: u32, extra: &[u8], pixels: &[u8]) -> Vec<u8> {
+        let data = FILE_HEADER + 40 + extra.len();
+        let mut bytes = Vec::from(MAGIC);
+        bytes.extend_from_slice(&((data + pixels.len()) as u32).to_le_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&(data as u32).to_le_bytes());
+
+        bytes.extend_from_slice(&40u32.to_le_bytes());
+        bytes.extend_from_slice(&width.to_le_bytes());
+        bytes.extend_from_slice(&height.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&bpp.to_le_bytes());
+        bytes.extend_from_slice(&compression.to_le_bytes());
+        bytes.extend_from_slice(&[0; 20]);
+
+        bytes.extend_from_slice(extra);
+        bytes.extend_from_slice(pixels);
+        bytes
+    }
+
+    #[test_case]
+    fn bottom_up_24_bit_rows_are_padded() {
+        // Two rows of two BGR pixels, each padded to eight bytes, the bottom row first
+        let pixels = [0, 0, 255, 0, 255, 0, 0, 0, 255, 0, 0, 255, 255, 255, 0, 0];
+        let bitmap = decode(&bmp(2, 2, 24, BI_RGB, &[], &pixels)).unwrap();
+
+        assert_eq!(bitmap.pixels, [
+            Rgba::opaque(0, 0, 255), Rgba::opaque(255, 255, 255),
+            Rgba::opaque(255, 0, 0), Rgba::opaque(0, 255, 0)
+        ]);
+    }
+
+    #[test_case]
+    fn palettes_and_bit_fields() {
+        let palette = [0, 0, 0, 0, 255, 255, 255, 0];
+        let bitmap = decode(&bmp(3, -1, 1, BI_RGB, &palette, &[0b1010_0000, 0, 0, 0])).unwrap();
+        assert_eq!(bitmap.pixels, [Rgba::opaque(255, 255, 255), Rgba::opaque(0, 0, 0), Rgba::opaque(255, 255, 255)]);
+
+        // RGB565
+        let masks = [0x00, 0xf8, 0, 0, 0xe0, 0x07, 0, 0, 0x1f, 0, 0, 0];
+        let bitmap = decode(&bmp(1, 1, 16, BI_BITFIELDS, &masks, &[0x1f, 0xf8, 0, 0])).unwrap();
+        assert_eq!(bitmap.pixels, [Rgba::opaque(255, 0, 255)]);
+    }
+
+    #[test_case]
+    fn unsupported_and_truncated_files_fail() {
+        assert!(decode(&bmp(1, 1, 8, 1, &[], &[0; 4])).is_err());
+        assert!(decode(&bmp(4, 4, 24, BI_RGB, &[], &[0; 8])).is_err());
+        assert!(decode(&bmp(0, 4, 24, BI_RGB, &[], &[0; 8])).is_err());
+        assert!(decode(&bmp(i32::MIN, i32::MIN, 32, BI_RGB, &[], &[0; 8])).is_err());
+    }
+
+    #[test_case]
+    fn full_width_masks_scale_without_overflow() {
+        assert_eq!(channel(u32::MAX, u32::MAX), 255);
+        assert_eq!(channel(0x8000_0000, 0xffff_0000), 127);
+    }
+}
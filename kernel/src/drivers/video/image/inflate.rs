@@ -0,0 +1,291 @@
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use anyhow::{anyhow, Result};
+
+const LENGTH_BASE:  [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29]  = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE:    [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073,
+    4097, 6145, 8193, 12289, 16385, 24577
+];
+const DIST_EXTRA:   [u8; 30]  = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+// The order code length code lengths come in, most likely first
+const ORDER:        [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+const MAX_BITS:     usize = 15;
+
+// Deflate packs from the least significant bit up
+struct Bits<'a> {
+    data:  &'a [u8],
+    pos:   usize,
+    buf:   u32,
+    count: u32
+}
+
+impl<'a> Bits<'a> {
+    fn new(data: &'a [u8]) -> Bits<'a> {
+        Bits { data, pos: 0, buf: 0, count: 0 }
+    }
+
+    fn bits(&mut self, n: u32) -> Result<u32> {
+        while self.count < n {
+            let byte = *self.data.get(self.pos).ok_or(anyhow!("Deflate stream is truncated"))?;
+            self.buf |= (byte as u32) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+
+        let value = self.buf & ((1 << n) - 1);
+        self.buf >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    // Whatever is left of the current byte
+    fn align(&mut self) {
+        self.buf = 0;
+        self.count = 0;
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos + len).ok_or(anyhow!("Stored block is truncated"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+}
+
+// Canonical code, symbols sorted by code length and then by value
+struct Huffman {
+    counts:  [u16; MAX_BITS + 1],
+    symbols: Vec<u16>
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Huffman> {
+        let mut counts = [0; MAX_BITS + 1];
+        for &len in lengths { counts[len as usize] += 1; }
+
+        // A code may be incomplete, with a single distance for instance, but never oversubscribed
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = left * 2 - count as i32;
+            if left < 0 { return Err(anyhow!("Huffman code is oversubscribed")); }
+        }
+
+        let mut offsets = [0; MAX_BITS + 2];
+        for len in 1..=MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+
+        let mut symbols = vec![0; offsets[MAX_BITS + 1] as usize];
+        for (symbol, &len) in lengths.iter().enumerate().filter(|(_, &len)| len != 0) {
+            symbols[offsets[len as usize] as usize] = symbol as u16;
+            offsets[len as usize] += 1;
+        }
+
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+
+        for &count in &self.counts[1..] {
+            code |= bits.bits(1)? as i32;
+            let count = count as i32;
+
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(anyhow!("Invalid Huffman code"))
+    }
+}
+
+fn fixed() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+
+    (Huffman::new(&lengths).unwrap(), Huffman::new(&[5; 30]).unwrap())
+}
+
+fn dynamic(bits: &mut Bits) -> Result<(Huffman, Huffman)> {
+    let literals = bits.bits(5)? as usize + 257;
+    let distances = bits.bits(5)? as usize + 1;
+    let codes = bits.bits(4)? as usize + 4;
+    if literals > 286 || distances > 30 { return Err(anyhow!("Too many Huffman codes")); }
+
+    let mut lengths = [0u8; 19];
+    for &i in &ORDER[..codes] { lengths[i] = bits.bits(3)? as u8; }
+    let code = Huffman::new(&lengths)?;
+
+    let mut lengths = vec![0u8; literals + distances];
+    let mut i = 0;
+
+    while i < lengths.len() {
+        let (len, repeat) = match code.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths[..i].last().ok_or(anyhow!("Repeat with no previous length"))?;
+                (previous, 3 + bits.bits(2)? as usize)
+            }
+            17 => (0, 3 + bits.bits(3)? as usize),
+            _  => (0, 11 + bits.bits(7)? as usize)
+        };
+
+        if i + repeat > lengths.len() { return Err(anyhow!("Code lengths run past the end")); }
+        lengths[i..i + repeat].fill(len);
+        i += repeat;
+    }
+
+    if lengths[256] == 0 { return Err(anyhow!("No end of block code")); }
+
+    Ok((Huffman::new(&lengths[..literals])?, Huffman::new(&lengths[literals..])?))
+}
+
+fn codes(bits: &mut Bits, out: &mut Vec<u8>, (literals, distances): &(Huffman, Huffman), limit: usize) -> Result<()> {
+    loop {
+        let symbol = literals.decode(bits)? as usize;
+
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256     => return Ok(()),
+            _       => {
+                let i = symbol - 257;
+                if i >= LENGTH_BASE.len() { return Err(anyhow!("Invalid length code {symbol}")); }
+                let len = LENGTH_BASE[i] as usize + bits.bits(LENGTH_EXTRA[i] as u32)? as usize;
+
+                let i = distances.decode(bits)? as usize;
+                if i >= DIST_BASE.len() { return Err(anyhow!("Invalid distance code {i}")); }
+                let distance = DIST_BASE[i] as usize + bits.bits(DIST_EXTRA[i] as u32)? as usize;
+                if distance > out.len() { return Err(anyhow!("Distance {distance} reaches before the start")); }
+
+                // Byte by byte, the copy may overlap what it produces
+                let start = out.len() - distance;
+                for j in 0..len { out.push(out[start + j]); }
+            }
+        }
+
+        if out.len() > limit { return Err(anyhow!("Inflates to more than {limit} bytes")); }
+    }
+}
+
+// Raw deflate as in RFC 1951, refusing to produce more than limit bytes
+pub fn inflate(data: &[u8], limit: usize) -> Result<Vec<u8>> {
+    let mut bits = Bits::new(data);
+    let mut out = Vec::new();
+
+    loop {
+        let last = bits.bits(1)? == 1;
+
+        match bits.bits(2)? {
+            0 => {
+                bits.align();
+                let header = bits.bytes(4)?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                if len != !u16::from_le_bytes([header[2], header[3]]) { return Err(anyhow!("Stored block length is corrupt")); }
+                if out.len() + len as usize > limit { return Err(anyhow!("Inflates to more than {limit} bytes")); }
+
+                out.extend_from_slice(bits.bytes(len as usize)?);
+            }
+            1 => codes(&mut bits, &mut out, &fixed(), limit)?,
+            2 => {
+                let tables = dynamic(&mut bits)?;
+                codes(&mut bits, &mut out, &tables, limit)?;
+            }
+            _ => return Err(anyhow!("Invalid deflate block type"))
+        }
+
+        if last { return Ok(out); }
+    }
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+
+    // 5552 is the most bytes before the sums can overflow
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+
+        a %= 65521;
+        b %= 65521;
+    }
+
+    b << 16 | a
+}
+
+// RFC 1950, a two byte header, deflate data and the Adler-32 of what it inflates to
+pub fn zlib(data: &[u8], limit: usize) -> Result<Vec<u8>> {
+    if data.len() < 6 { return Err(anyhow!("zlib stream is truncated")); }
+
+    let (cmf, flags) = (data[0], data[1]);
+    if cmf & 0xf != 8 || !(cmf as u16 * 256 + flags as u16).is_multiple_of(31) { return Err(anyhow!("Not a zlib stream")); }
+    if flags & 0x20 != 0 { return Err(anyhow!("zlib preset dictionaries are not supported")); }
+
+    let out = inflate(&data[2..data.len() - 4], limit)?;
+    let checksum = u32::from_be_bytes(data[data.len() - 4..].try_into().unwrap());
+    if adler32(&out) != checksum { return Err(anyhow!("zlib checksum mismatch")); }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    #[test_case]
+    fn stored_blocks_are_copied() {
+        let mut stream = vec![0x78, 0x01, 0x01, 5, 0, !5, !0];
+        stream.extend_from_slice(b"hello");
+        stream.extend_from_slice(&adler32(b"hello").to_be_bytes());
+
+        assert_eq!(zlib(&stream, 100).unwrap(), b"hello");
+    }
+
+    #[test_case]
+    fn fixed_codes_decode() {
+        assert_eq!(zlib(&hex("789ccb48cdc9c90700062c0215"), 100).unwrap(), b"hello");
+    }
+
+    // zlib level 9 of an LCG picking from a skewed alphabet, which gets its own Huffman tables
+    #[test_case]
+    fn dynamic_codes_decode() {
+        let stream = hex(concat!(
+            "78da3d508b15c4200c9a15c2fe331c1f7bedd3464a0073008ee725efbca3812c40348277bcfe090f86efdf8496ad21f7a8",
+            "1d56caab0a49e67007c6221a374e1442c443c2513a5415f84be275becab966d9fe229654a32473ad4d499e24f5a6874e94",
+            "dc8d761a7b39f03dac41352ea5e35f2fc7e20a019d48b432b6108784d31145cfd67cd6b7b075f3b01693f7c6b659f107e40c9908"
+        ));
+
+        let mut x = 1u32;
+        let expected: Vec<u8> = (0..400).map(|_| {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            b"aaaaabbbccd"[(x >> 16) as usize % 11]
+        }).collect();
+
+        assert_eq!(zlib(&stream, 1000).unwrap(), expected);
+        assert!(zlib(&stream, 100).is_err());
+    }
+
+    #[test_case]
+    fn corruption_is_caught() {
+        let mut stream = hex("789ccb48cdc9c90700062c0215");
+        *stream.last_mut().unwrap() ^= 1;
+        assert!(zlib(&stream, 100).is_err());
+        assert!(zlib(&stream[..8], 100).is_err());
+    }
+}
@@ -0,0 +1,255 @@
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use anyhow::{anyhow, Result};
+
+use crate::drivers::video::graphics::Rgba;
+use crate::drivers::video::image::{inflate, Bitmap};
+
+pub const MAGIC: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+const GRAY:       u8 = 0;
+const RGB:        u8 = 2;
+const PALETTE:    u8 = 3;
+const GRAY_ALPHA: u8 = 4;
+const RGBA:       u8 = 6;
+
+struct Header {
+    width:  usize,
+    height: usize,
+    depth:  u8,
+    color:  u8
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Result<Header> {
+        if data.len() != 13 { return Err(anyhow!("IHDR is {} bytes", data.len())); }
+
+        let width = u32::from_be_bytes(data[0..4].try_into().unwrap()) as usize;
+        let height = u32::from_be_bytes(data[4..8].try_into().unwrap()) as usize;
+        let (depth, color) = (data[8], data[9]);
+
+        let valid = match color {
+            GRAY                   => matches!(depth, 1 | 2 | 4 | 8 | 16),
+            PALETTE                => matches!(depth, 1 | 2 | 4 | 8),
+            RGB | GRAY_ALPHA | RGBA => matches!(depth, 8 | 16),
+            _                      => false
+        };
+        if !valid { return Err(anyhow!("PNG color type {color} at depth {depth} is invalid")); }
+        if data[12] != 0 { return Err(anyhow!("Interlaced PNGs are not supported")); }
+
+        Ok(Header { width, height, depth, color })
+    }
+
+    fn channels(&self) -> usize {
+        match self.color {
+            RGB        => 3,
+            GRAY_ALPHA => 2,
+            RGBA       => 4,
+            _          => 1
+        }
+    }
+
+    fn bits(&self) -> usize {
+        self.channels() * self.depth as usize
+    }
+
+    fn stride(&self) -> usize {
+        (self.width * self.bits()).div_ceil(8)
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+// Undoes the per row filters in place, each row starts with its filter type
+fn unfilter(data: &mut [u8], stride: usize, bpp: usize) -> Result<()> {
+    let mut previous = vec![0u8; stride];
+
+    for line in data.chunks_exact_mut(stride + 1) {
+        let (filter, row) = line.split_first_mut().unwrap();
+
+        for i in 0..stride {
+            let a = if i >= bpp { row[i - bpp] } else { 0 };
+            let (b, c) = (previous[i], if i >= bpp { previous[i - bpp] } else { 0 });
+
+            row[i] = row[i].wrapping_add(match *filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(anyhow!("Invalid PNG filter type {filter}"))
+            });
+        }
+
+        previous.copy_from_slice(row);
+    }
+
+    Ok(())
+}
+
+// Sample i of a row at its stored depth, 16 bit samples are big endian
+fn sample(row: &[u8], i: usize, depth: u8) -> u16 {
+    match depth {
+        16 => u16::from_be_bytes([row[i * 2], row[i * 2 + 1]]),
+        8  => row[i] as u16,
+        _  => {
+            let bit = i * depth as usize;
+            (row[bit / 8] >> (8 - depth as usize - bit % 8)) as u16 & ((1 << depth) - 1)
+        }
+    }
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Bitmap> {
+    let mut rest = bytes.get(MAGIC.len()..).ok_or(anyhow!("PNG is truncated"))?;
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut transparency: &[u8] = &[];
+    let mut compressed = Vec::new();
+
+    // CRCs are not checked, the zlib checksum covers the pixels
+    loop {
+        if rest.len() < 12 { return Err(anyhow!("PNG is truncated")); }
+
+        let len = u32::from_be_bytes(rest[0..4].try_into().unwrap()) as usize;
+        let kind = &rest[4..8];
+        let data = rest.get(8..8 + len).ok_or(anyhow!("PNG chunk is truncated"))?;
+        rest = rest.get(12 + len..).ok_or(anyhow!("PNG chunk is truncated"))?;
+
+        if header.is_none() && kind != b"IHDR" { return Err(anyhow!("PNG does not start with IHDR")); }
+
+        match kind {
+            b"IHDR" => header = Some(Header::parse(data)?),
+            b"PLTE" => palette = data,
+            b"tRNS" => transparency = data,
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => break,
+            // Ancillary chunks have a lowercase first letter and can be skipped
+            _ if kind[0].is_ascii_uppercase() => return Err(anyhow!("Unknown critical PNG chunk {:?}", core::str::from_utf8(kind))),
+            _ => {}
+        }
+    }
+
+    let header = header.unwrap();
+    let mut bitmap = Bitmap::new(header.width, header.height)?;
+    let stride = header.stride();
+
+    let mut data = inflate::zlib(&compressed, (stride + 1) * header.height)?;
+    if data.len() != (stride + 1) * header.height { return Err(anyhow!("PNG image data is {} bytes short", (stride + 1) * header.height - data.len())); }
+    unfilter(&mut data, stride, header.bits().div_ceil(8))?;
+
+    let depth = header.depth;
+    let (channels, max) = (header.channels(), (1u32 << depth) - 1);
+    // Down to 8 bits, keeping white white
+    let scale = |value: u16| (value as u32 * 255 / max) as u8;
+    // A single colour that stands for transparent, compared before scaling
+    let key = |i: usize| transparency.get(i * 2..i * 2 + 2).map(|v| u16::from_be_bytes([v[0], v[1]]));
+
+    for (y, line) in data.chunks_exact(stride + 1).enumerate() {
+        let row = &line[1..];
+
+        for x in 0..header.width {
+            let s = |c: usize| sample(row, x * channels + c, depth);
+
+            bitmap.pixels[y * header.width + x] = match header.color {
+                GRAY => {
+                    let v = s(0);
+                    Rgba::new(scale(v), scale(v), scale(v), if key(0) == Some(v) { 0 } else { 255 })
+                }
+                RGB => {
+                    let (r, g, b) = (s(0), s(1), s(2));
+                    let transparent = key(0) == Some(r) && key(1) == Some(g) && key(2) == Some(b);
+                    Rgba::new(scale(r), scale(g), scale(b), if transparent { 0 } else { 255 })
+                }
+                PALETTE => {
+                    let i = s(0) as usize;
+                    let entry = palette.get(i * 3..i * 3 + 3).ok_or(anyhow!("PNG palette index {i} is out of range"))?;
+                    Rgba::new(entry[0], entry[1], entry[2], transparency.get(i).copied().unwrap_or(255))
+                }
+                GRAY_ALPHA => Rgba::new(scale(s(0)), scale(s(0)), scale(s(0)), scale(s(1))),
+                _          => Rgba::new(scale(s(0)), scale(s(1)), scale(s(2)), scale(s(3)))
+            };
+        }
+    }
+
+    Ok(bitmap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(out: &mut Vec<u8>, kind: &[u8], data: &[u8]) {
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        out.extend_from_slice(kind);
+        out.extend_from_slice(data);
+        out.extend_from_slice(&[0; 4]);
+    }
+
+    // Scanlines in a single stored deflate block
+    fn png(width: u32, height: u32, depth: u8, color: u8, extra: &[(&[u8], &[u8])], rows: &[u8]) -> Vec<u8> {
+        let mut ihdr = Vec::new();
+        ihdr.extend_from_slice(&width.to_be_bytes());
+        ihdr.extend_from_slice(&height.to_be_bytes());
+        ihdr.extend_from_slice(&[depth, color, 0, 0, 0]);
+
+        let mut zlib = vec![0x78, 0x01, 0x01];
+        zlib.extend_from_slice(&(rows.len() as u16).to_le_bytes());
+        zlib.extend_from_slice(&(!(rows.len() as u16)).to_le_bytes());
+        zlib.extend_from_slice(rows);
+        zlib.extend_from_slice(&inflate::adler32(rows).to_be_bytes());
+
+        let mut out = Vec::from(MAGIC);
+        chunk(&mut out, b"IHDR", &ihdr);
+        for (kind, data) in extra { chunk(&mut out, kind, data); }
+        chunk(&mut out, b"IDAT", &zlib);
+        chunk(&mut out, b"IEND", &[]);
+        out
+    }
+
+    #[test_case]
+    fn filtered_rgba_rows_decode() {
+        let rows = [
+            1, 10, 20, 30, 255, 5, 5, 5, 0,  // Sub
+            2, 1, 1, 1, 0, 2, 2, 2, 0,       // Up
+            4, 1, 1, 1, 0, 1, 1, 1, 0        // Paeth
+        ];
+        let bitmap = decode(&png(2, 3, 8, RGBA, &[], &rows)).unwrap();
+
+        assert_eq!(bitmap.pixels, [
+            Rgba::new(10, 20, 30, 255), Rgba::new(15, 25, 35, 255),
+            Rgba::new(11, 21, 31, 255), Rgba::new(17, 27, 37, 255),
+            Rgba::new(12, 22, 32, 255), Rgba::new(18, 28, 38, 255)
+        ]);
+    }
+
+    #[test_case]
+    fn packed_palettes_use_transparency() {
+        let palette: &[u8] = &[255, 0, 0, 0, 0, 255];
+        let bitmap = decode(&png(3, 1, 1, PALETTE, &[(b"PLTE", palette), (b"tRNS", &[128]), (b"tEXt", b"x")], &[0, 0b0100_0000])).unwrap();
+
+        assert_eq!(bitmap.pixels, [Rgba::new(255, 0, 0, 128), Rgba::opaque(0, 0, 255), Rgba::new(255, 0, 0, 128)]);
+    }
+
+    #[test_case]
+    fn sixteen_bit_gray_scales_down() {
+        let bitmap = decode(&png(2, 1, 16, GRAY, &[(b"tRNS", &[0, 0])], &[0, 0xff, 0xff, 0, 0])).unwrap();
+        assert_eq!(bitmap.pixels, [Rgba::opaque(255, 255, 255), Rgba::new(0, 0, 0, 0)]);
+    }
+
+    #[test_case]
+    fn broken_files_fail() {
+        let good = png(1, 1, 8, GRAY, &[], &[0, 7]);
+        assert!(decode(&good).is_ok());
+        assert!(decode(&good[..good.len() - 12]).is_err());
+        assert!(decode(&png(1, 1, 8, GRAY, &[], &[5, 7])).is_err());
+        assert!(decode(&png(1, 1, 3, RGB, &[], &[0, 7])).is_err());
+        assert!(decode(&png(2, 1, 8, GRAY, &[], &[0, 7])).is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use anyhow::{anyhow, Result};
+
+use crate::drivers::video::graphics::Rgba;
+use crate::drivers::video::image::Bitmap;
+
+pub const MAGIC: &[u8] = b"qoif";
+
+const HEADER: usize = 14;
+const OP_RGB:  u8 = 0xfe;
+const OP_RGBA: u8 = 0xff;
+
+fn hash(p: Rgba) -> usize {
+    (p.r as usize * 3 + p.g as usize * 5 + p.b as usize * 7 + p.a as usize * 11) % 64
+}
+
+// https://qoiformat.org/qoi-specification.pdf
+pub fn decode(bytes: &[u8]) -> Result<Bitmap> {
+    let header = bytes.get(..HEADER).ok_or(anyhow!("QOI header is truncated"))?;
+    let width = u32::from_be_bytes(header[4..8].try_into().unwrap()) as usize;
+    let height = u32::from_be_bytes(header[8..12].try_into().unwrap()) as usize;
+    if !matches!(header[12], 3 | 4) { return Err(anyhow!("QOI with {} channels", header[12])); }
+
+    let mut bitmap = Bitmap::new(width, height)?;
+    let mut index = [Rgba::new(0, 0, 0, 0); 64];
+    let mut pixel = Rgba::new(0, 0, 0, 255);
+    let mut data = bytes[HEADER..].iter().copied();
+    let mut next = || data.next().ok_or(anyhow!("QOI data is truncated"));
+    let mut i = 0;
+
+    while i < bitmap.pixels.len() {
+        let op = next()?;
+
+        match op {
+            OP_RGB  => pixel = Rgba { r: next()?, g: next()?, b: next()?, ..pixel },
+            OP_RGBA => pixel = Rgba { r: next()?, g: next()?, b: next()?, a: next()? },
+            _ => match op >> 6 {
+                0 => pixel = index[op as usize & 0x3f],
+                1 => {
+                    pixel.r = pixel.r.wrapping_add((op >> 4 & 3).wrapping_sub(2));
+                    pixel.g = pixel.g.wrapping_add((op >> 2 & 3).wrapping_sub(2));
+                    pixel.b = pixel.b.wrapping_add((op & 3).wrapping_sub(2));
+                }
+                2 => {
+                    let green = (op & 0x3f).wrapping_sub(32);
+                    let rb = next()?;
+                    pixel.r = pixel.r.wrapping_add(green.wrapping_add((rb >> 4).wrapping_sub(8)));
+                    pixel.g = pixel.g.wrapping_add(green);
+                    pixel.b = pixel.b.wrapping_add(green.wrapping_add((rb & 0xf).wrapping_sub(8)));
+                }
+                _ => {
+                    // The run includes this pixel, the rest are filled here
+                    let run = (op & 0x3f) as usize;
+                    let end = (i + run).min(bitmap.pixels.len() - 1);
+                    bitmap.pixels[i..end].fill(pixel);
+                    i = end;
+                }
+            }
+        }
+
+        index[hash(pixel)] = pixel;
+        bitmap.pixels[i] = pixel;
+        i += 1;
+    }
+
+    Ok(bitmap)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn qoi(width: u32, height: u32, ops: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::from(MAGIC);
+        bytes.extend_from_slice(&width.to_be_bytes());
+        bytes.extend_from_slice(&height.to_be_bytes());
+        bytes.extend_from_slice(&[4, 0]);
+        bytes.extend_from_slice(ops);
+        bytes.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+        bytes
+    }
+
+    #[test_case]
+    fn every_op_decodes() {
+        let red = Rgba::opaque(255, 0, 0);
+        let ops = [
+            OP_RGB, 255, 0, 0,          // red
+            0xc0 | 1,                   // red twice more
+            OP_RGBA, 0, 0, 255, 128,    // translucent blue
+            0x40 | 3 << 4 | 2 << 2 | 1, // blue, r + 1 and b - 1
+            0x80 | 34, 0x99,            // g + 2, r + 3, b + 3
+            (hash(red)) as u8           // red again from the index
+        ];
+        let bitmap = decode(&qoi(7, 1, &ops)).unwrap();
+
+        assert_eq!(bitmap.pixels[..3], [red; 3]);
+        assert_eq!(bitmap.pixels[3], Rgba::new(0, 0, 255, 128));
+        assert_eq!(bitmap.pixels[4], Rgba::new(1, 0, 254, 128));
+        assert_eq!(bitmap.pixels[5], Rgba::new(4, 2, 1, 128));
+        assert_eq!(bitmap.pixels[6], red);
+    }
+
+    #[test_case]
+    fn truncated_data_fails() {
+        assert!(decode(&qoi(4, 4, &[OP_RGB, 1, 2])[..17]).is_err());
+        assert!(decode(MAGIC).is_err());
+    }
+}
@@ -2,7 +2,7 @@ use core::fmt::Write;
 use anyhow::{anyhow, Result};
 use rusttype::{Font, Scale, Point};
 
-use crate::drivers::video::framebuffer::{Framebuffer, Pixel, HRES};
+use crate::drivers::video::framebuffer::{Framebuffer, Pixel};
 
 pub struct Color {
     r: f32,
@@ -16,12 +16,22 @@ impl Color {
     }
 }
 
+const BACKGROUND: Pixel = Pixel { blue: 0, green: 0, red: 0 };
+
 pub struct Printer<'a> {
-    fb:    Framebuffer<'a>,
-    font:  Font<'a>,
-    scale: Scale,
-    color: Color,
-    pos:   Point<f32>
+    fb:       Framebuffer<'a>,
+    font:     Font<'a>,
+    scale:    Scale,
+    color:    Color,
+    pos:      Point<f32>,
+    scrolled: f32
+}
+
+// A remembered pen position that stays valid while the screen scrolls underneath it
+#[derive(Clone, Copy)]
+pub struct Mark {
+    pos:      Point<f32>,
+    scrolled: f32
 }
 
 impl<'a> Printer<'a> {
@@ -32,13 +42,69 @@ impl<'a> Printer<'a> {
         let v_metrics = font.v_metrics(scale);
         let pos = rusttype::point(0.0, v_metrics.ascent + v_metrics.line_gap);
 
-        Ok(Printer { fb, font, scale, color, pos })
+        Ok(Printer { fb, font, scale, color, pos, scrolled: 0.0 })
+    }
+
+    // For drawing that is not text, which the printer does not know about and will scroll away
+    pub fn framebuffer(&mut self) -> &mut Framebuffer<'a> {
+        &mut self.fb
+    }
+
+    fn line_height(&self) -> f32 {
+        let v_metrics = self.font.v_metrics(self.scale);
+        v_metrics.ascent - v_metrics.descent + v_metrics.line_gap
     }
 
     pub fn newline(&mut self) {
         let v_metrics = self.font.v_metrics(self.scale);
         self.pos.x = 0.0;
-        self.pos.y += v_metrics.ascent - v_metrics.descent + v_metrics.line_gap;
+        self.pos.y += self.line_height();
+
+        let overflow = (self.pos.y - v_metrics.descent) as isize + 1 - self.fb.height as isize;
+        if overflow > 0 {
+            self.fb.scroll_up(overflow as usize, BACKGROUND);
+            self.pos.y -= overflow as f32;
+            self.scrolled += overflow as f32;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        let v_metrics = self.font.v_metrics(self.scale);
+        let (width, height) = (self.fb.width, self.fb.height);
+
+        self.fb.fill(0, 0, width, height, BACKGROUND);
+        self.pos = rusttype::point(0.0, v_metrics.ascent + v_metrics.line_gap);
+    }
+
+    pub fn mark(&self) -> Mark {
+        Mark { pos: self.pos, scrolled: self.scrolled }
+    }
+
+    fn resolve(&self, mark: Mark) -> Point<f32> {
+        rusttype::point(mark.pos.x, mark.pos.y - (self.scrolled - mark.scrolled))
+    }
+
+    // Blanks everything after the mark, up to the bottom of the screen, and moves the pen back there
+    pub fn erase_from(&mut self, mark: Mark) {
+        let ascent = self.font.v_metrics(self.scale).ascent;
+        let pos = self.resolve(mark);
+        let top = (pos.y - ascent).max(0.0) as usize;
+        let bottom = (pos.y - ascent + self.line_height()).max(0.0) as usize;
+        let (width, height) = (self.fb.width, self.fb.height);
+
+        self.fb.fill(pos.x as usize, top, width, bottom - top, BACKGROUND);
+        self.fb.fill(0, bottom, width, height, BACKGROUND);
+        self.pos = pos;
+    }
+
+    pub fn draw_cursor(&mut self, mark: Mark) {
+        let v_metrics = self.font.v_metrics(self.scale);
+        let pos = self.resolve(mark);
+        let top = (pos.y - v_metrics.ascent).max(0.0) as usize;
+        let height = (v_metrics.ascent - v_metrics.descent) as usize;
+        let pixel = Pixel { red: self.color.r as u8, green: self.color.g as u8, blue: self.color.b as u8 };
+
+        self.fb.fill(pos.x as usize, top, 2, height, pixel);
     }
 
     pub fn put_char(&mut self, c: char) -> Result<()> {
@@ -52,7 +118,7 @@ impl<'a> Printer<'a> {
         let mut glyph = glyph.positioned(self.pos);
 
         if let Some(bounds) = glyph.pixel_bounding_box() {
-            if bounds.max.x >= HRES as i32 {
+            if bounds.max.x >= self.fb.width as i32 {
                 self.newline();
             }
 
@@ -73,7 +139,9 @@ impl<'a> Printer<'a> {
                     blue:  (self.color.b * a) as u8
                 };
 
-                self.fb[y][x] = self.fb[y][x].max(p);
+                if let Some(old) = self.fb.get(x, y) {
+                    self.fb.set(x, y, old.max(p));
+                }
             });
         }
 
@@ -90,3 +158,123 @@ impl Write for Printer<'_> {
             .try_for_each(|r| r.map_err(|_| core::fmt::Error))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use super::*;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use crate::drivers::video::fonts;
+
+    const WIDTH: usize = 200;
+    const HEIGHT: usize = 100;
+
+    // SF Pro is not redistributable and stays out of git, a checkout without it has nothing to test with
+    fn printer(pixels: &mut Vec<Pixel>) -> Option<Printer<'_>> {
+        if fonts::SF_PRO.is_empty() { return None; }
+
+        let fb = unsafe { Framebuffer::new(pixels.as_mut_ptr(), WIDTH, HEIGHT, WIDTH) };
+        Some(Printer::new(fb, fonts::SF_PRO, 20.0, Color::new(255.0, 255.0, 255.0)).unwrap())
+    }
+
+    fn blank() -> Vec<Pixel> {
+        vec![Pixel { blue: 0, green: 0, red: 0 }; WIDTH * HEIGHT]
+    }
+
+    #[test_case]
+    fn starts_on_first_baseline() {
+        let mut pixels = blank();
+        let Some(printer) = printer(&mut pixels) else { return; };
+        let v_metrics = printer.font.v_metrics(printer.scale);
+
+        assert_eq!(printer.pos.x, 0.0);
+        assert_eq!(printer.pos.y, v_metrics.ascent + v_metrics.line_gap);
+    }
+
+    #[test_case]
+    fn newline_returns_to_left_edge() {
+        let mut pixels = blank();
+        let Some(mut printer) = printer(&mut pixels) else { return; };
+        let y = printer.pos.y;
+
+        printer.write_str("ab\n").unwrap();
+
+        assert_eq!(printer.pos.x, 0.0);
+        assert!(printer.pos.y > y);
+    }
+
+    #[test_case]
+    fn characters_advance_and_draw() {
+        let mut pixels = blank();
+        let Some(mut printer) = printer(&mut pixels) else { return; };
+
+        printer.write_str("H").unwrap();
+        assert!(printer.pos.x > 0.0);
+        drop(printer);
+
+        assert!(pixels.iter().any(|p| p.red > 0));
+    }
+
+    // Greyscale PGM of one channel, the text is white so all channels are equal
+    #[cfg(not(target_os = "none"))]
+    fn pgm(pixels: &[Pixel]) -> Vec<u8> {
+        let mut out = alloc::format!("P5\n{WIDTH} {HEIGHT}\n255\n").into_bytes();
+        out.extend(pixels.iter().map(|p| p.red));
+        out
+    }
+
+    #[test_case]
+    #[cfg(not(target_os = "none"))]
+    fn renders_like_golden_image() {
+        let mut pixels = blank();
+        let Some(mut printer) = printer(&mut pixels) else { return; };
+
+        printer.write_str("Hello, AOS!\nfn main() {}").unwrap();
+        drop(printer);
+
+        crate::testing::golden("printer-hello.pgm", &pgm(&pixels));
+    }
+
+    #[test_case]
+    fn scrolls_instead_of_running_off_the_bottom() {
+        let mut pixels = blank();
+        let Some(mut printer) = printer(&mut pixels) else { return; };
+
+        printer.write_str("H\n\n\n\n\n\n\nH").unwrap();
+
+        assert!(printer.scrolled > 0.0);
+        assert!(printer.pos.y < HEIGHT as f32);
+    }
+
+    #[test_case]
+    fn erase_from_clears_and_rewinds() {
+        let mut pixels = blank();
+        let Some(mut printer) = printer(&mut pixels) else { return; };
+
+        printer.write_str("> ").unwrap();
+        let mark = printer.mark();
+        printer.write_str("abc\ndef").unwrap();
+        printer.erase_from(mark);
+
+        assert_eq!(printer.pos, mark.pos);
+        let below = (printer.pos.y as usize + 1) * WIDTH;
+        drop(printer);
+
+        assert!(pixels[below..].iter().all(|p| p.red == 0));
+    }
+
+    #[test_case]
+    fn long_lines_wrap() {
+        let mut pixels = blank();
+        let Some(mut printer) = printer(&mut pixels) else { return; };
+        let y = printer.pos.y;
+
+        printer.write_str("WWWWWWWWWWWWWWWWWWWW").unwrap();
+
+        assert!(printer.pos.y > y);
+        assert!(printer.pos.x < WIDTH as f32);
+    }
+}
@@ -0,0 +1,135 @@
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+
+use crate::drivers::video::graphics::{Canvas, Rect, Rgba};
+use crate::drivers::video::image::{self, Bitmap};
+use crate::drivers::video;
+use crate::{cmdline, initrd};
+
+const INITRD_PATHS: [&str; 3] = ["splash.qoi", "splash.png", "splash.bmp"];
+const BACKGROUND:   Rgba = Rgba::opaque(0, 0, 0);
+const BAR:          Rgba = Rgba::opaque(255, 255, 255);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Memory,
+    Acpi,
+    Pci,
+    Drivers
+}
+
+// The bar is full once the last one is done
+const STAGES: usize = Stage::Drivers as usize + 1;
+
+struct Splash {
+    // Inside the outline, what fills up
+    bar: Rect
+}
+
+static mut SPLASH: Option<Splash> = None;
+
+// A bar as wide as a third of the screen, a gap of a twentieth of its height below the logo
+fn layout(screen: Rect, logo: &Bitmap) -> (Rect, Rect) {
+    let height = (screen.height / 100).max(4);
+    let gap = screen.height / 20;
+    let top = (screen.height.saturating_sub(logo.height + gap + height) / 2) as isize;
+
+    let logo = Rect::new(((screen.width - logo.width) / 2) as isize, top, logo.width, logo.height);
+    let bar = Rect::new((screen.width / 3) as isize, logo.bottom() + gap as isize, screen.width / 3, height);
+
+    (logo, bar)
+}
+
+fn load(esp: &[u8]) -> Result<Bitmap> {
+    let bytes = match esp {
+        [] => INITRD_PATHS.iter().find_map(|path| initrd::read(path)).ok_or(anyhow!("No splash image"))?,
+        _  => esp
+    };
+
+    image::decode(bytes)
+}
+
+// Takes over the screen until finish, unless verbose asks for the messages instead. The image comes
+// from the bootloader, which reads it from the ESP, or else from the initrd
+pub fn init(esp: &[u8]) {
+    if cmdline::verbose() { return; }
+    let Some(printer) = video::printer() else { return; };
+
+    let bitmap = match load(esp) {
+        Ok(bitmap) => bitmap,
+        Err(e)     => {
+            info!("Boot splash: {e}");
+            return;
+        }
+    };
+
+    let fb = printer.framebuffer();
+    let screen = Rect::new(0, 0, fb.width, fb.height);
+
+    // Only ever scaled down, nearest neighbour makes a mess of anything else
+    let bitmap = if bitmap.width > screen.width / 2 || bitmap.height > screen.height / 2 {
+        match bitmap.fit(screen.width / 2, screen.height / 2) {
+            Ok(bitmap) => bitmap,
+            Err(e)     => {
+                warn!("Boot splash: {e}");
+                return;
+            }
+        }
+    } else {
+        bitmap
+    };
+
+    let image = match bitmap.image() {
+        Ok(image) => image,
+        Err(e)    => {
+            warn!("Boot splash: {e}");
+            return;
+        }
+    };
+
+    let (logo, bar) = layout(screen, &bitmap);
+    let mut canvas = Canvas::new(fb);
+    canvas.fill_rect(screen, BACKGROUND);
+    canvas.blit(&image, logo.x, logo.y);
+    canvas.rect(bar, BAR);
+
+    let inner = Rect::new(bar.x + 2, bar.y + 2, bar.width.saturating_sub(4), bar.height.saturating_sub(4));
+    unsafe { SPLASH = Some(Splash { bar: inner }); }
+}
+
+pub fn active() -> bool {
+    unsafe { SPLASH.is_some() }
+}
+
+pub fn progress(stage: Stage) {
+    let (Some(splash), Some(printer)) = (unsafe { SPLASH.as_ref() }, video::printer()) else { return; };
+
+    let bar = splash.bar;
+    let done = Rect::new(bar.x, bar.y, bar.width * (stage as usize + 1) / STAGES, bar.height);
+    Canvas::new(printer.framebuffer()).fill_rect(done, BAR);
+}
+
+// Back to text, on a clean screen
+pub fn finish() {
+    if unsafe { SPLASH.take() }.is_none() { return; }
+
+    if let Some(printer) = video::printer() {
+        printer.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use super::*;
+
+    #[test_case]
+    fn bar_sits_centred_below_the_logo() {
+        let logo = Bitmap { width: 200, height: 100, pixels: alloc::vec::Vec::new() };
+        let (logo, bar) = layout(Rect::new(0, 0, 1000, 800), &logo);
+
+        assert_eq!(logo, Rect::new(400, 326, 200, 100));
+        assert_eq!(bar, Rect::new(333, 466, 333, 8));
+    }
+}
@@ -0,0 +1,226 @@
+extern crate alloc;
+
+use core::alloc::Layout;
+use core::arch::asm;
+use core::arch::x86_64::{__m128i, _mm_loadu_si128, _mm_storeu_si128};
+use core::marker::PhantomData;
+use core::ptr::{self, NonNull};
+use alloc::alloc::{alloc_zeroed, dealloc};
+use log::info;
+use x86_64::instructions::interrupts;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+use x86_64::registers::xcontrol::{XCr0, XCr0Flags};
+
+use crate::cpu::{self, Cpuid, Feature, Native};
+
+const FXSAVE_SIZE: usize = 512;
+const ALIGN:       usize = 64;
+// Everything masked, round to nearest
+const MXCSR:       u32 = 0x1f80;
+
+static mut READY: bool = false;
+static mut XSAVE: bool = false;
+static mut SIZE: usize = FXSAVE_SIZE;
+// The registers right after fninit, what every new state starts from
+static mut CLEAN: Option<State> = None;
+// Whatever was live when the outermost guard started, usually the state of the running process
+static mut SCRATCH: Option<State> = None;
+static mut DEPTH: usize = 0;
+
+// A save area sized for the features enabled in XCR0
+pub struct State {
+    area: NonNull<u8>,
+    size: usize
+}
+
+impl State {
+    pub fn new() -> State {
+        let size = unsafe { SIZE };
+        let area = unsafe { alloc_zeroed(Layout::from_size_align(size, ALIGN).unwrap()) };
+        let state = State { area: NonNull::new(area).expect("Out of memory for FPU state"), size };
+
+        if let Some(clean) = unsafe { CLEAN.as_ref() } {
+            unsafe { ptr::copy_nonoverlapping(clean.area.as_ptr(), state.area.as_ptr(), size); }
+        }
+
+        state
+    }
+
+    /// # Safety
+    ///
+    /// The registers being saved must belong to whoever owns this state, nothing may be using them in between.
+    pub unsafe fn save(&mut self) {
+        if !READY { return; }
+
+        if XSAVE {
+            asm!("xsave64 [{}]", in(reg) self.area.as_ptr(), in("eax") u32::MAX, in("edx") u32::MAX, options(nostack));
+        } else {
+            asm!("fxsave64 [{}]", in(reg) self.area.as_ptr(), options(nostack));
+        }
+    }
+
+    /// # Safety
+    ///
+    /// Whatever the registers held before is lost, so it must have been saved or be of no further use.
+    pub unsafe fn restore(&self) {
+        if !READY { return; }
+
+        if XSAVE {
+            asm!("xrstor64 [{}]", in(reg) self.area.as_ptr(), in("eax") u32::MAX, in("edx") u32::MAX, options(nostack));
+        } else {
+            asm!("fxrstor64 [{}]", in(reg) self.area.as_ptr(), options(nostack));
+        }
+    }
+}
+
+impl Default for State {
+    fn default() -> State {
+        State::new()
+    }
+}
+
+impl Drop for State {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.area.as_ptr(), Layout::from_size_align(self.size, ALIGN).unwrap()); }
+    }
+}
+
+// Needs the heap for the save areas, so it comes after memory::init
+pub fn init() {
+    let xsave = cpu::has(Feature::Xsave);
+
+    unsafe {
+        // x87 errors as exceptions rather than through the PIC, and no #NM since nothing is lazy
+        Cr0::update(|cr0| {
+            cr0.remove(Cr0Flags::EMULATE_COPROCESSOR | Cr0Flags::TASK_SWITCHED);
+            cr0.insert(Cr0Flags::MONITOR_COPROCESSOR | Cr0Flags::NUMERIC_ERROR);
+        });
+
+        let mut cr4 = Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE;
+        if xsave { cr4 |= Cr4Flags::OSXSAVE; }
+        Cr4::update(|flags| flags.insert(cr4));
+
+        if xsave {
+            let supported = XCr0Flags::from_bits_truncate(Native.cpuid(0xd, 0)[0] as u64);
+            let mut xcr0 = XCr0Flags::X87 | XCr0Flags::SSE;
+
+            if cpu::has(Feature::Avx) {
+                xcr0 |= XCr0Flags::AVX;
+            }
+            if cpu::has(Feature::Avx512f) {
+                xcr0 |= XCr0Flags::OPMASK | XCr0Flags::ZMM_HI256 | XCr0Flags::HI16_ZMM;
+            }
+
+            XCr0::write(xcr0 & supported);
+            // ebx follows what XCR0 enables
+            SIZE = Native.cpuid(0xd, 0)[1] as usize;
+        }
+
+        asm!("fninit", "ldmxcsr [{}]", in(reg) &MXCSR, options(nostack));
+
+        XSAVE = xsave;
+        READY = true;
+
+        let mut clean = State::new();
+        clean.save();
+        CLEAN = Some(clean);
+        SCRATCH = Some(State::new());
+
+        info!("FPU state is {} bytes, saved with {}", SIZE, if xsave { "xsave" } else { "fxsave" });
+    }
+}
+
+// Lets kernel code use SIMD registers until it is dropped. Interrupts stay off for as long, so
+// nothing else can get in and the registers belonging to user space come back untouched
+pub struct Guard {
+    interrupts: bool,
+    _local:     PhantomData<*const ()>
+}
+
+pub fn begin() -> Guard {
+    let enabled = cfg!(target_os = "none") && interrupts::are_enabled();
+    if enabled { interrupts::disable(); }
+
+    unsafe {
+        if DEPTH == 0 {
+            if let Some(scratch) = SCRATCH.as_mut() { scratch.save(); }
+        }
+
+        DEPTH += 1;
+    }
+
+    Guard { interrupts: enabled, _local: PhantomData }
+}
+
+impl Guard {
+    /// # Safety
+    ///
+    /// `dst` and `src` must be valid for `count` elements and must not overlap.
+    pub unsafe fn copy<T: Copy>(&self, dst: *mut T, src: *const T, count: usize) {
+        copy_sse2(dst as *mut u8, src as *const u8, count * size_of::<T>());
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        unsafe {
+            DEPTH -= 1;
+
+            if DEPTH == 0 {
+                if let Some(scratch) = SCRATCH.as_ref() { scratch.restore(); }
+            }
+        }
+
+        if self.interrupts { interrupts::enable(); }
+    }
+}
+
+// SSE2 is part of the x86_64 baseline, cpu::init refuses to start without it
+#[target_feature(enable = "sse2")]
+unsafe fn copy_sse2(dst: *mut u8, src: *const u8, len: usize) {
+    let blocks = len / 64;
+
+    for i in 0..blocks {
+        let (src, dst) = (src.add(i * 64) as *const __m128i, dst.add(i * 64) as *mut __m128i);
+        let (a, b, c, d) = (_mm_loadu_si128(src), _mm_loadu_si128(src.add(1)), _mm_loadu_si128(src.add(2)), _mm_loadu_si128(src.add(3)));
+
+        _mm_storeu_si128(dst, a);
+        _mm_storeu_si128(dst.add(1), b);
+        _mm_storeu_si128(dst.add(2), c);
+        _mm_storeu_si128(dst.add(3), d);
+    }
+
+    ptr::copy_nonoverlapping(src.add(blocks * 64), dst.add(blocks * 64), len % 64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test_case]
+    fn copy_handles_blocks_and_tails() {
+        let src: Vec<u8> = (0..200).collect();
+        let mut dst = [0u8; 200];
+
+        let fpu = begin();
+        unsafe { fpu.copy(dst.as_mut_ptr().add(1), src.as_ptr(), 150); }
+        drop(fpu);
+
+        assert_eq!(dst[0], 0);
+        assert_eq!(dst[1..151], src[..150]);
+        assert_eq!(dst[151], 0);
+    }
+
+    #[test_case]
+    fn guards_nest() {
+        let outer = begin();
+        let inner = begin();
+        assert_eq!(unsafe { DEPTH }, 2);
+
+        drop(inner);
+        assert_eq!(unsafe { DEPTH }, 1);
+        drop(outer);
+        assert_eq!(unsafe { DEPTH }, 0);
+    }
+}
@@ -0,0 +1,69 @@
+use core::ops::Range;
+use core::ptr;
+use log::info;
+use x86_64::VirtAddr;
+use x86_64::instructions::segmentation::{Segment, CS, DS, ES, SS};
+use x86_64::instructions::tables::load_tss;
+use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
+use x86_64::structures::tss::TaskStateSegment;
+
+pub const DOUBLE_FAULT_IST: u16 = 0;
+
+const IST_STACK_SIZE: usize = 4096 * 5;
+
+#[derive(Clone, Copy)]
+pub struct Selectors {
+    pub kernel_code: SegmentSelector,
+    pub kernel_data: SegmentSelector,
+    pub user_data:   SegmentSelector,
+    pub user_code:   SegmentSelector,
+    pub tss:         SegmentSelector
+}
+
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+static mut GDT: GlobalDescriptorTable = GlobalDescriptorTable::new();
+static mut SELECTORS: Option<Selectors> = None;
+static mut DOUBLE_FAULT_STACK: [u8; IST_STACK_SIZE] = [0; IST_STACK_SIZE];
+
+pub fn init() {
+    info!("Loading Descriptor Tables..");
+
+    unsafe {
+        let stack = VirtAddr::from_ptr(ptr::addr_of!(DOUBLE_FAULT_STACK));
+        TSS.interrupt_stack_table[DOUBLE_FAULT_IST as usize] = stack + IST_STACK_SIZE as u64;
+
+        // SYSRET derives both user selectors from one base, so user data must come right before user code
+        let selectors = Selectors {
+            kernel_code: GDT.append(Descriptor::kernel_code_segment()),
+            kernel_data: GDT.append(Descriptor::kernel_data_segment()),
+            user_data:   GDT.append(Descriptor::user_data_segment()),
+            user_code:   GDT.append(Descriptor::user_code_segment()),
+            tss:         GDT.append(Descriptor::tss_segment(&TSS))
+        };
+
+        GDT.load();
+
+        CS::set_reg(selectors.kernel_code);
+        SS::set_reg(selectors.kernel_data);
+        DS::set_reg(selectors.kernel_data);
+        ES::set_reg(selectors.kernel_data);
+        load_tss(selectors.tss);
+
+        SELECTORS = Some(selectors);
+    }
+
+    info!("Success");
+}
+
+pub fn selectors() -> Selectors {
+    unsafe { SELECTORS.expect("GDT is not initialized") }
+}
+
+pub fn double_fault_stack() -> Range<u64> {
+    let start = ptr::addr_of!(DOUBLE_FAULT_STACK) as u64;
+    start..start + IST_STACK_SIZE as u64
+}
+
+pub fn set_kernel_stack(top: VirtAddr) {
+    unsafe { TSS.privilege_stack_table[0] = top; }
+}
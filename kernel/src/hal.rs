@@ -0,0 +1,169 @@
+// Port and MMIO access go through these traits so drivers can run against a mock on the host
+
+pub trait PortIo {
+    /// # Safety
+    ///
+    /// The caller must own the port, reading one can already change the state of the device.
+    unsafe fn read_u8(&mut self, port: u16) -> u8;
+
+    /// # Safety
+    ///
+    /// The caller must own the port and know what the value does to the device behind it.
+    unsafe fn write_u8(&mut self, port: u16, value: u8);
+
+    /// # Safety
+    ///
+    /// The caller must own the port, reading one can already change the state of the device.
+    unsafe fn read_u16(&mut self, port: u16) -> u16;
+
+    /// # Safety
+    ///
+    /// The caller must own the port and know what the value does to the device behind it.
+    unsafe fn write_u16(&mut self, port: u16, value: u16);
+
+    /// # Safety
+    ///
+    /// The caller must own the port, reading one can already change the state of the device.
+    unsafe fn read_u32(&mut self, port: u16) -> u32;
+
+    /// # Safety
+    ///
+    /// The caller must own the port and know what the value does to the device behind it.
+    unsafe fn write_u32(&mut self, port: u16, value: u32);
+}
+
+pub trait Mmio {
+    /// # Safety
+    ///
+    /// The address must be mapped and belong to a device the caller owns.
+    unsafe fn read_u16(&self, addr: u64) -> u16;
+
+    /// # Safety
+    ///
+    /// The address must be mapped and belong to a device the caller owns.
+    unsafe fn read_u32(&self, addr: u64) -> u32;
+}
+
+pub struct Ports;
+
+impl PortIo for Ports {
+    unsafe fn read_u8(&mut self, port: u16) -> u8 {
+        x86_64::instructions::port::Port::new(port).read()
+    }
+
+    unsafe fn write_u8(&mut self, port: u16, value: u8) {
+        x86_64::instructions::port::Port::new(port).write(value)
+    }
+
+    unsafe fn read_u16(&mut self, port: u16) -> u16 {
+        x86_64::instructions::port::Port::new(port).read()
+    }
+
+    unsafe fn write_u16(&mut self, port: u16, value: u16) {
+        x86_64::instructions::port::Port::new(port).write(value)
+    }
+
+    unsafe fn read_u32(&mut self, port: u16) -> u32 {
+        x86_64::instructions::port::Port::new(port).read()
+    }
+
+    unsafe fn write_u32(&mut self, port: u16, value: u32) {
+        x86_64::instructions::port::Port::new(port).write(value)
+    }
+}
+
+pub struct Direct;
+
+impl Mmio for Direct {
+    unsafe fn read_u16(&self, addr: u64) -> u16 {
+        (addr as *const u16).read_volatile()
+    }
+
+    unsafe fn read_u32(&self, addr: u64) -> u32 {
+        (addr as *const u32).read_volatile()
+    }
+}
+
+#[cfg(test)]
+pub mod mock {
+    extern crate alloc;
+
+    use alloc::collections::{BTreeMap, VecDeque};
+    use alloc::vec::Vec;
+
+    use super::{Mmio, PortIo};
+
+    // Reads pop queued values per port and fall back to a fixed value, writes are recorded in order
+    #[derive(Default)]
+    pub struct MockPorts {
+        pub reads:    BTreeMap<u16, VecDeque<u32>>,
+        pub fallback: BTreeMap<u16, u32>,
+        pub writes:   Vec<(u16, u32)>
+    }
+
+    impl MockPorts {
+        pub fn queue(&mut self, port: u16, values: &[u32]) {
+            self.reads.entry(port).or_default().extend(values);
+        }
+
+        pub fn read(&mut self, port: u16) -> u32 {
+            self.reads.get_mut(&port)
+                .and_then(VecDeque::pop_front)
+                .or_else(|| self.fallback.get(&port).copied())
+                .unwrap_or(0)
+        }
+
+        pub fn written(&self, port: u16) -> Vec<u32> {
+            self.writes.iter().filter(|(p, _)| *p == port).map(|(_, value)| *value).collect()
+        }
+    }
+
+    impl PortIo for MockPorts {
+        unsafe fn read_u8(&mut self, port: u16) -> u8 {
+            self.read(port) as u8
+        }
+
+        unsafe fn write_u8(&mut self, port: u16, value: u8) {
+            self.writes.push((port, value as u32));
+        }
+
+        unsafe fn read_u16(&mut self, port: u16) -> u16 {
+            self.read(port) as u16
+        }
+
+        unsafe fn write_u16(&mut self, port: u16, value: u16) {
+            self.writes.push((port, value as u32));
+        }
+
+        unsafe fn read_u32(&mut self, port: u16) -> u32 {
+            self.read(port)
+        }
+
+        unsafe fn write_u32(&mut self, port: u16, value: u32) {
+            self.writes.push((port, value));
+        }
+    }
+
+    // A little-endian memory window starting at base, reads outside of it return all ones like a missing device
+    pub struct MockMmio {
+        pub base:  u64,
+        pub bytes: Vec<u8>
+    }
+
+    impl MockMmio {
+        fn read<const N: usize>(&self, addr: u64) -> Option<[u8; N]> {
+            let offset = addr.checked_sub(self.base)? as usize;
+            self.bytes.get(offset..offset + N)?.try_into().ok()
+        }
+    }
+
+    impl Mmio for MockMmio {
+        unsafe fn read_u16(&self, addr: u64) -> u16 {
+            self.read(addr).map_or(0xffff, u16::from_le_bytes)
+        }
+
+        unsafe fn read_u32(&self, addr: u64) -> u32 {
+            self.read(addr).map_or(0xffff_ffff, u32::from_le_bytes)
+        }
+    }
+}
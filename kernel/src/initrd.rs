@@ -0,0 +1,150 @@
+pub mod cpio;
+pub mod tar;
+
+use core::fmt::{self, Display, Formatter};
+use anyhow::{anyhow, Result};
+use log::{error, info};
+
+use crate::bootinfo::Region;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    Cpio,
+    Tar
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Kind {
+    File,
+    Directory,
+    Other
+}
+
+// Long tar paths are split into a prefix and a name, cpio always has an empty prefix
+#[derive(Clone, Copy)]
+pub struct File<'a> {
+    pub prefix: &'a str,
+    pub name:   &'a str,
+    pub kind:   Kind,
+    pub mode:   u32,
+    pub data:   &'a [u8]
+}
+
+impl File<'_> {
+    pub fn matches(&self, path: &str) -> bool {
+        let path = normalize(path);
+
+        if self.prefix.is_empty() {
+            return path == self.name;
+        }
+
+        path.strip_prefix(self.prefix)
+            .and_then(|rest| rest.strip_prefix('/'))
+            .is_some_and(|rest| rest == self.name)
+    }
+}
+
+impl Display for File<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.prefix.is_empty() {
+            write!(f, "{}", self.name)
+        } else {
+            write!(f, "{}/{}", self.prefix, self.name)
+        }
+    }
+}
+
+pub struct Initrd {
+    format: Format,
+    data:   &'static [u8]
+}
+
+static mut INITRD: Option<Initrd> = None;
+
+impl Initrd {
+    pub fn new(data: &'static [u8]) -> Result<Initrd> {
+        let format = if cpio::detect(data) {
+            Format::Cpio
+        } else if tar::detect(data) {
+            Format::Tar
+        } else {
+            return Err(anyhow!("Unknown archive format"));
+        };
+
+        Ok(Initrd { format, data })
+    }
+
+    pub fn init_global(region: Region) -> Result<()> {
+        if region.is_empty() {
+            info!("No initrd provided");
+            return Ok(());
+        }
+
+        info!("Reading Archive..");
+
+        let initrd = Initrd::new(unsafe { region.as_slice() })?;
+        let count = initrd.files().filter(|file| file.kind == Kind::File).count();
+
+        info!("{:?} archive, {} bytes, {} files", initrd.format, region.size, count);
+
+        unsafe { INITRD = Some(initrd); }
+
+        Ok(())
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn files(&self) -> Files {
+        Files { format: self.format, data: self.data, offset: 0 }
+    }
+
+    pub fn find(&self, path: &str) -> Option<File<'static>> {
+        self.files().find(|file| file.matches(path))
+    }
+}
+
+pub fn get() -> Option<&'static Initrd> {
+    unsafe { INITRD.as_ref() }
+}
+
+pub fn read(path: &str) -> Option<&'static [u8]> {
+    get()?.find(path)
+        .filter(|file| file.kind == Kind::File)
+        .map(|file| file.data)
+}
+
+pub struct Files {
+    format: Format,
+    data:   &'static [u8],
+    offset: usize
+}
+
+impl Iterator for Files {
+    type Item = File<'static>;
+
+    fn next(&mut self) -> Option<File<'static>> {
+        let next = match self.format {
+            Format::Cpio => cpio::next(self.data, self.offset),
+            Format::Tar  => tar::next(self.data, self.offset)
+        };
+
+        match next {
+            Ok(Some((file, offset))) => {
+                self.offset = offset;
+                Some(file)
+            }
+            Ok(None) => None,
+            Err(e)   => {
+                error!("{e}");
+                None
+            }
+        }
+    }
+}
+
+fn normalize(path: &str) -> &str {
+    let path = path.trim_start_matches("./").trim_start_matches('/');
+    path.trim_end_matches('/')
+}
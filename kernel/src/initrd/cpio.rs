@@ -0,0 +1,69 @@
+use core::str;
+use anyhow::{anyhow, Result};
+
+use crate::initrd::{normalize, File, Kind};
+
+const HEADER_SIZE: usize = 110;
+const TRAILER: &str = "TRAILER!!!";
+
+const S_IFMT:  u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+
+pub fn detect(data: &[u8]) -> bool {
+    data.starts_with(b"070701") || data.starts_with(b"070702")
+}
+
+fn field(header: &[u8], index: usize) -> Result<u32> {
+    let start = 6 + index * 8;
+    let hex = str::from_utf8(&header[start..start + 8])?;
+    u32::from_str_radix(hex, 16).map_err(|e| anyhow!("Bad cpio header field: {e}"))
+}
+
+fn align(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+// newc: magic, ino, mode, uid, gid, nlink, mtime, filesize, devmajor, devminor, rdevmajor, rdevminor, namesize, check
+pub fn next(data: &'static [u8], mut offset: usize) -> Result<Option<(File<'static>, usize)>> {
+    loop {
+        let Some(header) = data.get(offset..offset + HEADER_SIZE) else {
+            return Err(anyhow!("Truncated cpio header at {offset}"));
+        };
+
+        if !detect(header) {
+            return Err(anyhow!("Bad cpio magic at {offset}"));
+        }
+
+        let mode = field(header, 1)?;
+        let size = field(header, 6)? as usize;
+        let namesize = field(header, 11)? as usize;
+
+        let name_start = offset + HEADER_SIZE;
+        let name = data.get(name_start..name_start + namesize.saturating_sub(1))
+            .ok_or(anyhow!("Truncated cpio name at {offset}"))?;
+        let name = str::from_utf8(name)?;
+
+        let data_start = align(name_start + namesize);
+        let file = data.get(data_start..data_start + size)
+            .ok_or(anyhow!("Truncated cpio data for {name}"))?;
+        let next = align(data_start + size);
+
+        if name == TRAILER { return Ok(None); }
+
+        let name = normalize(name);
+        if name.is_empty() || name == "." {
+            // The archive root carries no information of its own
+            offset = next;
+            continue;
+        }
+
+        let kind = match mode & S_IFMT {
+            S_IFREG => Kind::File,
+            S_IFDIR => Kind::Directory,
+            _       => Kind::Other
+        };
+
+        return Ok(Some((File { prefix: "", name, kind, mode: mode & 0o7777, data: file }, next)));
+    }
+}
@@ -0,0 +1,75 @@
+use core::str;
+use anyhow::{anyhow, Result};
+
+use crate::initrd::{normalize, File, Kind};
+
+const BLOCK_SIZE: usize = 512;
+
+pub fn detect(data: &[u8]) -> bool {
+    data.get(257..262) == Some(b"ustar")
+}
+
+fn string(field: &[u8]) -> Result<&str> {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    str::from_utf8(&field[..end]).map_err(|e| anyhow!("Bad tar string: {e}"))
+}
+
+fn octal(field: &[u8]) -> Result<usize> {
+    let s = string(field)?.trim_matches(|c| c == ' ' || c == '\0');
+    if s.is_empty() { return Ok(0); }
+
+    usize::from_str_radix(s, 8).map_err(|e| anyhow!("Bad tar number: {e}"))
+}
+
+fn checksum(header: &[u8]) -> usize {
+    // The checksum field itself counts as spaces
+    header.iter()
+        .enumerate()
+        .map(|(i, &b)| if (148..156).contains(&i) { b' ' as usize } else { b as usize })
+        .sum()
+}
+
+fn align(offset: usize) -> usize {
+    offset.div_ceil(BLOCK_SIZE) * BLOCK_SIZE
+}
+
+// ustar: name[100] mode[8] uid[8] gid[8] size[12] mtime[12] chksum[8] typeflag[1] linkname[100] magic[6] ... prefix[155] at 345
+pub fn next(data: &'static [u8], mut offset: usize) -> Result<Option<(File<'static>, usize)>> {
+    loop {
+        let Some(header) = data.get(offset..offset + BLOCK_SIZE) else {
+            // A missing end-of-archive marker is tolerated
+            return Ok(None);
+        };
+
+        if header.iter().all(|&b| b == 0) { return Ok(None); }
+
+        if checksum(header) != octal(&header[148..156])? {
+            return Err(anyhow!("Bad tar checksum at {offset}"));
+        }
+
+        let name = string(&header[0..100])?;
+        let prefix = if detect(header) { string(&header[345..500])? } else { "" };
+        let mode = octal(&header[100..108])? as u32;
+        let size = octal(&header[124..136])?;
+
+        let data_start = offset + BLOCK_SIZE;
+        let file = data.get(data_start..data_start + size)
+            .ok_or(anyhow!("Truncated tar data for {name}"))?;
+        let next = align(data_start + size);
+
+        let prefix = normalize(prefix);
+        let name = normalize(name);
+        if name.is_empty() || name == "." {
+            offset = next;
+            continue;
+        }
+
+        let kind = match header[156] {
+            b'0' | 0 => Kind::File,
+            b'5'     => Kind::Directory,
+            _        => Kind::Other
+        };
+
+        return Ok(Some((File { prefix, name, kind, mode, data: file }, next)));
+    }
+}
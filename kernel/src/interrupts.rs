@@ -0,0 +1,127 @@
+pub mod pic;
+
+use log::{info, warn};
+use x86_64::instructions::port::Port;
+use x86_64::{PrivilegeLevel, VirtAddr};
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+
+use crate::{cpu, gdt, power, process, random, time};
+use crate::symbols::Symbol;
+use crate::drivers::keyboard;
+use pic::ChainedPics;
+
+pub const PIC_OFFSET: u8 = 0x20;
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+pub enum Irq {
+    Timer    = 0,
+    Keyboard = 1
+}
+
+impl Irq {
+    pub fn vector(self) -> u8 {
+        PIC_OFFSET + self as u8
+    }
+}
+
+static mut IDT: InterruptDescriptorTable = InterruptDescriptorTable::new();
+static mut PICS: ChainedPics = ChainedPics::new(PIC_OFFSET);
+// The FADT decides which line the SCI uses, usually 9
+static mut SCI: u8 = 0;
+
+pub fn init() {
+    info!("Initializing Interrupts..");
+
+    unsafe {
+        IDT.breakpoint.set_handler_fn(breakpoint_handler);
+        IDT.double_fault.set_handler_fn(double_fault_handler).set_stack_index(gdt::DOUBLE_FAULT_IST);
+        IDT.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+        IDT.general_protection_fault.set_handler_fn(general_protection_fault_handler);
+        IDT.page_fault.set_handler_fn(page_fault_handler);
+        IDT[Irq::Timer.vector()].set_handler_fn(timer_handler);
+        IDT[Irq::Keyboard.vector()].set_handler_fn(keyboard_handler);
+        IDT.load();
+
+        PICS.init();
+        PICS.unmask(Irq::Timer as u8);
+        PICS.unmask(Irq::Keyboard as u8);
+    }
+
+    time::init();
+    x86_64::instructions::interrupts::enable();
+
+    info!("Success");
+}
+
+pub fn enable_sci(line: u8) {
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        SCI = line;
+        IDT[PIC_OFFSET + line].set_handler_fn(sci_handler);
+        PICS.unmask(line);
+    });
+}
+
+fn end_of_interrupt(irq: Irq) {
+    unsafe { PICS.notify_end_of_interrupt(irq as u8); }
+}
+
+extern "x86-interrupt" fn breakpoint_handler(frame: InterruptStackFrame) {
+    warn!("Breakpoint\n{frame:#?}");
+}
+
+extern "x86-interrupt" fn double_fault_handler(frame: InterruptStackFrame, _code: u64) -> ! {
+    panic!("Double Fault in {}\n{frame:#?}", Symbol(frame.instruction_pointer.as_u64()));
+}
+
+fn from_user(frame: &InterruptStackFrame) -> bool {
+    frame.code_segment.rpl() == PrivilegeLevel::Ring3
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler(frame: InterruptStackFrame) {
+    if from_user(&frame) {
+        process::kill(format_args!("Invalid Opcode at {:?}", frame.instruction_pointer));
+    }
+
+    panic!("Invalid Opcode in {}\n{frame:#?}", Symbol(frame.instruction_pointer.as_u64()));
+}
+
+extern "x86-interrupt" fn general_protection_fault_handler(mut frame: InterruptStackFrame, code: u64) {
+    if let Some(fixup) = cpu::fixup(frame.instruction_pointer.as_u64()) {
+        unsafe { frame.as_mut().update(|frame| frame.instruction_pointer = VirtAddr::new(fixup)); }
+        return;
+    }
+
+    if from_user(&frame) {
+        process::kill(format_args!("General Protection Fault (0x{code:x}) at {:?}", frame.instruction_pointer));
+    }
+
+    panic!("General Protection Fault (0x{code:x}) in {}\n{frame:#?}", Symbol(frame.instruction_pointer.as_u64()));
+}
+
+extern "x86-interrupt" fn page_fault_handler(frame: InterruptStackFrame, code: PageFaultErrorCode) {
+    if from_user(&frame) {
+        process::kill(format_args!("Page Fault at {:?} ({code:?}) from {:?}", Cr2::read(), frame.instruction_pointer));
+    }
+
+    panic!("Page Fault at {:?} ({code:?}) in {}\n{frame:#?}", Cr2::read(), Symbol(frame.instruction_pointer.as_u64()));
+}
+
+extern "x86-interrupt" fn timer_handler(_frame: InterruptStackFrame) {
+    time::tick();
+    random::add_interrupt(Irq::Timer as u8);
+    end_of_interrupt(Irq::Timer);
+}
+
+extern "x86-interrupt" fn keyboard_handler(_frame: InterruptStackFrame) {
+    let scancode = unsafe { Port::<u8>::new(0x60).read() };
+    keyboard::push_scancode(scancode);
+    random::add_interrupt(Irq::Keyboard as u8);
+    end_of_interrupt(Irq::Keyboard);
+}
+
+extern "x86-interrupt" fn sci_handler(_frame: InterruptStackFrame) {
+    power::handle_sci();
+    unsafe { PICS.notify_end_of_interrupt(SCI); }
+}
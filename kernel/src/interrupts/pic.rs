@@ -0,0 +1,99 @@
+use x86_64::instructions::port::Port;
+
+const CMD_INIT: u8 = 0x11;
+const CMD_EOI:  u8 = 0x20;
+const MODE_8086: u8 = 0x01;
+
+struct Pic {
+    offset:  u8,
+    command: Port<u8>,
+    data:    Port<u8>
+}
+
+impl Pic {
+    const fn new(offset: u8, command: u16, data: u16) -> Pic {
+        Pic { offset, command: Port::new(command), data: Port::new(data) }
+    }
+
+    fn handles(&self, irq: u8) -> bool {
+        self.offset <= irq && irq < self.offset + 8
+    }
+}
+
+pub struct ChainedPics {
+    master: Pic,
+    slave:  Pic
+}
+
+impl ChainedPics {
+    pub const fn new(offset: u8) -> ChainedPics {
+        ChainedPics {
+            master: Pic::new(offset, 0x20, 0x21),
+            slave:  Pic::new(offset + 8, 0xa0, 0xa1)
+        }
+    }
+
+    /// # Safety
+    ///
+    /// Reprograms both controllers, interrupts must be disabled and the offsets free in the IDT.
+    pub unsafe fn init(&mut self) {
+        // Port 0x80 is unused and writing to it gives the PIC time to settle
+        let mut wait_port = Port::<u8>::new(0x80);
+        let mut wait = || wait_port.write(0);
+
+        self.master.command.write(CMD_INIT);
+        wait();
+        self.slave.command.write(CMD_INIT);
+        wait();
+
+        self.master.data.write(self.master.offset);
+        wait();
+        self.slave.data.write(self.slave.offset);
+        wait();
+
+        self.master.data.write(4);
+        wait();
+        self.slave.data.write(2);
+        wait();
+
+        self.master.data.write(MODE_8086);
+        wait();
+        self.slave.data.write(MODE_8086);
+        wait();
+
+        // Everything but the cascade line stays masked until a driver asks for it
+        self.master.data.write(!(1 << 2));
+        self.slave.data.write(0xff);
+    }
+
+    /// # Safety
+    ///
+    /// A handler must be installed for the line before it is unmasked.
+    pub unsafe fn unmask(&mut self, irq: u8) {
+        let (pic, line) = if irq < 8 { (&mut self.master, irq) } else { (&mut self.slave, irq - 8) };
+        let mask = pic.data.read();
+        pic.data.write(mask & !(1 << line));
+    }
+
+    /// # Safety
+    ///
+    /// Whatever waits on the line stops being woken.
+    pub unsafe fn mask(&mut self, irq: u8) {
+        let (pic, line) = if irq < 8 { (&mut self.master, irq) } else { (&mut self.slave, irq - 8) };
+        let mask = pic.data.read();
+        pic.data.write(mask | 1 << line);
+    }
+
+    /// # Safety
+    ///
+    /// Only from the handler of an interrupt on this line, or the next one is lost.
+    pub unsafe fn notify_end_of_interrupt(&mut self, irq: u8) {
+        let vector = self.master.offset + irq;
+
+        if self.slave.handles(vector) {
+            self.slave.command.write(CMD_EOI);
+        }
+
+        self.master.command.write(CMD_EOI);
+    }
+}
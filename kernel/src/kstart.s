@@ -0,0 +1,8 @@
+    .section .ltext.kstart, "ax"
+    .global kstart
+
+    # The bootloader jumps here with the BootInfo pointer in rdi, which is passed straight on to astart
+kstart:
+    lea rsp, [rip + _stack_top]
+    call astart
+    ud2
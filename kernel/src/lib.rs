@@ -1,5 +1,40 @@
-#![no_std]
+#![cfg_attr(any(not(test), target_os = "none"), no_std)]
+#![cfg_attr(all(test, target_os = "none"), no_main)]
+#![feature(abi_x86_interrupt)]
+#![feature(custom_test_frameworks)]
+#![test_runner(crate::testing::run)]
+#![reexport_test_harness_main = "test_main"]
 
+pub mod abi;
 pub mod acpi;
+pub mod backtrace;
+pub mod bootconfig;
+pub mod bootinfo;
+pub mod cmdline;
+pub mod cpu;
 pub mod drivers;
+pub mod fpu;
+pub mod gdt;
+pub mod hal;
+pub mod initrd;
+pub mod interrupts;
+pub mod loader;
+pub mod logger;
 pub mod memory;
+pub mod monitor;
+pub mod power;
+pub mod process;
+pub mod random;
+pub mod relocation;
+pub mod shell;
+pub mod symbols;
+pub mod syscall;
+pub mod task;
+pub mod time;
+
+#[cfg(test)]
+mod testing;
+
+// The entry point of both the kernel and the test kernel, each of which brings its own astart
+#[cfg(target_os = "none")]
+core::arch::global_asm!(include_str!("kstart.s"));
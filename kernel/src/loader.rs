@@ -0,0 +1,202 @@
+extern crate alloc;
+
+use alloc::vec::Vec;
+use anyhow::{anyhow, Result};
+use log::info;
+use elf::ElfBytes;
+use elf::abi::{EM_X86_64, ET_DYN, ET_EXEC, PF_W, PF_X, PT_INTERP, PT_LOAD, PT_PHDR};
+use elf::endian::LittleEndian;
+use elf::file::Class;
+use elf::segment::ProgramHeader;
+use x86_64::addr;
+use x86_64::structures::paging::PageTableFlags;
+
+use crate::{cmdline, memory, random, relocation};
+use crate::abi::{auxv, PAGE_SIZE};
+use crate::memory::USER_START;
+use crate::memory::address_space::AddressSpace;
+use crate::process::Process;
+
+pub const PIE_BASE:  u64 = 0x00004000_00000000;
+pub const PIE_SLOTS: u64 = 1 << 28;
+
+pub const STACK_TOP:  u64 = 0x00007fff_fffff000;
+pub const STACK_SIZE: u64 = 64 * 1024;
+
+struct Segment {
+    start: u64,
+    end:   u64,
+    phdr:  ProgramHeader
+}
+
+pub fn load(name: &str, bytes: &[u8], argv: &[&str], envp: &[&str]) -> Result<Process> {
+    info!("Loading {name}..");
+
+    let elf: ElfBytes<LittleEndian> = ElfBytes::minimal_parse(bytes)?;
+
+    if elf.ehdr.class != Class::ELF64 || elf.ehdr.e_machine != EM_X86_64 {
+        return Err(anyhow!("{name} is not an x86_64 ELF64 executable"));
+    }
+
+    let base = match elf.ehdr.e_type {
+        ET_EXEC => 0,
+        ET_DYN  => PIE_BASE + pie_slot() * PAGE_SIZE,
+        ty      => return Err(anyhow!("Unsupported ELF type {ty}"))
+    };
+
+    let phdrs = elf.segments()
+        .ok_or(anyhow!("{name} does not contain segments"))?
+        .into_iter()
+        .collect::<Vec<_>>();
+
+    if phdrs.iter().any(|phdr| phdr.p_type == PT_INTERP) {
+        return Err(anyhow!("{name} requests an interpreter, only static executables are supported"));
+    }
+
+    // Everything below USER_START is the kernel's, so the usual -Ttext 0x400000 cannot be honoured
+    let lowest = phdrs.iter().filter(|phdr| phdr.p_type == PT_LOAD).map(|phdr| phdr.p_vaddr).min();
+    if let Some(vaddr) = lowest.filter(|&vaddr| base == 0 && vaddr < USER_START) {
+        return Err(anyhow!("{name} is linked at 0x{vaddr:x}, link it at 0x{USER_START:x} or above, or build it as a PIE"));
+    }
+
+    let segments = segments(bytes, base, &phdrs)?;
+    let mut space = AddressSpace::new()?;
+
+    for segment in &segments {
+        let phdr = &segment.phdr;
+
+        let mut flags = PageTableFlags::empty();
+        if phdr.p_flags & PF_W != 0 { flags |= PageTableFlags::WRITABLE; }
+        if phdr.p_flags & PF_X == 0 { flags |= memory::no_execute(); }
+
+        // Fresh frames are zeroed, which takes care of the BSS past p_filesz
+        space.map_anonymous(segment.start, segment.end - segment.start, flags)?;
+        space.write(base + phdr.p_vaddr, elf.segment_data(phdr)?)?;
+    }
+
+    if base != 0 {
+        relocate(&elf, bytes, &mut space, base)?;
+    }
+
+    let phdr_addr = phdrs.iter()
+        .find(|phdr| phdr.p_type == PT_PHDR)
+        .map(|phdr| base + phdr.p_vaddr)
+        .or_else(|| {
+            // Without PT_PHDR the headers are only visible if a load segment covers them
+            let offset = elf.ehdr.e_phoff;
+            segments.iter()
+                .map(|segment| &segment.phdr)
+                .find(|phdr| phdr.p_offset <= offset && offset < phdr.p_offset + phdr.p_filesz)
+                .map(|phdr| base + phdr.p_vaddr + offset - phdr.p_offset)
+        })
+        .unwrap_or(0);
+
+    let entry = base.checked_add(elf.ehdr.e_entry).ok_or(anyhow!("{name} has its entry point out of range"))?;
+    let auxv = [
+        (auxv::AT_PHDR,   phdr_addr),
+        (auxv::AT_PHENT,  elf.ehdr.e_phentsize as u64),
+        (auxv::AT_PHNUM,  elf.ehdr.e_phnum as u64),
+        (auxv::AT_PAGESZ, PAGE_SIZE),
+        (auxv::AT_BASE,   0),
+        (auxv::AT_ENTRY,  entry)
+    ];
+
+    space.map_anonymous(STACK_TOP - STACK_SIZE, STACK_SIZE, PageTableFlags::WRITABLE | memory::no_execute())?;
+    let stack = build_stack(&mut space, argv, envp, &auxv)?;
+
+    info!("{name}: base 0x{base:x}, entry 0x{entry:x}");
+
+    Ok(Process::new(name, space, entry, stack))
+}
+
+// nokaslr pins programs to the first slot, same as the kernel
+fn pie_slot() -> u64 {
+    if cmdline::kaslr() { random::next_u64() % PIE_SLOTS } else { 0 }
+}
+
+fn segments(bytes: &[u8], base: u64, phdrs: &[ProgramHeader]) -> Result<Vec<Segment>> {
+    let mut segments = phdrs.iter()
+        .filter(|phdr| phdr.p_type == PT_LOAD && phdr.p_memsz > 0)
+        .map(|phdr| -> Result<Segment> {
+            if phdr.p_filesz > phdr.p_memsz {
+                return Err(anyhow!("Segment at 0x{:x} has more file data than memory", phdr.p_vaddr));
+            }
+
+            let file_end = phdr.p_offset.checked_add(phdr.p_filesz).ok_or(anyhow!("Segment offset overflows"))?;
+            if file_end > bytes.len() as u64 {
+                return Err(anyhow!("Segment at 0x{:x} extends past the end of the file", phdr.p_vaddr));
+            }
+
+            if phdr.p_align > 1 && (!phdr.p_align.is_power_of_two() || phdr.p_vaddr % phdr.p_align != phdr.p_offset % phdr.p_align) {
+                return Err(anyhow!("Segment at 0x{:x} is misaligned", phdr.p_vaddr));
+            }
+
+            let start = base.checked_add(phdr.p_vaddr).ok_or(anyhow!("Segment address overflows"))?;
+            let end = start.checked_add(phdr.p_memsz).ok_or(anyhow!("Segment size overflows"))?;
+
+            Ok(Segment {
+                start: addr::align_down(start, PAGE_SIZE),
+                end:   addr::align_up(end, PAGE_SIZE),
+                phdr:  *phdr
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    segments.sort_by_key(|segment| segment.start);
+
+    // Permissions are per page, so segments sharing a page cannot both be honoured
+    if let Some(pair) = segments.windows(2).find(|pair| pair[0].end > pair[1].start) {
+        return Err(anyhow!("Segments at 0x{:x} and 0x{:x} overlap", pair[0].phdr.p_vaddr, pair[1].phdr.p_vaddr));
+    }
+
+    Ok(segments)
+}
+
+fn relocate(elf: &ElfBytes<LittleEndian>, bytes: &[u8], space: &mut AddressSpace, base: u64) -> Result<()> {
+    for (offset, addend) in relocation::relative(elf, bytes)? {
+        let addr = base.checked_add(offset).ok_or(anyhow!("Relocation at 0x{offset:x} overflows"))?;
+        space.write(addr, &base.wrapping_add(addend).to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+fn build_stack(space: &mut AddressSpace, argv: &[&str], envp: &[&str], auxv: &[(u64, u64)]) -> Result<u64> {
+    let mut strings = Vec::new();
+    let mut offsets = Vec::new();
+
+    for s in argv.iter().chain(envp) {
+        offsets.push(strings.len() as u64);
+        strings.extend_from_slice(s.as_bytes());
+        strings.push(0);
+    }
+
+    let mut random = [0; 16];
+    random::fill_bytes(&mut random);
+
+    let strings_addr = STACK_TOP - strings.len() as u64;
+    let random_addr = addr::align_down(strings_addr - random.len() as u64, 16);
+
+    let mut words = Vec::new();
+    words.push(argv.len() as u64);
+    words.extend(offsets[..argv.len()].iter().map(|offset| strings_addr + offset));
+    words.push(0);
+    words.extend(offsets[argv.len()..].iter().map(|offset| strings_addr + offset));
+    words.push(0);
+    for (ty, value) in auxv.iter().chain(&[(auxv::AT_RANDOM, random_addr), (auxv::AT_NULL, 0)]) {
+        words.push(*ty);
+        words.push(*value);
+    }
+
+    let rsp = addr::align_down(random_addr - words.len() as u64 * 8, 16);
+    if STACK_TOP - rsp > STACK_SIZE {
+        return Err(anyhow!("Arguments do not fit on the initial stack"));
+    }
+
+    let words = words.iter().flat_map(|word| word.to_le_bytes()).collect::<Vec<u8>>();
+    space.write(strings_addr, &strings)?;
+    space.write(random_addr, &random)?;
+    space.write(rsp, &words)?;
+
+    Ok(rsp)
+}
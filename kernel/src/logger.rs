@@ -0,0 +1,166 @@
+extern crate alloc;
+
+use core::fmt::{self, Write};
+use alloc::vec::Vec;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use x86_64::instructions::interrupts;
+
+use crate::{print, time};
+
+// Enough for a full boot at debug level, older lines are dropped first
+const RING_SIZE: usize = 64 * 1024;
+
+struct Ring {
+    buf:     [u8; RING_SIZE],
+    head:    usize,
+    len:     usize,
+    wrapped: bool
+}
+
+impl Ring {
+    const fn new() -> Ring {
+        Ring { buf: [0; RING_SIZE], head: 0, len: 0, wrapped: false }
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.buf[(self.head + self.len) % RING_SIZE] = byte;
+
+        if self.len == RING_SIZE {
+            self.head = (self.head + 1) % RING_SIZE;
+            self.wrapped = true;
+        } else {
+            self.len += 1;
+        }
+    }
+
+    fn chunks(&self) -> (&[u8], &[u8]) {
+        let end = self.head + self.len;
+
+        if end <= RING_SIZE {
+            (&self.buf[self.head..end], &[])
+        } else {
+            (&self.buf[self.head..], &self.buf[..end - RING_SIZE])
+        }
+    }
+}
+
+impl Write for Ring {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.bytes().for_each(|byte| self.push(byte));
+        Ok(())
+    }
+}
+
+struct Filter {
+    default: LevelFilter,
+    modules: Vec<(&'static str, LevelFilter)>
+}
+
+impl Filter {
+    // "info,acpi:debug,drivers::keyboard:off", the first bare level is the default
+    fn parse(spec: &'static str) -> Filter {
+        let mut filter = Filter { default: LevelFilter::Info, modules: Vec::new() };
+
+        for part in spec.split(',').filter(|part| !part.is_empty()) {
+            match part.rsplit_once(':') {
+                Some((module, level)) => match level.parse() {
+                    Ok(level) => filter.modules.push((module, level)),
+                    Err(_)    => log::warn!("Ignoring invalid log level {part}")
+                },
+                None => match part.parse() {
+                    Ok(level) => filter.default = level,
+                    Err(_)    => log::warn!("Ignoring invalid log level {part}")
+                }
+            }
+        }
+
+        filter
+    }
+
+    // The longest matching module wins, so "acpi:off,acpi::pci:debug" does what it looks like
+    fn level(&self, target: &str) -> LevelFilter {
+        let target = target.strip_prefix("kernel::").unwrap_or(target);
+
+        self.modules.iter()
+            .filter(|(module, _)| {
+                target.strip_prefix(module).is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .max_by_key(|(module, _)| module.len())
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    fn max(&self) -> LevelFilter {
+        self.modules.iter().map(|(_, level)| *level).fold(self.default, Ord::max)
+    }
+}
+
+struct Logger;
+
+static LOGGER: Logger = Logger;
+static mut RING: Ring = Ring::new();
+static mut FILTER: Option<Filter> = None;
+static mut CONSOLE: bool = false;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match unsafe { FILTER.as_ref() } {
+            Some(filter) => metadata.level() <= filter.level(metadata.target()),
+            None         => metadata.level() <= Level::Info
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) { return; }
+
+        let uptime = time::uptime();
+        let target = record.target().strip_prefix("kernel::").unwrap_or(record.target());
+        let line = format_args!(
+            "[{:>5}.{:03}] {:<5} {target}: {}\n",
+            uptime.as_secs(), uptime.subsec_millis(), record.level(), record.args()
+        );
+
+        interrupts::without_interrupts(|| unsafe {
+            let _ = RING.write_fmt(line);
+            if CONSOLE { print!("{line}"); }
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+// Messages are only kept in the ring buffer until the console is attached
+pub fn init(spec: &'static str) {
+    // Installed first with the default filter, parsing the spec can already have something to say
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(LevelFilter::Info);
+
+    let filter = Filter::parse(spec);
+    log::set_max_level(filter.max());
+
+    unsafe { FILTER = Some(filter); }
+}
+
+pub fn attach_console() {
+    interrupts::without_interrupts(|| unsafe {
+        if CONSOLE { return; }
+
+        CONSOLE = true;
+        dmesg(|text| print!("{text}"));
+    });
+}
+
+// Replays everything still in the ring buffer, oldest first
+pub fn dmesg(mut f: impl FnMut(&str)) {
+    let ring = unsafe { &RING };
+    let (first, second) = ring.chunks();
+
+    // After wrapping the oldest line is cut somewhere in the middle
+    let skip = if ring.wrapped { first.iter().position(|&b| b == b'\n').map_or(0, |i| i + 1) } else { 0 };
+
+    for chunk in [&first[skip..], second] {
+        for part in chunk.utf8_chunks() {
+            f(part.valid());
+        }
+    }
+}
@@ -4,39 +4,100 @@
 mod alloc;
 
 use core::panic::PanicInfo;
+use log::{error, warn};
 
+use kernel::acpi::aml::Aml;
+use kernel::acpi::numa::Topology;
 use kernel::acpi::pci::PCI;
-use kernel::{print, println};
+use kernel::{acpi, backtrace, cmdline, cpu, fpu, gdt, initrd, interrupts, loader, logger, memory, monitor, power, println, random, shell, symbols, syscall};
 use kernel::acpi::tables::ACPI;
-use kernel::drivers::keyboard::Keyboard;
-use kernel::drivers::video::framebuffer::Framebuffer;
+use kernel::drivers::{console, keyboard};
+use kernel::bootinfo::BootInfo;
 use kernel::drivers::video::printer::{Color, Printer};
-use kernel::memory::MemoryPool;
+use kernel::drivers::video::splash::{self, Stage};
+use kernel::initrd::Initrd;
+use kernel::task::executor::Executor;
 
 #[no_mangle]
 #[link_section = ".ltext.astart"]
-extern "sysv64" fn astart(acpi_addr: u64, _free_ptr: *const MemoryPool, _free_size: usize, fb: Framebuffer<'static>) -> ! {
-    Printer::init_global(fb, kernel::drivers::video::fonts::SF_PRO, 30.0, Color::new(255.0, 255.0, 255.0));
+extern "sysv64" fn astart(info: &'static mut BootInfo) -> ! {
+    cmdline::init(info.cmdline());
+    logger::init(cmdline::log_filter());
+    symbols::init(info.symtab, info.strtab);
 
-    let acpi = ACPI::parse(acpi_addr).unwrap();
-    let pci = PCI::enumerate(&acpi).unwrap();
+    let (r, g, b) = cmdline::color();
+    Printer::init_global(info.framebuffer.take(), cmdline::font(), cmdline::font_size(), Color::new(r, g, b));
+    console::init(cmdline::console());
+    logger::attach_console();
+    cmdline::report();
+    cpu::init();
 
-    let mut kb = Keyboard::new();
+    unsafe { memory::init(info.free_ptr, info.free_size); }
+    fpu::init();
+    random::init(info.seed());
+    gdt::init();
 
-    loop {
-        if let Some(x) = kb.read_char().unwrap() {
-            print!("{x}");
+    if let Err(e) = Initrd::init_global(info.initrd) {
+        error!("{e}");
+    }
+
+    splash::init(info.splash());
+    splash::progress(Stage::Memory);
+
+    acpi::mapper::init(info.firmware());
+    ACPI::init_global(info.acpi).unwrap();
+    splash::progress(Stage::Acpi);
+    PCI::init_global(acpi::tables::get().unwrap()).unwrap();
+
+    if let Err(e) = Topology::init_global(acpi::tables::get().unwrap()) {
+        warn!("{e}");
+    }
+
+    splash::progress(Stage::Pci);
+
+    keyboard::init();
+    interrupts::init();
+    syscall::init();
+
+    if let Err(e) = Aml::init_global(acpi::tables::get().unwrap()) {
+        error!("{e}");
+    }
+
+    if let Err(e) = power::init(acpi::tables::get().unwrap()) {
+        error!("{e}");
+    }
+
+    splash::progress(Stage::Drivers);
+    splash::finish();
+
+    if let Some(init) = initrd::read("init") {
+        match loader::load("init", init, &["init"], &[]) {
+            Ok(mut process) => { process.run(); }
+            Err(e)          => error!("{e}")
         }
     }
-}
 
-#[used]
-#[link_section = ".ltext.kstart"]
-static KSTART: [u8; 16] = [0x48, 0xc7, 0xc4, 0xf0, 0xff, 0xff, 0xff, 0x48, 0xc7, 0xc0, 0x00, 0x00, 0x00, 0xaf, 0xff, 0xe0];
+    shell::builtins::register();
+    acpi::register_commands();
+    cpu::register_commands();
+    monitor::register();
+    power::register_commands();
+    random::register_commands();
+
+    let mut executor = Executor::new();
+    executor.spawn(shell::run());
+    executor.spawn(power::power_button());
+    executor.run();
+}
 
 #[panic_handler]
 fn panic_handler(info: &PanicInfo) -> ! {
+    // A panic before the console came up would otherwise take the whole log with it
+    logger::attach_console();
+    splash::finish();
+
     println!("[Panic]: {}", info);
+    backtrace::print();
 
     loop {}
 }
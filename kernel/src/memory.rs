@@ -1,16 +1,80 @@
+pub mod address_space;
+pub mod frames;
+
 extern crate alloc;
 
+use core::{ptr, slice};
 use core::alloc::Layout;
 use alloc::alloc::alloc;
 use anyhow::{anyhow, Error, Result};
 use x86_64::structures::paging::mapper::MapToError;
 use x86_64::{addr, PhysAddr, VirtAddr};
-use x86_64::registers::control::Cr3;
+use x86_64::instructions::tlb;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr3};
+use x86_64::registers::model_specific::{Efer, EferFlags};
 use x86_64::structures::paging::{FrameAllocator, Mapper, OffsetPageTable, Page, PageSize, PageTable, PageTableFlags, PhysFrame, Size2MiB, Size4KiB, Translate};
 
-pub const KERNEL_START: u64 = 0xffffffff_af000000;
-pub const KERNEL_END:   u64 = 0xffffffff_ffffffff;
-pub const KERNEL_SIZE:  u64 = KERNEL_END - KERNEL_START + 1;
+// The kernel image, heap and stack, see link.ld
+pub const KERNEL_SIZE: u64 = 0x51000000;
+
+// The bootloader picks a random 2MB-aligned kernel base inside this window, below the ACPI and PCI mappings
+pub const KASLR_START: u64 = 0xffffff80_00000000;
+pub const KASLR_END:   u64 = 0xfffffffe_00000000;
+
+// The first 512GB stay identity mapped for the kernel, user space starts at the next PML4 entry
+pub const USER_START: u64 = 0x00000080_00000000;
+pub const USER_END:   u64 = 0x00008000_00000000;
+
+static mut KERNEL_PML4: Option<PhysFrame> = None;
+
+/// # Safety
+///
+/// Once, with the bootloader's page table still active and the free list it left behind.
+pub unsafe fn init(free_ptr: *const MemoryPool, free_size: usize) {
+    KERNEL_PML4 = Some(Cr3::read().0);
+    frames::init(slice::from_raw_parts(free_ptr, free_size));
+}
+
+pub fn kernel_base() -> u64 {
+    extern "C" {
+        #[link_name = "_kernel_begin"]
+        static KERNEL_BEGIN: u8;
+    }
+
+    ptr::addr_of!(KERNEL_BEGIN) as u64
+}
+
+pub fn kernel_pml4() -> PhysFrame {
+    unsafe { KERNEL_PML4.expect("Memory is not initialized") }
+}
+
+pub fn translate(addr: u64) -> Option<PhysAddr> {
+    let (ptframe, _) = Cr3::read();
+
+    unsafe {
+        let pt = &mut *(ptframe.start_address().as_u64() as *mut PageTable);
+        OffsetPageTable::new(pt, VirtAddr::zero()).translate_addr(VirtAddr::try_new(addr).ok()?)
+    }
+}
+
+/// Turns on NX and write protection, without WP the kernel can write straight through read-only mappings.
+///
+/// # Safety
+///
+/// Every existing writable mapping the kernel relies on must already be marked as such.
+pub unsafe fn enable_protection() {
+    Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+    Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT));
+}
+
+// The NX bit is reserved (and faults) until EFER.NXE is set
+pub fn no_execute() -> PageTableFlags {
+    if Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE) {
+        PageTableFlags::NO_EXECUTE
+    } else {
+        PageTableFlags::empty()
+    }
+}
 
 #[derive(Clone, Copy)]
 pub struct MemoryPool {
@@ -38,7 +102,10 @@ impl MemoryPool {
         self.end - self.start
     }
 
-    pub unsafe fn map<A: FrameAllocator<Size4KiB>>(&self, page_table: &mut OffsetPageTable, falloc: &mut A, vstart: u64) -> Result<()> {
+    /// # Safety
+    ///
+    /// The pool must not back anything that the new mapping could corrupt, and `vstart` must be unused.
+    pub unsafe fn map<A: FrameAllocator<Size4KiB>>(&self, page_table: &mut OffsetPageTable, falloc: &mut A, vstart: u64, flags: PageTableFlags) -> Result<()> {
         let pstart = self.start;
         let pend = self.end - 1;
         let vend = vstart + self.size() - 1;
@@ -56,7 +123,7 @@ impl MemoryPool {
         for (page, frame) in pages.zip(frames) {
             let mut map_page = || -> Result<(), MapToError<_>> {
                 page_table
-                    .map_to(page, frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE, falloc)?
+                    .map_to(page, frame, flags | PageTableFlags::PRESENT, falloc)?
                     .flush();
 
                 Ok(())
@@ -95,14 +162,56 @@ unsafe impl<S: PageSize> FrameAllocator<S> for GlobalFrameAllocator {
 
 // TODO: maybe store page table in a static struct
 
+/// # Safety
+///
+/// As for [`MemoryPool::map`], in the active page table.
 pub unsafe fn map(pool: MemoryPool, virt: u64) {
+    map_flags(pool, virt, PageTableFlags::WRITABLE | no_execute()).unwrap();
+}
+
+/// # Safety
+///
+/// As for [`MemoryPool::map`], in the active page table.
+pub unsafe fn map_flags(pool: MemoryPool, virt: u64, flags: PageTableFlags) -> Result<()> {
+    let (ptframe, _) = Cr3::read();
+    let pt = &mut *(ptframe.start_address().as_u64() as *mut PageTable);
+    let mut page_table = OffsetPageTable::new(pt, VirtAddr::zero());
+
+    pool.map(&mut page_table, &mut GlobalFrameAllocator, virt, flags)
+}
+
+/// Maps at 4KiB granularity so neighbouring sections can carry different permissions.
+///
+/// # Safety
+///
+/// The physical range must be safe to alias and the virtual range unused in the active page table.
+pub unsafe fn map_pages(pstart: u64, vstart: u64, size: u64, flags: PageTableFlags) -> Result<()> {
     let (ptframe, _) = Cr3::read();
     let pt = &mut *(ptframe.start_address().as_u64() as *mut PageTable);
     let mut page_table = OffsetPageTable::new(pt, VirtAddr::zero());
 
-    pool.map(&mut page_table, &mut GlobalFrameAllocator, virt).unwrap();
+    if size == 0 { return Ok(()); }
+
+    let vfirst = Page::<Size4KiB>::containing_address(VirtAddr::new(vstart));
+    let vlast = Page::containing_address(VirtAddr::new(vstart + size - 1));
+    let pfirst = PhysFrame::containing_address(PhysAddr::new(pstart));
+
+    let table_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let flags = flags | PageTableFlags::PRESENT;
+
+    for (i, page) in Page::range_inclusive(vfirst, vlast).enumerate() {
+        page_table
+            .map_to_with_table_flags(page, pfirst + i as u64, flags, table_flags, &mut GlobalFrameAllocator)
+            .map_err(|e| anyhow!("0x{:x}: {e:?}", page.start_address()))?
+            .flush();
+    }
+
+    Ok(())
 }
 
+/// # Safety
+///
+/// Nothing may reference the large pages afterwards.
 pub unsafe fn unmap(vstart: u64, count: usize) {
     let (ptframe, _) = Cr3::read();
     let pt = &mut *(ptframe.start_address().as_u64() as *mut PageTable);
@@ -115,3 +224,108 @@ pub unsafe fn unmap(vstart: u64, count: usize) {
             .flush();
     }
 }
+
+/// The counterpart of [`map_pages`], the page tables themselves stay around for the next mapping.
+///
+/// # Safety
+///
+/// Nothing may reference the range afterwards.
+pub unsafe fn unmap_pages(vstart: u64, size: u64) -> Result<()> {
+    let (ptframe, _) = Cr3::read();
+    let pt = &mut *(ptframe.start_address().as_u64() as *mut PageTable);
+    let mut page_table = OffsetPageTable::new(pt, VirtAddr::zero());
+
+    if size == 0 { return Ok(()); }
+
+    let vfirst = Page::<Size4KiB>::containing_address(VirtAddr::new(vstart));
+    let vlast = Page::containing_address(VirtAddr::new(vstart + size - 1));
+
+    for page in Page::range_inclusive(vfirst, vlast) {
+        page_table
+            .unmap(page)
+            .map_err(|e| anyhow!("0x{:x}: {e:?}", page.start_address()))?
+            .1
+            .flush();
+    }
+
+    Ok(())
+}
+
+/// Once the 4KiB pages under a 2MiB slot are gone their table is dropped, so the slot can take a large page again.
+/// Page tables come from the kernel heap, which never reuses memory, so the table itself is left behind.
+///
+/// # Safety
+///
+/// Nothing may be mapped through the table any more, and no other page table may share it.
+pub unsafe fn release_table(vaddr: u64) {
+    let addr = VirtAddr::new(vaddr);
+    let mut table = &mut *(Cr3::read().0.start_address().as_u64() as *mut PageTable);
+
+    for index in [addr.p4_index(), addr.p3_index()] {
+        let entry = &table[index];
+        if entry.is_unused() || entry.flags().contains(PageTableFlags::HUGE_PAGE) { return; }
+
+        table = &mut *(entry.addr().as_u64() as *mut PageTable);
+    }
+
+    let entry = &mut table[addr.p2_index()];
+    if entry.is_unused() || entry.flags().contains(PageTableFlags::HUGE_PAGE) { return; }
+
+    let p1 = &*(entry.addr().as_u64() as *const PageTable);
+    if p1.iter().all(|e| e.is_unused()) {
+        entry.set_unused();
+        tlb::flush(addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Far away from the kernel window and the ACPI/PCI mappings
+    #[cfg(target_os = "none")]
+    const SCRATCH: u64 = 0xffffc000_00000000;
+
+    #[test_case]
+    fn align_rounds_outwards() {
+        let pool = MemoryPool::align(0x1234, 0x200001);
+        assert_eq!(pool.start, 0);
+        assert_eq!(pool.end, 0x400000);
+        assert_eq!(pool.size(), 0x400000);
+    }
+
+    #[test_case]
+    fn align_keeps_aligned_bounds() {
+        let pool = MemoryPool::align(0x200000, 0x600000);
+        assert_eq!(pool.start, 0x200000);
+        assert_eq!(pool.end, 0x600000);
+    }
+
+    #[test_case]
+    fn single_covers_one_large_page() {
+        let pool = MemoryPool::single(0x40000000);
+        assert_eq!(pool.start, 0x40000000);
+        assert_eq!(pool.size(), Size2MiB::SIZE);
+    }
+
+    #[test_case]
+    #[cfg(target_os = "none")]
+    fn map_aliases_physical_memory() {
+        let pool = frames::pools().first().map(|pool| MemoryPool::single(pool.start)).expect("No free memory");
+
+        unsafe {
+            map(pool, SCRATCH);
+
+            let alias = SCRATCH as *mut u64;
+            let phys = pool.start as *mut u64;
+
+            alias.write_volatile(0x1122334455667788);
+            assert_eq!(phys.read_volatile(), 0x1122334455667788);
+
+            phys.add(512).write_volatile(0xdeadbeef);
+            assert_eq!(alias.add(512).read_volatile(), 0xdeadbeef);
+
+            unmap(SCRATCH, 1);
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use anyhow::{anyhow, Result};
+use x86_64::{PhysAddr, VirtAddr};
+use x86_64::registers::control::{Cr3, Cr3Flags};
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTable, PageTableFlags, PhysFrame, Size4KiB, Translate};
+use x86_64::structures::paging::mapper::TranslateResult;
+
+use crate::memory::{self, USER_END, USER_START};
+use crate::memory::frames::PhysFrameAllocator;
+
+const USER_PML4: core::ops::Range<usize> = (USER_START >> 39) as usize .. (USER_END >> 39) as usize;
+// A SYSCALL from the very last page leaves a non-canonical return address, which SYSRET faults on in ring 0
+const USER_LIMIT: u64 = USER_END - Size4KiB::SIZE;
+
+pub struct AddressSpace {
+    pml4: PhysFrame
+}
+
+impl AddressSpace {
+    pub fn new() -> Result<AddressSpace> {
+        let pml4 = PhysFrameAllocator.allocate_frame().ok_or(anyhow!("Unable to allocate Page Table"))?;
+        let table = unsafe { &mut *(pml4.start_address().as_u64() as *mut PageTable) };
+        table.zero();
+
+        // Sharing the kernel's top-level entries shares everything below them as well
+        let kernel = unsafe { &*(memory::kernel_pml4().start_address().as_u64() as *const PageTable) };
+        for (i, entry) in kernel.iter().enumerate() {
+            if !USER_PML4.contains(&i) && !entry.is_unused() { table[i] = entry.clone(); }
+        }
+
+        Ok(AddressSpace { pml4 })
+    }
+
+    pub fn pml4(&self) -> PhysFrame {
+        self.pml4
+    }
+
+    fn page_table(&self) -> OffsetPageTable<'_> {
+        unsafe {
+            let table = &mut *(self.pml4.start_address().as_u64() as *mut PageTable);
+            OffsetPageTable::new(table, VirtAddr::zero())
+        }
+    }
+
+    pub fn check_user(start: u64, size: u64) -> Result<()> {
+        let end = start.checked_add(size).ok_or(anyhow!("Region 0x{start:x}+0x{size:x} overflows"))?;
+
+        if start < USER_START || end > USER_LIMIT {
+            return Err(anyhow!("Region 0x{start:x} -- 0x{end:x} is outside of user space"));
+        }
+
+        Ok(())
+    }
+
+    pub fn map_frame(&mut self, page: Page, frame: PhysFrame, flags: PageTableFlags) -> Result<()> {
+        Self::check_user(page.start_address().as_u64(), Size4KiB::SIZE)?;
+
+        let table_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+        let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+
+        unsafe {
+            self.page_table()
+                .map_to_with_table_flags(page, frame, flags, table_flags, &mut PhysFrameAllocator)
+                .map_err(|e| anyhow!("{e:?}"))?
+                .ignore();
+        }
+
+        Ok(())
+    }
+
+    pub fn map_anonymous(&mut self, start: u64, size: u64, flags: PageTableFlags) -> Result<()> {
+        Self::check_user(start, size)?;
+        if size == 0 { return Ok(()); }
+
+        let first = Page::<Size4KiB>::containing_address(VirtAddr::new(start));
+        let last = Page::containing_address(VirtAddr::new(start + size - 1));
+
+        for page in Page::range_inclusive(first, last) {
+            let mapped = PhysFrameAllocator.allocate_frame()
+                .ok_or(anyhow!("Out of physical memory"))
+                .and_then(|frame| {
+                    unsafe { core::ptr::write_bytes(frame.start_address().as_u64() as *mut u8, 0, Size4KiB::SIZE as usize); }
+
+                    self.map_frame(page, frame, flags).inspect_err(|_| unsafe { PhysFrameAllocator.deallocate_frame(frame); })
+                });
+
+            // Only what this call mapped is taken down again, the page in the way may belong to someone else
+            if let Err(e) = mapped {
+                self.unmap(first.start_address().as_u64(), page.start_address() - first.start_address())?;
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn unmap(&mut self, start: u64, size: u64) -> Result<()> {
+        Self::check_user(start, size)?;
+        if size == 0 { return Ok(()); }
+
+        let first = Page::<Size4KiB>::containing_address(VirtAddr::new(start));
+        let last = Page::containing_address(VirtAddr::new(start + size - 1));
+        let active = Cr3::read().0 == self.pml4;
+        let mut table = self.page_table();
+
+        for page in Page::range_inclusive(first, last) {
+            let Ok((frame, flush)) = table.unmap(page) else { continue; };
+
+            if active { flush.flush(); } else { flush.ignore(); }
+            unsafe { PhysFrameAllocator.deallocate_frame(frame); }
+        }
+
+        Ok(())
+    }
+
+    pub fn translate(&self, addr: u64) -> Option<(PhysAddr, PageTableFlags)> {
+        match self.page_table().translate(VirtAddr::try_new(addr).ok()?) {
+            TranslateResult::Mapped { frame, offset, flags } => Some((frame.start_address() + offset, flags)),
+            _                                                 => None
+        }
+    }
+
+    // Writes through the physical mapping, so read-only user pages can be filled as well
+    pub fn write(&mut self, addr: u64, data: &[u8]) -> Result<()> {
+        let mut done = 0;
+
+        while done < data.len() {
+            let vaddr = addr + done as u64;
+            let (phys, _) = self.translate(vaddr).ok_or(anyhow!("0x{vaddr:x} is not mapped"))?;
+
+            let chunk = ((Size4KiB::SIZE - vaddr % Size4KiB::SIZE) as usize).min(data.len() - done);
+            unsafe { core::ptr::copy_nonoverlapping(data[done..].as_ptr(), phys.as_u64() as *mut u8, chunk); }
+
+            done += chunk;
+        }
+
+        Ok(())
+    }
+
+    // The highest page in the range that is mapped, a search for free space can carry on above it
+    pub fn last_mapped(&self, start: u64, size: u64) -> Option<u64> {
+        (0..size.div_ceil(Size4KiB::SIZE))
+            .rev()
+            .map(|i| start + i * Size4KiB::SIZE)
+            .find(|&page| self.translate(page).is_some())
+    }
+
+    pub fn is_mapped(&self, start: u64, size: u64, flags: PageTableFlags) -> bool {
+        if Self::check_user(start, size).is_err() { return false; }
+        if size == 0 { return true; }
+
+        let first = Page::<Size4KiB>::containing_address(VirtAddr::new(start));
+        let last = Page::containing_address(VirtAddr::new(start + size - 1));
+        let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+
+        Page::range_inclusive(first, last)
+            .all(|page| {
+                self.translate(page.start_address().as_u64())
+                    .is_some_and(|(_, f)| f.contains(flags))
+            })
+    }
+
+    /// # Safety
+    ///
+    /// The kernel keeps running from the shared upper half, whatever user memory it still
+    /// references belongs to this space afterwards.
+    pub unsafe fn activate(&self) {
+        Cr3::write(self.pml4, Cr3Flags::empty());
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        if Cr3::read().0 == self.pml4 {
+            unsafe { Cr3::write(memory::kernel_pml4(), Cr3Flags::empty()); }
+        }
+
+        unsafe {
+            let table = |frame: PhysFrame| &*(frame.start_address().as_u64() as *const PageTable);
+            let pml4 = table(self.pml4);
+
+            for l4 in pml4.iter().skip(USER_PML4.start).take(USER_PML4.len()).filter_map(|e| e.frame().ok()) {
+                for l3 in table(l4).iter().filter_map(|e| e.frame().ok()) {
+                    for l2 in table(l3).iter().filter_map(|e| e.frame().ok()) {
+                        for l1 in table(l2).iter().filter_map(|e| e.frame().ok()) {
+                            PhysFrameAllocator.deallocate_frame(l1);
+                        }
+                        PhysFrameAllocator.deallocate_frame(l2);
+                    }
+                    PhysFrameAllocator.deallocate_frame(l3);
+                }
+                PhysFrameAllocator.deallocate_frame(l4);
+            }
+
+            PhysFrameAllocator.deallocate_frame(self.pml4);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn last_user_page_is_left_out() {
+        assert!(AddressSpace::check_user(USER_START, Size4KiB::SIZE).is_ok());
+        assert!(AddressSpace::check_user(USER_LIMIT - Size4KiB::SIZE, Size4KiB::SIZE).is_ok());
+        assert!(AddressSpace::check_user(USER_LIMIT, Size4KiB::SIZE).is_err());
+        assert!(AddressSpace::check_user(USER_START - Size4KiB::SIZE, Size4KiB::SIZE).is_err());
+        assert!(AddressSpace::check_user(u64::MAX, 2).is_err());
+    }
+}
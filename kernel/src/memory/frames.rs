@@ -0,0 +1,90 @@
+extern crate alloc;
+
+use core::ptr;
+use alloc::vec::Vec;
+use x86_64::PhysAddr;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size4KiB};
+
+use crate::memory::MemoryPool;
+
+// Physical memory is identity mapped, so freed frames can hold the free list themselves
+
+struct Frames {
+    pools:     Vec<MemoryPool>,
+    pool:      usize,
+    next:      u64,
+    free_list: Option<PhysFrame>,
+    allocated: u64
+}
+
+static mut FRAMES: Option<Frames> = None;
+
+/// # Safety
+///
+/// The pools must be free memory that stays identity mapped and is handed out by nothing else.
+pub unsafe fn init(pools: &[MemoryPool]) {
+    // A copy of its own, the bootloader's list is not ours to reorder
+    let pools = pools.to_vec();
+    let next = pools.first().map(|pool| pool.start).unwrap_or(0);
+
+    FRAMES = Some(Frames { pools, pool: 0, next, free_list: None, allocated: 0 });
+}
+
+pub fn pools() -> &'static [MemoryPool] {
+    unsafe { FRAMES.as_ref().map(|frames| frames.pools.as_slice()).unwrap_or(&[]) }
+}
+
+// Pools the allocator has not reached yet are used cheapest first, the one in use is finished first
+pub fn prefer(mut cost: impl FnMut(&MemoryPool) -> u8) {
+    let Some(frames) = (unsafe { FRAMES.as_mut() }) else { return; };
+
+    if let Some(rest) = frames.pools.get_mut(frames.pool + 1..) {
+        rest.sort_unstable_by_key(|pool| (cost(pool), pool.start));
+    }
+}
+
+pub fn total() -> u64 {
+    pools().iter().map(MemoryPool::size).sum()
+}
+
+pub fn allocated() -> u64 {
+    unsafe { FRAMES.as_ref().map(|frames| frames.allocated * Size4KiB::SIZE).unwrap_or(0) }
+}
+
+pub struct PhysFrameAllocator;
+
+unsafe impl FrameAllocator<Size4KiB> for PhysFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let frames = unsafe { FRAMES.as_mut()? };
+
+        if let Some(frame) = frames.free_list {
+            frames.free_list = unsafe { ptr::read(frame.start_address().as_u64() as *const Option<PhysFrame>) };
+            frames.allocated += 1;
+            return Some(frame);
+        }
+
+        while let Some(pool) = frames.pools.get(frames.pool) {
+            if frames.next + Size4KiB::SIZE <= pool.end {
+                let frame = PhysFrame::containing_address(PhysAddr::new(frames.next));
+                frames.next += Size4KiB::SIZE;
+                frames.allocated += 1;
+                return Some(frame);
+            }
+
+            frames.pool += 1;
+            frames.next = frames.pools.get(frames.pool).map(|pool| pool.start).unwrap_or(0);
+        }
+
+        None
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for PhysFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let Some(frames) = FRAMES.as_mut() else { return; };
+
+        ptr::write(frame.start_address().as_u64() as *mut Option<PhysFrame>, frames.free_list);
+        frames.free_list = Some(frame);
+        frames.allocated -= 1;
+    }
+}
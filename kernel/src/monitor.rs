@@ -0,0 +1,251 @@
+use core::arch::asm;
+use core::fmt::Write;
+use anyhow::anyhow;
+use x86_64::instructions::segmentation::{Segment, CS, DS, ES, FS, GS, SS};
+use x86_64::instructions::tables::{sgdt, sidt};
+use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+use x86_64::registers::model_specific::Efer;
+use x86_64::registers::rflags;
+use x86_64::structures::paging::{PageTable, PageTableFlags};
+
+use crate::{cpu, memory};
+use crate::hal::{PortIo, Ports};
+use crate::memory::frames;
+use crate::shell::{self, Args, Command, Error};
+
+const DEFAULT_LENGTH: u64 = 256;
+// The shell cannot be interrupted, a dump stays short enough to scroll past
+const MAX_LENGTH:     u64 = 4 * 4096;
+
+// Debugging commands for poking at the machine from the shell
+pub fn register() {
+    shell::register(Command { name: "x",     usage: "x <addr> [len]",          help: "hex dump virtual memory",                   run: x });
+    shell::register(Command { name: "xp",    usage: "xp <addr> [len]",         help: "hex dump physical memory",                  run: xp });
+    shell::register(Command { name: "pt",    usage: "pt <addr>",               help: "walk the page tables for a virtual address", run: pt });
+    shell::register(Command { name: "mem",   usage: "mem",                     help: "list physical memory pools and usage",      run: mem });
+    shell::register(Command { name: "inb",   usage: "inb <port>",              help: "read an 8-bit I/O port",                    run: port_in });
+    shell::register(Command { name: "inw",   usage: "inw <port>",              help: "read a 16-bit I/O port",                    run: port_in });
+    shell::register(Command { name: "inl",   usage: "inl <port>",              help: "read a 32-bit I/O port",                    run: port_in });
+    shell::register(Command { name: "outb",  usage: "outb <port> <val>",       help: "write an 8-bit I/O port",                   run: port_out });
+    shell::register(Command { name: "outw",  usage: "outw <port> <val>",       help: "write a 16-bit I/O port",                   run: port_out });
+    shell::register(Command { name: "outl",  usage: "outl <port> <val>",       help: "write a 32-bit I/O port",                   run: port_out });
+    shell::register(Command { name: "rdmsr", usage: "rdmsr <msr>",             help: "read a model specific register",            run: rdmsr });
+    shell::register(Command { name: "wrmsr", usage: "wrmsr <msr> <val>",       help: "write a model specific register",           run: wrmsr });
+    shell::register(Command { name: "regs",  usage: "regs",                    help: "show control, segment and table registers", run: regs });
+}
+
+fn x(args: &mut Args, out: &mut dyn Write) -> Result<(), Error> {
+    let addr = args.number("addr")?;
+    let len = args.optional_number()?.unwrap_or(DEFAULT_LENGTH);
+    args.end()?;
+
+    dump(out, addr, len)
+}
+
+// Physical memory is identity mapped below user space
+fn xp(args: &mut Args, out: &mut dyn Write) -> Result<(), Error> {
+    let addr = args.number("addr")?;
+    let len = args.optional_number()?.unwrap_or(DEFAULT_LENGTH);
+    args.end()?;
+
+    if addr >= memory::USER_START { return Err(anyhow!("0x{addr:x} is outside of the identity mapping").into()); }
+
+    dump(out, addr, len)
+}
+
+// The command name carries the access width
+fn port_in(args: &mut Args, out: &mut dyn Write) -> Result<(), Error> {
+    let width = args.command().chars().last();
+    let port = args.number("port")?;
+    args.end()?;
+
+    unsafe {
+        match width {
+            Some('b') => writeln!(out, "0x{:02x}", Ports.read_u8(port))?,
+            Some('w') => writeln!(out, "0x{:04x}", Ports.read_u16(port))?,
+            _         => writeln!(out, "0x{:08x}", Ports.read_u32(port))?
+        }
+    }
+
+    Ok(())
+}
+
+fn port_out(args: &mut Args, _: &mut dyn Write) -> Result<(), Error> {
+    let width = args.command().chars().last();
+    let port = args.number("port")?;
+
+    unsafe {
+        match width {
+            Some('b') => Ports.write_u8(port, args.number("val")?),
+            Some('w') => Ports.write_u16(port, args.number("val")?),
+            _         => Ports.write_u32(port, args.number("val")?)
+        }
+    }
+
+    args.end()
+}
+
+fn rdmsr(args: &mut Args, out: &mut dyn Write) -> Result<(), Error> {
+    let msr = args.number("msr")?;
+    args.end()?;
+
+    let value = cpu::read_msr(msr).ok_or(anyhow!("MSR 0x{msr:x} does not exist"))?;
+    writeln!(out, "0x{value:016x}")?;
+
+    Ok(())
+}
+
+fn wrmsr(args: &mut Args, _: &mut dyn Write) -> Result<(), Error> {
+    let msr = args.number("msr")?;
+    let value = args.number("val")?;
+    args.end()?;
+
+    if !unsafe { cpu::write_msr(msr, value) } {
+        return Err(anyhow!("MSR 0x{msr:x} does not exist or rejected 0x{value:x}").into());
+    }
+
+    Ok(())
+}
+
+pub fn hexdump(out: &mut dyn Write, addr: u64, bytes: &[u8]) -> core::fmt::Result {
+    for (i, line) in bytes.chunks(16).enumerate() {
+        write!(out, "{:016x} ", addr + i as u64 * 16)?;
+
+        for j in 0..16 {
+            if j == 8 { out.write_char(' ')?; }
+
+            match line.get(j) {
+                Some(byte) => write!(out, " {byte:02x}")?,
+                None       => out.write_str("   ")?
+            }
+        }
+
+        out.write_str("  |")?;
+        for &byte in line {
+            out.write_char(if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })?;
+        }
+        out.write_str("|\n")?;
+    }
+
+    Ok(())
+}
+
+// Only whole mapped pages are read, so a typo doesn't turn into a page fault
+fn dump(out: &mut dyn Write, addr: u64, len: u64) -> Result<(), Error> {
+    let end = addr.checked_add(len.min(MAX_LENGTH)).ok_or(anyhow!("Range overflows"))?;
+    let mut cur = addr;
+
+    while cur < end {
+        let chunk = (4096 - cur % 4096).min(end - cur);
+        if memory::translate(cur).is_none() { return Err(anyhow!("0x{cur:x} is not mapped").into()); }
+
+        let bytes = unsafe { core::slice::from_raw_parts(cur as *const u8, chunk as usize) };
+        hexdump(out, cur, bytes)?;
+
+        cur += chunk;
+    }
+
+    if len > MAX_LENGTH {
+        writeln!(out, "(truncated to 0x{MAX_LENGTH:x} of 0x{len:x} bytes)")?;
+    }
+
+    Ok(())
+}
+
+// Page tables live in identity mapped physical memory
+fn pt(args: &mut Args, out: &mut dyn Write) -> Result<(), Error> {
+    let addr: u64 = args.number("addr")?;
+    args.end()?;
+
+    const LEVELS: [&str; 4] = ["PML4", "PDPT", "PD", "PT"];
+
+    let mut table = Cr3::read().0.start_address().as_u64();
+
+    for (i, name) in LEVELS.iter().enumerate() {
+        let shift = 39 - 9 * i;
+        let index = (addr >> shift) as usize & 0x1ff;
+        let entry = &unsafe { &*(table as *const PageTable) }[index];
+        let flags = entry.flags();
+
+        writeln!(out, "{name:4}[{index:3}] @ 0x{table:x}: 0x{:016x} {flags:?}", entry.addr().as_u64() | flags.bits())?;
+
+        if !flags.contains(PageTableFlags::PRESENT) {
+            writeln!(out, "not mapped")?;
+            return Ok(());
+        }
+
+        if i == 3 || flags.contains(PageTableFlags::HUGE_PAGE) {
+            let offset = addr & ((1 << shift) - 1);
+            writeln!(out, "0x{addr:x} -> 0x{:x}", entry.addr().as_u64() + offset)?;
+            return Ok(());
+        }
+
+        table = entry.addr().as_u64();
+    }
+
+    Ok(())
+}
+
+fn mem(args: &mut Args, out: &mut dyn Write) -> Result<(), Error> {
+    args.end()?;
+
+    for pool in frames::pools() {
+        writeln!(out, "0x{:012x} -- 0x{:012x} {:8} KiB", pool.start, pool.end, pool.size() / 1024)?;
+    }
+
+    writeln!(out, "{} KiB total, {} KiB allocated", frames::total() / 1024, frames::allocated() / 1024)?;
+
+    Ok(())
+}
+
+fn regs(args: &mut Args, out: &mut dyn Write) -> Result<(), Error> {
+    args.end()?;
+
+    let (rip, rsp, rbp): (u64, u64, u64);
+    unsafe { asm!("lea {}, [rip]", "mov {}, rsp", "mov {}, rbp", out(reg) rip, out(reg) rsp, out(reg) rbp); }
+
+    let (cr3, pcid) = Cr3::read_raw();
+    let gdt = sgdt();
+    let idt = sidt();
+
+    writeln!(out, "rip 0x{rip:016x} rsp 0x{rsp:016x} rbp 0x{rbp:016x}")?;
+    writeln!(out, "rflags 0x{:x}", rflags::read_raw())?;
+    writeln!(out, "cr0 0x{:x} cr2 0x{:x} cr3 0x{:x} (pcid {pcid}) cr4 0x{:x}", Cr0::read_raw(), Cr2::read_raw(), cr3.start_address(), Cr4::read_raw())?;
+    writeln!(out, "efer 0x{:x}", Efer::read_raw())?;
+    writeln!(
+        out, "cs 0x{:x} ss 0x{:x} ds 0x{:x} es 0x{:x} fs 0x{:x} gs 0x{:x}",
+        CS::get_reg().0, SS::get_reg().0, DS::get_reg().0, ES::get_reg().0, FS::get_reg().0, GS::get_reg().0
+    )?;
+    writeln!(out, "gdt 0x{:x}+0x{:x} idt 0x{:x}+0x{:x}", { gdt.base }, { gdt.limit }, { idt.base }, { idt.limit })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use super::*;
+    use alloc::string::String;
+
+    #[test_case]
+    fn hexdump_pads_the_last_line() {
+        let mut out = String::new();
+        hexdump(&mut out, 0x1000, b"Hello, monitor!\0ab").unwrap();
+
+        assert_eq!(
+            out,
+            "0000000000001000  48 65 6c 6c 6f 2c 20 6d  6f 6e 69 74 6f 72 21 00  |Hello, monitor!.|\n\
+             0000000000001010  61 62                                             |ab|\n"
+        );
+    }
+
+    #[test_case]
+    fn malformed_lengths_are_reported() {
+        let mut out = String::new();
+
+        assert!(matches!(x(&mut Args::new("x 0x1000 12z"), &mut out), Err(Error::InvalidNumber(arg)) if arg == "12z"));
+        assert!(matches!(xp(&mut Args::new("xp 0x1000 -1"), &mut out), Err(Error::InvalidNumber(_))));
+        assert!(out.is_empty());
+    }
+}
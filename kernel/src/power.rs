@@ -0,0 +1,291 @@
+extern crate alloc;
+
+use core::fmt::Write;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll};
+use acpi::address::{AddressSpace, GenericAddress};
+use acpi::fadt::Fadt;
+use alloc::vec::Vec;
+use anyhow::{anyhow, Result};
+use futures_util::task::AtomicWaker;
+use log::{error, info, warn};
+use x86_64::instructions::interrupts;
+use x86_64::structures::DescriptorTablePointer;
+use x86_64::VirtAddr;
+
+use crate::acpi::aml;
+use crate::acpi::aml::value::Value;
+use crate::acpi::tables::ACPI;
+use crate::hal::{PortIo, Ports};
+use crate::interrupts as irq;
+use crate::shell::{self, Args, Command, Error};
+
+const SCI_EN:     u16 = 1 << 0;
+const SLP_TYP:    u16 = 7 << 10;
+const SLP_EN:     u16 = 1 << 13;
+const PWRBTN:     u16 = 1 << 8;
+
+const KBC_STATUS: u16 = 0x64;
+const KBC_RESET:  u8 = 0xfe;
+
+// Roughly how long to wait for the hardware to act before trying the next method
+const SETTLE: usize = 10_000_000;
+
+struct Registers {
+    pm1a_control: GenericAddress,
+    pm1b_control: Option<GenericAddress>,
+    pm1a_event:   GenericAddress,
+    pm1b_event:   Option<GenericAddress>,
+    reset:        Option<(GenericAddress, u8)>,
+    smi_command:  u16,
+    acpi_enable:  u8,
+    sci:          u8,
+    // SLP_TYPa and SLP_TYPb for the soft-off state
+    s5:           Option<(u8, u8)>
+}
+
+static mut REGISTERS: Option<Registers> = None;
+static BUTTON: AtomicBool = AtomicBool::new(false);
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+pub fn init(acpi: &ACPI) -> Result<()> {
+    let fadt = acpi.tables.find_table::<Fadt>().map_err(|e| anyhow!("{e:?}"))?;
+    let err = |e| anyhow!("{e:?}");
+    let flags = fadt.flags;
+
+    let reset = fadt.reset_register().ok()
+        .filter(|reg| flags.supports_system_reset_via_fadt() && reg.address != 0)
+        .map(|reg| (reg, fadt.reset_value));
+
+    let s5 = s5().map_err(|e| warn!("{e}, shutdown is unavailable")).ok();
+
+    let registers = Registers {
+        pm1a_control: fadt.pm1a_control_block().map_err(err)?,
+        pm1b_control: fadt.pm1b_control_block().map_err(err)?,
+        pm1a_event:   fadt.pm1a_event_block().map_err(err)?,
+        pm1b_event:   fadt.pm1b_event_block().map_err(err)?,
+        reset,
+        smi_command:  fadt.smi_cmd_port as u16,
+        acpi_enable:  fadt.acpi_enable,
+        sci:          fadt.sci_interrupt as u8,
+        s5
+    };
+
+    let button = !flags.power_button_is_control_method();
+    unsafe { REGISTERS = Some(registers); }
+
+    enable_acpi()?;
+
+    if button {
+        enable_button();
+    } else {
+        info!("Power button is a control method device, not handled");
+    }
+
+    Ok(())
+}
+
+fn registers() -> Result<&'static Registers> {
+    unsafe { REGISTERS.as_ref().ok_or(anyhow!("Power management is not initialized")) }
+}
+
+// Firmware may still own the fixed hardware until it is asked to hand it over through SMI_CMD
+fn enable_acpi() -> Result<()> {
+    let regs = registers()?;
+    if read_control(regs) & SCI_EN != 0 { return Ok(()); }
+
+    if regs.smi_command == 0 || regs.acpi_enable == 0 {
+        return Err(anyhow!("ACPI mode is off and cannot be enabled"));
+    }
+
+    unsafe { Ports.write_u8(regs.smi_command, regs.acpi_enable); }
+
+    for _ in 0..SETTLE {
+        if read_control(regs) & SCI_EN != 0 {
+            info!("Switched to ACPI mode");
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+
+    Err(anyhow!("Firmware did not switch to ACPI mode"))
+}
+
+fn enable_button() {
+    let Ok(regs) = registers() else { return; };
+
+    for event in [Some(regs.pm1a_event), regs.pm1b_event].into_iter().flatten() {
+        unsafe {
+            // Status bits are cleared by writing ones
+            write(event.address_space, event.address, 16, PWRBTN as u64);
+            let enable = read(event.address_space, enable_register(&event), 16);
+            write(event.address_space, enable_register(&event), 16, enable | PWRBTN as u64);
+        }
+    }
+
+    irq::enable_sci(regs.sci);
+}
+
+// The event block is split in two halves, status first and enable second
+fn enable_register(event: &GenericAddress) -> u64 {
+    event.address + event.bit_width as u64 / 16
+}
+
+fn read_control(regs: &Registers) -> u16 {
+    unsafe { read(regs.pm1a_control.address_space, regs.pm1a_control.address, 16) as u16 }
+}
+
+pub fn shutdown() -> Result<()> {
+    let regs = registers()?;
+    let (a, b) = regs.s5.ok_or(anyhow!("Soft-off is not described by the firmware"))?;
+
+    info!("Powering off..");
+    interrupts::disable();
+
+    for (control, typ) in [(Some(regs.pm1a_control), a), (regs.pm1b_control, b)] {
+        let Some(control) = control else { continue; };
+
+        unsafe {
+            let value = read(control.address_space, control.address, 16) as u16 & !(SLP_TYP | SLP_EN);
+            write(control.address_space, control.address, 16, (value | (typ as u16) << 10 | SLP_EN) as u64);
+        }
+    }
+
+    for _ in 0..SETTLE { core::hint::spin_loop(); }
+
+    interrupts::enable();
+    Err(anyhow!("Machine is still running"))
+}
+
+pub fn reboot() -> ! {
+    info!("Rebooting..");
+    interrupts::disable();
+
+    if let Ok(Registers { reset: Some((reg, value)), .. }) = registers() {
+        unsafe { write(reg.address_space, reg.address, 8, *value as u64); }
+        for _ in 0..SETTLE { core::hint::spin_loop(); }
+        warn!("FADT reset register had no effect");
+    }
+
+    unsafe {
+        for _ in 0..SETTLE {
+            if Ports.read_u8(KBC_STATUS) & 2 == 0 { break; }
+            core::hint::spin_loop();
+        }
+
+        Ports.write_u8(KBC_STATUS, KBC_RESET);
+    }
+
+    for _ in 0..SETTLE { core::hint::spin_loop(); }
+    warn!("Keyboard controller reset had no effect");
+
+    // With an empty IDT the breakpoint becomes a double and then a triple fault
+    unsafe {
+        let idt = DescriptorTablePointer { limit: 0, base: VirtAddr::zero() };
+        x86_64::instructions::tables::lidt(&idt);
+        core::arch::asm!("int3", options(noreturn));
+    }
+}
+
+// Called from the SCI handler, so it only records the event
+pub fn handle_sci() {
+    let Ok(regs) = registers() else { return; };
+
+    for event in [Some(regs.pm1a_event), regs.pm1b_event].into_iter().flatten() {
+        unsafe {
+            if read(event.address_space, event.address, 16) as u16 & PWRBTN != 0 {
+                write(event.address_space, event.address, 16, PWRBTN as u64);
+                BUTTON.store(true, Ordering::Relaxed);
+                WAKER.wake();
+            }
+        }
+    }
+}
+
+struct ButtonPress;
+
+impl Future for ButtonPress {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if BUTTON.swap(false, Ordering::Relaxed) { return Poll::Ready(()); }
+
+        WAKER.register(cx.waker());
+
+        if BUTTON.swap(false, Ordering::Relaxed) {
+            WAKER.take();
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+pub async fn power_button() {
+    loop {
+        ButtonPress.await;
+        info!("Power button pressed");
+
+        if let Err(e) = shutdown() {
+            error!("{e}");
+        }
+    }
+}
+
+unsafe fn read(space: AddressSpace, address: u64, bits: u8) -> u64 {
+    match (space, bits) {
+        (AddressSpace::SystemIo, 8)      => Ports.read_u8(address as u16) as u64,
+        (AddressSpace::SystemIo, 16)     => Ports.read_u16(address as u16) as u64,
+        (AddressSpace::SystemIo, _)      => Ports.read_u32(address as u16) as u64,
+        (AddressSpace::SystemMemory, 8)  => (address as *const u8).read_volatile() as u64,
+        (AddressSpace::SystemMemory, 16) => (address as *const u16).read_volatile() as u64,
+        (AddressSpace::SystemMemory, _)  => (address as *const u32).read_volatile() as u64,
+        _                                => 0
+    }
+}
+
+unsafe fn write(space: AddressSpace, address: u64, bits: u8, value: u64) {
+    match (space, bits) {
+        (AddressSpace::SystemIo, 8)      => Ports.write_u8(address as u16, value as u8),
+        (AddressSpace::SystemIo, 16)     => Ports.write_u16(address as u16, value as u16),
+        (AddressSpace::SystemIo, _)      => Ports.write_u32(address as u16, value as u32),
+        (AddressSpace::SystemMemory, 8)  => (address as *mut u8).write_volatile(value as u8),
+        (AddressSpace::SystemMemory, 16) => (address as *mut u16).write_volatile(value as u16),
+        (AddressSpace::SystemMemory, _)  => (address as *mut u32).write_volatile(value as u32),
+        // Segment 0, bus 0, with device, function and offset packed into the address
+        (AddressSpace::PciConfigSpace, _) => {
+            let (device, function, offset) = ((address >> 32) as u32 & 0x1f, (address >> 16) as u32 & 7, address as u16);
+            Ports.write_u32(0xcf8, 0x80000000 | device << 11 | function << 8 | (offset & 0xfc) as u32);
+            Ports.write_u8(0xcfc + (offset & 3), value as u8);
+        }
+        _ => warn!("Unsupported register address space {space:?}")
+    }
+}
+
+// SLP_TYPa and SLP_TYPb lead the \_S5 package, later elements are reserved
+fn s5() -> Result<(u8, u8)> {
+    let aml = aml::get().ok_or(anyhow!("AML is not loaded"))?;
+    let Value::Package(elements) = aml.evaluate("\\_S5", Vec::new())? else {
+        return Err(anyhow!("\\_S5 is not a package"));
+    };
+
+    let typ = |i: usize| elements.get(i).map_or(Ok(0), Value::as_integer).map(|value| value as u8);
+    Ok((typ(0)?, typ(1)?))
+}
+
+pub fn register_commands() {
+    shell::register(Command { name: "reboot",   usage: "reboot",   help: "restart the machine",  run: reboot_command });
+    shell::register(Command { name: "shutdown", usage: "shutdown", help: "power off through ACPI", run: shutdown_command });
+}
+
+fn reboot_command(args: &mut Args, _: &mut dyn Write) -> Result<(), Error> {
+    args.end()?;
+    reboot()
+}
+
+fn shutdown_command(args: &mut Args, _: &mut dyn Write) -> Result<(), Error> {
+    args.end()?;
+    Ok(shutdown()?)
+}
@@ -0,0 +1,230 @@
+extern crate alloc;
+
+use core::arch::naked_asm;
+use core::fmt::Arguments;
+use core::mem;
+use core::ops::Range;
+use core::ptr;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use log::{info, warn};
+use x86_64::VirtAddr;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::segmentation::{Segment, DS, ES};
+use x86_64::registers::control::{Cr3, Cr3Flags};
+
+use crate::{fpu, gdt, memory, syscall};
+use crate::memory::address_space::AddressSpace;
+
+const KERNEL_STACK_SIZE: usize = 4096 * 16;
+
+pub const MMAP_BASE: u64 = 0x00001000_00000000;
+pub const MMAP_END:  u64 = 0x00002000_00000000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitStatus {
+    Exited(i64),
+    Killed
+}
+
+pub struct Process {
+    pid:          u64,
+    name:         String,
+    space:        AddressSpace,
+    kernel_stack: Box<[u8]>,
+    fpu:          fpu::State,
+    entry:        u64,
+    stack:        u64,
+    mmap_next:    u64
+}
+
+// Processes run to completion, so a single saved kernel context is enough to come back to
+static mut CURRENT: Option<*mut Process> = None;
+static mut KERNEL_RSP: u64 = 0;
+static mut EXIT: Option<ExitStatus> = None;
+static mut NEXT_PID: u64 = 1;
+// The heap never gives memory back, so the stacks of finished processes are kept for the next ones
+static mut FREE_STACKS: Vec<Box<[u8]>> = Vec::new();
+
+impl Process {
+    pub fn new(name: &str, space: AddressSpace, entry: u64, stack: u64) -> Process {
+        let pid = unsafe {
+            let pid = NEXT_PID;
+            NEXT_PID += 1;
+            pid
+        };
+
+        Process {
+            pid,
+            name: String::from(name),
+            space,
+            kernel_stack: unsafe { FREE_STACKS.pop() }.unwrap_or_else(|| vec![0; KERNEL_STACK_SIZE].into_boxed_slice()),
+            fpu: fpu::State::new(),
+            entry,
+            stack,
+            mmap_next: MMAP_BASE
+        }
+    }
+
+    pub fn pid(&self) -> u64 {
+        self.pid
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn space(&mut self) -> &mut AddressSpace {
+        &mut self.space
+    }
+
+    pub fn kernel_stack(&self) -> Range<u64> {
+        let range = self.kernel_stack.as_ptr_range();
+        range.start as u64..range.end as u64
+    }
+
+    pub fn kernel_stack_top(&self) -> VirtAddr {
+        VirtAddr::from_ptr(self.kernel_stack.as_ptr_range().end).align_down(16u64)
+    }
+
+    pub fn reserve_mmap(&mut self, size: u64) -> Option<u64> {
+        if size > MMAP_END - MMAP_BASE { return None; }
+
+        let mut start = self.mmap_next;
+
+        loop {
+            // One unmapped guard page between consecutive mappings
+            let next = start.checked_add(size)?.checked_add(4096)?;
+            if next > MMAP_END { return None; }
+
+            // Fixed mappings can sit anywhere in the window, the search carries on past them. It goes forwards,
+            // so the pages it already found free are behind the new start and never looked at again
+            match (start..start + size).step_by(4096).find(|&page| self.space.last_mapped(page, 4096).is_some()) {
+                Some(page) => start = page + 2 * 4096,
+                None       => {
+                    self.mmap_next = next;
+                    return Some(start);
+                }
+            }
+        }
+    }
+
+    pub fn run(&mut self) -> ExitStatus {
+        info!("Starting {} ({})", self.pid, self.name);
+
+        let selectors = gdt::selectors();
+
+        unsafe {
+            interrupts::disable();
+
+            gdt::set_kernel_stack(self.kernel_stack_top());
+            syscall::set_kernel_stack(self.kernel_stack_top());
+            CURRENT = Some(self as *mut Process);
+            self.space.activate();
+            self.fpu.restore();
+
+            enter_user(
+                ptr::addr_of_mut!(KERNEL_RSP),
+                self.entry,
+                self.stack,
+                selectors.user_code.0 as u64,
+                selectors.user_data.0 as u64
+            );
+
+            self.fpu.save();
+            Cr3::write(memory::kernel_pml4(), Cr3Flags::empty());
+            DS::set_reg(selectors.kernel_data);
+            ES::set_reg(selectors.kernel_data);
+            CURRENT = None;
+
+            interrupts::enable();
+
+            let status = EXIT.take().expect("Process returned without exit status");
+            info!("{} ({}) finished: {status:?}", self.pid, self.name);
+
+            status
+        }
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        unsafe { FREE_STACKS.push(mem::take(&mut self.kernel_stack)); }
+    }
+}
+
+pub fn current() -> Option<&'static mut Process> {
+    unsafe { CURRENT.map(|process| &mut *process) }
+}
+
+pub fn exit(status: ExitStatus) -> ! {
+    unsafe {
+        if CURRENT.is_none() { panic!("exit called outside of a process"); }
+
+        EXIT = Some(status);
+        leave_user(KERNEL_RSP)
+    }
+}
+
+pub fn kill(reason: Arguments) -> ! {
+    if let Some(process) = current() {
+        warn!("Killing {} ({}): {reason}", process.pid, process.name);
+    }
+
+    exit(ExitStatus::Killed)
+}
+
+#[unsafe(naked)]
+unsafe extern "sysv64" fn enter_user(kernel_rsp: *mut u64, entry: u64, stack: u64, cs: u64, ss: u64) {
+    naked_asm!(
+        "push rbp",
+        "push rbx",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov [rdi], rsp",
+
+        "mov ds, r8w",
+        "mov es, r8w",
+
+        "push r8",
+        "push rdx",
+        "push 0x202",
+        "push rcx",
+        "push rsi",
+
+        "xor eax, eax",
+        "xor ebx, ebx",
+        "xor ecx, ecx",
+        "xor edx, edx",
+        "xor esi, esi",
+        "xor edi, edi",
+        "xor ebp, ebp",
+        "xor r8d, r8d",
+        "xor r9d, r9d",
+        "xor r10d, r10d",
+        "xor r11d, r11d",
+        "xor r12d, r12d",
+        "xor r13d, r13d",
+        "xor r14d, r14d",
+        "xor r15d, r15d",
+        "iretq"
+    )
+}
+
+#[unsafe(naked)]
+unsafe extern "sysv64" fn leave_user(kernel_rsp: u64) -> ! {
+    naked_asm!(
+        "mov rsp, rdi",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbx",
+        "pop rbp",
+        "ret"
+    )
+}
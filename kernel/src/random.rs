@@ -0,0 +1,344 @@
+extern crate alloc;
+
+use core::arch::x86_64::{_rdrand64_step, _rdseed64_step, _rdtsc};
+use core::fmt::Write;
+use core::hint::black_box;
+use alloc::vec::Vec;
+use log::info;
+use x86_64::instructions::interrupts;
+
+use crate::cpu::{self, Feature, Features, Info, Native};
+use crate::shell::{self, Args, Command, Error};
+
+const CONSTANTS: [u32; 4] = [0x61707865, 0x3320646e, 0x79622d32, 0x6b206574];
+// Words of the pool state that samples go into, the rest is never output
+const RATE: usize = 8;
+const RESEED_EVENTS: usize = 64;
+const JITTER_SAMPLES: usize = 256;
+const RDRAND_RETRIES: usize = 10;
+
+fn quarter(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    s[a] = s[a].wrapping_add(s[b]); s[d] = (s[d] ^ s[a]).rotate_left(16);
+    s[c] = s[c].wrapping_add(s[d]); s[b] = (s[b] ^ s[c]).rotate_left(12);
+    s[a] = s[a].wrapping_add(s[b]); s[d] = (s[d] ^ s[a]).rotate_left(8);
+    s[c] = s[c].wrapping_add(s[d]); s[b] = (s[b] ^ s[c]).rotate_left(7);
+}
+
+// The ChaCha20 block function, twenty rounds and the input added back in
+fn block(input: &[u32; 16]) -> [u32; 16] {
+    let mut s = *input;
+
+    for _ in 0..10 {
+        quarter(&mut s, 0, 4, 8, 12);
+        quarter(&mut s, 1, 5, 9, 13);
+        quarter(&mut s, 2, 6, 10, 14);
+        quarter(&mut s, 3, 7, 11, 15);
+        quarter(&mut s, 0, 5, 10, 15);
+        quarter(&mut s, 1, 6, 11, 12);
+        quarter(&mut s, 2, 7, 8, 13);
+        quarter(&mut s, 3, 4, 9, 14);
+    }
+
+    for (word, input) in s.iter_mut().zip(input) {
+        *word = word.wrapping_add(*input);
+    }
+
+    s
+}
+
+fn words<const N: usize>(bytes: &[u8]) -> [u32; N] {
+    core::array::from_fn(|i| u32::from_le_bytes(bytes[i * 4..][..4].try_into().unwrap()))
+}
+
+pub struct ChaCha20 {
+    key:     [u32; 8],
+    nonce:   [u32; 3],
+    counter: u32
+}
+
+impl ChaCha20 {
+    pub fn new(key: &[u8; 32], nonce: &[u8; 12], counter: u32) -> ChaCha20 {
+        ChaCha20 { key: words(key), nonce: words(nonce), counter }
+    }
+
+    pub fn next_block(&mut self) -> [u8; 64] {
+        let mut input = [0; 16];
+        input[..4].copy_from_slice(&CONSTANTS);
+        input[4..12].copy_from_slice(&self.key);
+        input[12] = self.counter;
+        input[13..].copy_from_slice(&self.nonce);
+        self.counter = self.counter.wrapping_add(1);
+
+        let mut out = [0; 64];
+        for (chunk, word) in out.chunks_exact_mut(4).zip(block(&input)) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+
+        out
+    }
+}
+
+// Every block replaces the key with its first half before the second half is handed out, so
+// nothing that was output can be recomputed from the state afterwards
+pub struct Csprng {
+    cipher: ChaCha20,
+    buffer: [u8; 32],
+    used:   usize
+}
+
+impl Csprng {
+    pub fn new(seed: &[u8; 32]) -> Csprng {
+        Csprng { cipher: ChaCha20::new(seed, &[0; 12], 0), buffer: [0; 32], used: 32 }
+    }
+
+    fn refill(&mut self) {
+        let block = self.cipher.next_block();
+        self.cipher = ChaCha20::new(block[..32].try_into().unwrap(), &[0; 12], 0);
+        self.buffer.copy_from_slice(&block[32..]);
+        self.used = 0;
+    }
+
+    pub fn fill_bytes(&mut self, mut buf: &mut [u8]) {
+        while !buf.is_empty() {
+            if self.used == self.buffer.len() { self.refill(); }
+
+            let len = buf.len().min(self.buffer.len() - self.used);
+            buf[..len].copy_from_slice(&self.buffer[self.used..self.used + len]);
+            // Handed out bytes are gone from the buffer as well
+            self.buffer[self.used..self.used + len].fill(0);
+
+            self.used += len;
+            buf = &mut buf[len..];
+        }
+    }
+
+    pub fn reseed(&mut self, seed: &[u8; 32]) {
+        let mut key = [0; 32];
+        self.fill_bytes(&mut key);
+
+        for (byte, seed) in key.iter_mut().zip(seed) { *byte ^= seed; }
+        *self = Csprng::new(&key);
+    }
+}
+
+// Collects samples of unknown quality, a sponge over the ChaCha block function
+pub struct Pool {
+    state:  [u32; 16],
+    next:   usize,
+    events: usize
+}
+
+impl Pool {
+    pub const fn new() -> Pool {
+        Pool { state: [0; 16], next: 0, events: 0 }
+    }
+
+    pub fn add(&mut self, sample: u64) {
+        self.state[self.next] ^= sample as u32;
+        self.state[self.next + 1] ^= (sample >> 32) as u32;
+        self.next += 2;
+        self.events += 1;
+
+        if self.next == RATE {
+            self.state = block(&self.state);
+            self.next = 0;
+        }
+    }
+
+    pub fn add_bytes(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(8) {
+            let mut sample = [0; 8];
+            sample[..chunk.len()].copy_from_slice(chunk);
+            self.add(u64::from_le_bytes(sample));
+        }
+    }
+
+    pub fn events(&self) -> usize {
+        self.events
+    }
+
+    pub fn extract(&mut self) -> [u8; 32] {
+        self.state = block(&self.state);
+
+        let mut seed = [0; 32];
+        for (chunk, word) in seed.chunks_exact_mut(4).zip(&self.state[..RATE]) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+
+        // Once more, so the output says nothing about the state that comes next
+        self.state = block(&self.state);
+        self.next = 0;
+        self.events = 0;
+
+        seed
+    }
+}
+
+impl Default for Pool {
+    fn default() -> Pool {
+        Pool::new()
+    }
+}
+
+static mut POOL: Pool = Pool::new();
+static mut CSPRNG: Option<Csprng> = None;
+
+#[target_feature(enable = "rdseed")]
+unsafe fn rdseed() -> Option<u64> {
+    let mut value = 0;
+    (_rdseed64_step(&mut value) == 1).then_some(value)
+}
+
+#[target_feature(enable = "rdrand")]
+unsafe fn rdrand() -> Option<u64> {
+    let mut value = 0;
+    // Only fails when the DRNG is drained for a moment, Intel suggests ten tries
+    (0..RDRAND_RETRIES).find(|_| _rdrand64_step(&mut value) == 1).map(|_| value)
+}
+
+// Straight from the conditioned entropy source if there is one, otherwise the DRNG it feeds
+// The sample and the instruction that gave it, RDRAND stands in whenever RDSEED runs dry
+fn hardware(features: Features) -> Option<(u64, &'static str)> {
+    unsafe {
+        if features.contains(Feature::Rdseed) {
+            if let Some(value) = rdseed() { return Some((value, "rdseed")); }
+        }
+
+        if features.contains(Feature::Rdrand) { rdrand().map(|value| (value, "rdrand")) } else { None }
+    }
+}
+
+// How long a fixed amount of work takes wobbles with caches, refresh and SMIs, the low bits of
+// the difference are what counts
+fn jitter() -> u64 {
+    let start = unsafe { _rdtsc() };
+    let mut x = start;
+
+    for i in 0..64 {
+        x = black_box(x.rotate_left(7) ^ i);
+    }
+
+    unsafe { _rdtsc() }.wrapping_sub(start) ^ x << 32
+}
+
+// Everything there is at boot, returns the names of the sources that gave anything
+fn gather(pool: &mut Pool, seed: &[u8], features: Features) -> Vec<&'static str> {
+    let mut sources = Vec::new();
+
+    if !seed.is_empty() {
+        pool.add_bytes(seed);
+        sources.push("firmware");
+    }
+
+    for (value, source) in (0..8).filter_map(|_| hardware(features)) {
+        pool.add(value);
+        if !sources.contains(&source) { sources.push(source); }
+    }
+
+    for _ in 0..JITTER_SAMPLES { pool.add(jitter()); }
+    sources.push("jitter");
+
+    sources
+}
+
+// For the bootloader, which picks the kernel base before there is a kernel
+pub fn entropy(seed: &[u8]) -> [u8; 32] {
+    let mut pool = Pool::new();
+    gather(&mut pool, seed, Info::decode(&Native).features);
+    pool.extract()
+}
+
+// Needs the heap, and the CPU features to know which instructions there are
+pub fn init(seed: &[u8]) {
+    let pool = unsafe { &mut *core::ptr::addr_of_mut!(POOL) };
+    let sources = gather(pool, seed, cpu::features());
+
+    unsafe { CSPRNG = Some(Csprng::new(&pool.extract())); }
+    info!("Random seeded from {}", sources.join(", "));
+}
+
+// Called from interrupt handlers, where the arrival time is the entropy
+pub fn add_interrupt(irq: u8) {
+    unsafe { (*core::ptr::addr_of_mut!(POOL)).add(_rdtsc() ^ (irq as u64) << 56); }
+}
+
+pub fn fill_bytes(buf: &mut [u8]) {
+    interrupts::without_interrupts(|| unsafe {
+        let pool = &mut *core::ptr::addr_of_mut!(POOL);
+        let csprng = CSPRNG.as_mut().expect("Random is not initialized");
+
+        if pool.events() >= RESEED_EVENTS {
+            if let Some((value, _)) = hardware(cpu::features()) { pool.add(value); }
+            csprng.reseed(&pool.extract());
+        }
+
+        csprng.fill_bytes(buf);
+    });
+}
+
+pub fn next_u64() -> u64 {
+    let mut bytes = [0; 8];
+    fill_bytes(&mut bytes);
+    u64::from_le_bytes(bytes)
+}
+
+pub fn register_commands() {
+    shell::register(Command { name: "random", usage: "random [bytes]", help: "print random bytes in hex", run: random });
+}
+
+fn random(args: &mut Args, out: &mut dyn Write) -> Result<(), Error> {
+    let len = args.optional_number::<usize>()?.unwrap_or(32).min(256);
+    args.end()?;
+
+    let mut bytes = [0; 256];
+    fill_bytes(&mut bytes[..len]);
+
+    for line in bytes[..len].chunks(32) {
+        for byte in line { write!(out, "{byte:02x}")?; }
+        writeln!(out)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 8439, section 2.3.2
+    #[test_case]
+    fn chacha20_matches_the_rfc_vector() {
+        let key = core::array::from_fn(|i| i as u8);
+        let nonce = [0, 0, 0, 9, 0, 0, 0, 0x4a, 0, 0, 0, 0];
+        let block = ChaCha20::new(&key, &nonce, 1).next_block();
+
+        assert_eq!(block[..16], [0x10, 0xf1, 0xe7, 0xe4, 0xd1, 0x3b, 0x59, 0x15, 0x50, 0x0f, 0xdd, 0x1f, 0xa3, 0x20, 0x71, 0xc4]);
+        assert_eq!(block[48..], [0xb5, 0x12, 0x9c, 0xd1, 0xde, 0x16, 0x4e, 0xb9, 0xcb, 0xd0, 0x83, 0xe8, 0xa2, 0x50, 0x3c, 0x4e]);
+    }
+
+    #[test_case]
+    fn csprng_output_does_not_repeat() {
+        let mut csprng = Csprng::new(&[7; 32]);
+        let (mut a, mut b) = ([0; 48], [0; 48]);
+        csprng.fill_bytes(&mut a);
+        csprng.fill_bytes(&mut b);
+        assert_ne!(a, b);
+
+        // Split requests see the same stream as a single one
+        let mut whole = [0; 48];
+        Csprng::new(&[7; 32]).fill_bytes(&mut whole);
+        assert_eq!(whole, a);
+    }
+
+    #[test_case]
+    fn pool_output_depends_on_every_sample() {
+        let mut a = Pool::new();
+        let mut b = Pool::new();
+        a.add_bytes(&[1, 2, 3]);
+        b.add_bytes(&[1, 2, 4]);
+
+        assert_eq!(a.events(), 1);
+        assert_ne!(a.extract(), b.extract());
+        assert_eq!(a.events(), 0);
+    }
+}
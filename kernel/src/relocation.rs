@@ -0,0 +1,56 @@
+extern crate alloc;
+
+use alloc::vec::Vec;
+use anyhow::{anyhow, Result};
+use elf::ElfBytes;
+use elf::abi::{DT_RELA, DT_RELAENT, DT_RELASZ, PT_LOAD, R_X86_64_RELATIVE};
+use elf::endian::LittleEndian;
+
+const RELA_SIZE: usize = 24;
+
+// The offset and addend of every relocation, all of which must be relative. Shared by the
+// program loader and the bootloader, which each write base + addend to base + offset
+pub fn relative(elf: &ElfBytes<LittleEndian>, bytes: &[u8]) -> Result<Vec<(u64, u64)>> {
+    let Some(dynamic) = elf.dynamic()? else { return Ok(Vec::new()); };
+
+    let (mut rela, mut size, mut entsize) = (None, 0, RELA_SIZE as u64);
+    for entry in dynamic.iter() {
+        match entry.d_tag {
+            DT_RELA    => rela = Some(entry.d_ptr()),
+            DT_RELASZ  => size = entry.d_val(),
+            DT_RELAENT => entsize = entry.d_val(),
+            _          => {}
+        }
+    }
+
+    let Some(rela) = rela else { return Ok(Vec::new()); };
+    if entsize != RELA_SIZE as u64 { return Err(anyhow!("Unexpected relocation entry size {entsize}")); }
+
+    let end = rela.checked_add(size).ok_or(anyhow!("Relocation table size overflows"))?;
+
+    // Only segments that get loaded at all, with no more file data than memory
+    let phdr = elf.segments()
+        .ok_or(anyhow!("Relocations without segments"))?
+        .iter()
+        .filter(|phdr| phdr.p_type == PT_LOAD && phdr.p_memsz > 0 && phdr.p_filesz <= phdr.p_memsz)
+        .find(|phdr| phdr.p_vaddr <= rela && phdr.p_vaddr.checked_add(phdr.p_filesz).is_some_and(|file_end| end <= file_end))
+        .ok_or(anyhow!("Relocation table is not backed by the file"))?;
+
+    let data = phdr.p_offset
+        .checked_add(rela - phdr.p_vaddr)
+        .and_then(|start| bytes.get(start as usize..start.checked_add(size)? as usize))
+        .ok_or(anyhow!("Relocation table extends past the end of the file"))?;
+
+    data.chunks_exact(RELA_SIZE)
+        .map(|entry| {
+            let word = |i: usize| u64::from_le_bytes(entry[i * 8..i * 8 + 8].try_into().expect("Impossible"));
+            let (offset, info, addend) = (word(0), word(1), word(2));
+
+            if info as u32 != R_X86_64_RELATIVE {
+                return Err(anyhow!("Unsupported relocation type {}", info as u32));
+            }
+
+            Ok((offset, addend))
+        })
+        .collect()
+}
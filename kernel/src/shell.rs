@@ -0,0 +1,239 @@
+pub mod builtins;
+pub mod editor;
+pub mod input;
+
+extern crate alloc;
+
+use core::fmt::{self, Display, Formatter, Write};
+use core::str::SplitWhitespace;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::{print, println};
+use crate::drivers::console::{self, Console, Output};
+use crate::drivers::keyboard;
+use crate::drivers::video;
+use crate::drivers::video::printer::Mark;
+use editor::{Editor, Event, Key};
+
+const PROMPT: &str = "> ";
+
+#[derive(Debug)]
+pub enum Error {
+    UnknownCommand(String),
+    MissingArgument(&'static str),
+    InvalidNumber(String),
+    UnexpectedArgument(String),
+    Failed(anyhow::Error)
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Error::UnknownCommand(name)    => write!(f, "Unknown command {name}, try help"),
+            Error::MissingArgument(name)   => write!(f, "Missing argument <{name}>"),
+            Error::InvalidNumber(arg)      => write!(f, "Invalid number {arg}"),
+            Error::UnexpectedArgument(arg) => write!(f, "Unexpected argument {arg}"),
+            Error::Failed(e)               => write!(f, "{e}")
+        }
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Error {
+        Error::Failed(e)
+    }
+}
+
+impl From<fmt::Error> for Error {
+    fn from(_: fmt::Error) -> Error {
+        Error::Failed(anyhow::anyhow!("Output failed"))
+    }
+}
+
+pub struct Args<'a> {
+    command: &'a str,
+    words:   SplitWhitespace<'a>
+}
+
+impl<'a> Args<'a> {
+    pub fn new(line: &'a str) -> Args<'a> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("");
+
+        Args { command, words }
+    }
+
+    pub fn command(&self) -> &'a str {
+        self.command
+    }
+
+    pub fn optional_word(&mut self) -> Option<&'a str> {
+        self.words.next()
+    }
+
+    pub fn word(&mut self, name: &'static str) -> Result<&'a str, Error> {
+        self.words.next().ok_or(Error::MissingArgument(name))
+    }
+
+    // Hexadecimal with a 0x prefix or decimal, underscores are ignored
+    pub fn optional_number<T: TryFrom<u64>>(&mut self) -> Result<Option<T>, Error> {
+        let Some(arg) = self.words.next() else { return Ok(None); };
+        let digits = arg.replace('_', "");
+
+        let value = match digits.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None      => digits.parse().ok()
+        };
+
+        value
+            .and_then(|value| T::try_from(value).ok())
+            .map(Some)
+            .ok_or(Error::InvalidNumber(arg.into()))
+    }
+
+    pub fn number<T: TryFrom<u64>>(&mut self, name: &'static str) -> Result<T, Error> {
+        self.optional_number()?.ok_or(Error::MissingArgument(name))
+    }
+
+    pub fn end(&mut self) -> Result<(), Error> {
+        match self.words.next() {
+            Some(arg) => Err(Error::UnexpectedArgument(arg.into())),
+            None      => Ok(())
+        }
+    }
+}
+
+pub type Handler = fn(&mut Args, &mut dyn Write) -> Result<(), Error>;
+
+pub struct Command {
+    pub name:  &'static str,
+    pub usage: &'static str,
+    pub help:  &'static str,
+    pub run:   Handler
+}
+
+// Registered during boot before the shell starts, kept sorted by name for help and completion
+static mut COMMANDS: Vec<Command> = Vec::new();
+
+pub fn register(command: Command) {
+    let commands = unsafe { &mut *core::ptr::addr_of_mut!(COMMANDS) };
+
+    match commands.binary_search_by(|c| c.name.cmp(command.name)) {
+        Ok(_)      => panic!("Command {} already registered", command.name),
+        Err(index) => commands.insert(index, command)
+    }
+}
+
+pub fn commands() -> &'static [Command] {
+    unsafe { &*core::ptr::addr_of!(COMMANDS) }
+}
+
+pub fn execute(line: &str, out: &mut dyn Write) -> Result<(), Error> {
+    let mut args = Args::new(line);
+    let name = args.command();
+    if name.is_empty() { return Ok(()); }
+
+    let command = commands()
+        .iter()
+        .find(|c| c.name == name)
+        .ok_or(Error::UnknownCommand(name.into()))?;
+
+    (command.run)(&mut args, out)
+}
+
+pub async fn run() {
+    let mut serial = input::Serial::default();
+    let mut editor = Editor::new();
+    let names: Vec<&str> = commands().iter().map(|c| c.name).collect();
+
+    let mut mark = prompt();
+    render(mark, "", "", true);
+
+    loop {
+        let key = input::next(keyboard::get(), &mut serial).await;
+
+        if key == Key::Enter {
+            render(mark, &editor.before_cursor(), &editor.after_cursor(), false);
+        }
+
+        match editor.handle(key, &names) {
+            Event::Redraw => render(mark, &editor.before_cursor(), &editor.after_cursor(), true),
+            Event::Submit(line) => {
+                println!();
+
+                if let Err(e) = execute(&line, &mut Console) {
+                    println!("{e}");
+                }
+
+                mark = prompt();
+                render(mark, "", "", true);
+            }
+            Event::Candidates(candidates) => {
+                render(mark, &editor.before_cursor(), &editor.after_cursor(), false);
+                println!();
+                println!("{}", candidates.join("  "));
+
+                mark = prompt();
+                render(mark, &editor.before_cursor(), &editor.after_cursor(), true);
+            }
+            Event::None => {}
+        }
+    }
+}
+
+fn prompt() -> Option<Mark> {
+    print!("{PROMPT}");
+    video::printer().map(|printer| printer.mark())
+}
+
+// The whole line is redrawn on every change, which is cheap next to rasterising the glyphs anyway
+fn render(mark: Option<Mark>, before: &str, after: &str, cursor: bool) {
+    let output = console::output();
+
+    if let (Some(printer), Some(mark), true) = (video::printer(), mark, output != Output::Serial) {
+        printer.erase_from(mark);
+        let _ = printer.write_str(before);
+        let at = printer.mark();
+        let _ = printer.write_str(after);
+        if cursor { printer.draw_cursor(at); }
+    }
+
+    if output != Output::Framebuffer {
+        let serial = console::serial();
+        let _ = write!(serial, "\r\x1b[K{PROMPT}{before}{after}");
+
+        let back = after.chars().count();
+        if back > 0 { let _ = write!(serial, "\x1b[{back}D"); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn numbers_accept_hex_and_decimal() {
+        let mut args = Args::new("x 0x1f 42 0xffff_8000_0000_0000");
+        assert_eq!(args.number::<u8>("a").unwrap(), 0x1f);
+        assert_eq!(args.number::<u64>("b").unwrap(), 42);
+        assert_eq!(args.number::<u64>("c").unwrap(), 0xffff_8000_0000_0000);
+        assert!(matches!(args.number::<u64>("d"), Err(Error::MissingArgument("d"))));
+    }
+
+    #[test_case]
+    fn numbers_must_fit_the_type() {
+        let mut args = Args::new("x 0x10000 0xzz");
+        assert!(matches!(args.number::<u16>("port"), Err(Error::InvalidNumber(arg)) if arg == "0x10000"));
+        assert!(matches!(args.optional_number::<u64>(), Err(Error::InvalidNumber(_))));
+        assert!(matches!(args.optional_number::<u64>(), Ok(None)));
+    }
+
+    #[test_case]
+    fn end_rejects_leftovers() {
+        let mut args = Args::new("x a b");
+        assert_eq!(args.command(), "x");
+        assert_eq!(args.word("first").unwrap(), "a");
+        assert!(matches!(args.end(), Err(Error::UnexpectedArgument(arg)) if arg == "b"));
+    }
+}
@@ -0,0 +1,51 @@
+use core::fmt::Write;
+
+use crate::{drivers, time};
+use crate::drivers::console::{self, Output};
+use crate::shell::{self, Args, Command, Error};
+
+pub fn register() {
+    shell::register(Command { name: "help",   usage: "help [command]", help: "list commands or describe one", run: help });
+    shell::register(Command { name: "clear",  usage: "clear",          help: "clear the screen",              run: clear });
+    shell::register(Command { name: "uptime", usage: "uptime",         help: "time since boot",               run: uptime });
+}
+
+fn help(args: &mut Args, out: &mut dyn Write) -> Result<(), Error> {
+    let name = args.optional_word();
+    args.end()?;
+
+    if let Some(name) = name {
+        let command = shell::commands().iter().find(|c| c.name == name).ok_or(Error::UnknownCommand(name.into()))?;
+        writeln!(out, "{}\n  {}", command.usage, command.help)?;
+        return Ok(());
+    }
+
+    for command in shell::commands() {
+        writeln!(out, "{:28} {}", command.usage, command.help)?;
+    }
+
+    Ok(())
+}
+
+fn clear(args: &mut Args, _: &mut dyn Write) -> Result<(), Error> {
+    args.end()?;
+
+    if let Some(printer) = drivers::video::printer() {
+        printer.clear();
+    }
+
+    if console::output() != Output::Framebuffer {
+        let _ = console::serial().write_str("\x1b[2J\x1b[H");
+    }
+
+    Ok(())
+}
+
+fn uptime(args: &mut Args, out: &mut dyn Write) -> Result<(), Error> {
+    args.end()?;
+
+    let secs = time::uptime().as_secs();
+    writeln!(out, "up {}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)?;
+
+    Ok(())
+}
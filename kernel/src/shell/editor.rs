@@ -0,0 +1,238 @@
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const HISTORY_SIZE: usize = 64;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Key {
+    Char(char),
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Home,
+    End,
+    Up,
+    Down,
+    Tab,
+    Enter
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum Event<'a> {
+    Redraw,
+    Submit(String),
+    Candidates(Vec<&'a str>),
+    None
+}
+
+#[derive(Default)]
+pub struct Editor {
+    line:    Vec<char>,
+    cursor:  usize,
+    history: Vec<String>,
+    // Index into history while browsing it, the line being typed is kept in draft
+    browse:  Option<usize>,
+    draft:   String
+}
+
+impl Editor {
+    pub fn new() -> Editor {
+        Editor::default()
+    }
+
+    pub fn before_cursor(&self) -> String {
+        self.line[..self.cursor].iter().collect()
+    }
+
+    pub fn after_cursor(&self) -> String {
+        self.line[self.cursor..].iter().collect()
+    }
+
+    fn set_line(&mut self, line: &str) {
+        self.line = line.chars().collect();
+        self.cursor = self.line.len();
+    }
+
+    pub fn handle<'a>(&mut self, key: Key, commands: &[&'a str]) -> Event<'a> {
+        match key {
+            Key::Char(c) => {
+                self.line.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            Key::Backspace => {
+                if self.cursor == 0 { return Event::None; }
+                self.cursor -= 1;
+                self.line.remove(self.cursor);
+            }
+            Key::Delete => {
+                if self.cursor == self.line.len() { return Event::None; }
+                self.line.remove(self.cursor);
+            }
+            Key::Left  => self.cursor = self.cursor.saturating_sub(1),
+            Key::Right => self.cursor = (self.cursor + 1).min(self.line.len()),
+            Key::Home  => self.cursor = 0,
+            Key::End   => self.cursor = self.line.len(),
+            Key::Up    => self.older(),
+            Key::Down  => self.newer(),
+            Key::Tab   => return self.complete(commands),
+            Key::Enter => return Event::Submit(self.submit())
+        }
+
+        Event::Redraw
+    }
+
+    fn older(&mut self) {
+        let index = match self.browse {
+            Some(0)     => return,
+            Some(index) => index - 1,
+            None        => {
+                if self.history.is_empty() { return; }
+                self.draft = self.line.iter().collect();
+                self.history.len() - 1
+            }
+        };
+
+        self.browse = Some(index);
+        let line = self.history[index].clone();
+        self.set_line(&line);
+    }
+
+    fn newer(&mut self) {
+        let Some(index) = self.browse else { return; };
+
+        if index + 1 < self.history.len() {
+            self.browse = Some(index + 1);
+            let line = self.history[index + 1].clone();
+            self.set_line(&line);
+        } else {
+            self.browse = None;
+            let draft = core::mem::take(&mut self.draft);
+            self.set_line(&draft);
+        }
+    }
+
+    // Only the command name is completed, arguments belong to the command
+    fn complete<'a>(&mut self, commands: &[&'a str]) -> Event<'a> {
+        let before = self.before_cursor();
+        if before.contains(' ') { return Event::None; }
+
+        let candidates: Vec<&str> = commands.iter().copied().filter(|c| c.starts_with(before.as_str())).collect();
+        let Some(first) = candidates.first() else { return Event::None; };
+
+        let common = candidates.iter().fold(first.len(), |len, c| {
+            first.bytes().zip(c.bytes()).take(len).take_while(|(a, b)| a == b).count()
+        });
+
+        if common > before.len() || candidates.len() == 1 {
+            let mut completion: String = first[before.len()..common].into();
+            if candidates.len() == 1 && self.cursor == self.line.len() { completion.push(' '); }
+
+            for c in completion.chars() {
+                self.line.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+
+            return Event::Redraw;
+        }
+
+        Event::Candidates(candidates)
+    }
+
+    fn submit(&mut self) -> String {
+        let line: String = self.line.iter().collect();
+
+        if !line.trim().is_empty() && self.history.last() != Some(&line) {
+            if self.history.len() == HISTORY_SIZE { self.history.remove(0); }
+            self.history.push(line.clone());
+        }
+
+        self.line.clear();
+        self.cursor = 0;
+        self.browse = None;
+        self.draft.clear();
+
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMANDS: &[&str] = &["help", "history", "lspci", "acpi"];
+
+    fn typed(text: &str) -> Editor {
+        let mut editor = Editor::new();
+        for c in text.chars() { editor.handle(Key::Char(c), COMMANDS); }
+        editor
+    }
+
+    #[test_case]
+    fn edits_at_the_cursor() {
+        let mut editor = typed("acpi");
+        editor.handle(Key::Left, COMMANDS);
+        editor.handle(Key::Left, COMMANDS);
+        editor.handle(Key::Backspace, COMMANDS);
+        editor.handle(Key::Char('x'), COMMANDS);
+        editor.handle(Key::Delete, COMMANDS);
+
+        assert_eq!(editor.before_cursor(), "ax");
+        assert_eq!(editor.after_cursor(), "i");
+
+        editor.handle(Key::Home, COMMANDS);
+        assert_eq!(editor.before_cursor(), "");
+        assert_eq!(editor.handle(Key::Backspace, COMMANDS), Event::None);
+    }
+
+    #[test_case]
+    fn enter_submits_and_resets() {
+        let mut editor = typed("lspci");
+        assert_eq!(editor.handle(Key::Enter, COMMANDS), Event::Submit("lspci".into()));
+        assert_eq!(editor.before_cursor(), "");
+    }
+
+    #[test_case]
+    fn history_walks_back_and_restores_the_draft() {
+        let mut editor = Editor::new();
+        for line in ["one", "two", "two", " "] {
+            for c in line.chars() { editor.handle(Key::Char(c), COMMANDS); }
+            editor.handle(Key::Enter, COMMANDS);
+        }
+        assert_eq!(editor.history, ["one", "two"]);
+
+        for c in "dr".chars() { editor.handle(Key::Char(c), COMMANDS); }
+
+        editor.handle(Key::Up, COMMANDS);
+        assert_eq!(editor.before_cursor(), "two");
+        editor.handle(Key::Up, COMMANDS);
+        editor.handle(Key::Up, COMMANDS);
+        assert_eq!(editor.before_cursor(), "one");
+
+        editor.handle(Key::Down, COMMANDS);
+        editor.handle(Key::Down, COMMANDS);
+        assert_eq!(editor.before_cursor(), "dr");
+    }
+
+    #[test_case]
+    fn tab_completes_unique_names() {
+        let mut editor = typed("ls");
+        assert_eq!(editor.handle(Key::Tab, COMMANDS), Event::Redraw);
+        assert_eq!(editor.before_cursor(), "lspci ");
+    }
+
+    #[test_case]
+    fn tab_extends_common_prefix_then_lists() {
+        let mut editor = typed("h");
+        assert_eq!(editor.handle(Key::Tab, COMMANDS), Event::Candidates(["help", "history"].into()));
+
+        let mut editor = typed("hi");
+        editor.handle(Key::Tab, COMMANDS);
+        assert_eq!(editor.before_cursor(), "history ");
+
+        let mut editor = typed("acpi x");
+        assert_eq!(editor.handle(Key::Tab, COMMANDS), Event::None);
+    }
+}
@@ -0,0 +1,154 @@
+use core::time::Duration;
+use futures_util::StreamExt;
+use futures_util::future::{self, Either};
+use pc_keyboard::{DecodedKey, KeyCode};
+
+use crate::drivers::console::{self, Output};
+use crate::drivers::keyboard::Keyboard;
+use crate::shell::editor::Key;
+use crate::task::timer;
+
+const SERIAL_POLL: Duration = Duration::from_millis(10);
+// ESC [ 1 ; 5 A is about as long as it gets for keys, longer sequences are skipped without being kept
+const SEQUENCE_MAX: usize = 8;
+
+pub fn from_keyboard(key: DecodedKey) -> Option<Key> {
+    match key {
+        DecodedKey::Unicode('\n')   => Some(Key::Enter),
+        DecodedKey::Unicode('\x08') => Some(Key::Backspace),
+        DecodedKey::Unicode('\t')   => Some(Key::Tab),
+        DecodedKey::Unicode('\x7f') => Some(Key::Delete),
+        DecodedKey::Unicode(c) if !c.is_control() => Some(Key::Char(c)),
+        DecodedKey::RawKey(code) => match code {
+            KeyCode::ArrowLeft  => Some(Key::Left),
+            KeyCode::ArrowRight => Some(Key::Right),
+            KeyCode::ArrowUp    => Some(Key::Up),
+            KeyCode::ArrowDown  => Some(Key::Down),
+            KeyCode::Home       => Some(Key::Home),
+            KeyCode::End        => Some(Key::End),
+            _                   => None
+        },
+        _ => None
+    }
+}
+
+// Terminals send the cursor keys as ESC [ <letter>, ESC O <letter> or ESC [ <number> ~. A CSI sequence
+// runs until its final byte, anything in it that is not understood is dropped as a whole
+#[derive(Default)]
+pub struct Serial {
+    sequence: [u8; SEQUENCE_MAX],
+    len:      usize
+}
+
+impl Serial {
+    pub fn feed(&mut self, byte: u8) -> Option<Key> {
+        if self.len == 0 {
+            return match byte {
+                0x1b                => { self.push(byte); None }
+                b'\r' | b'\n'       => Some(Key::Enter),
+                0x08 | 0x7f         => Some(Key::Backspace),
+                b'\t'               => Some(Key::Tab),
+                0x20..=0x7e         => Some(Key::Char(byte as char)),
+                _                   => None
+            };
+        }
+
+        let csi = self.len >= 2 && self.sequence[1] == b'[';
+        match byte {
+            b'[' | b'O' if self.len == 1 => {
+                self.push(byte);
+                return None;
+            }
+            // Parameter and intermediate bytes, the sequence goes on
+            0x20..=0x3f if csi => {
+                self.push(byte);
+                return None;
+            }
+            0x40..=0x7e => self.push(byte),
+            // A control byte or a broken sequence, which takes the byte with it
+            _ => {
+                self.len = 0;
+                return None;
+            }
+        }
+
+        let key = match self.sequence.get(..self.len).unwrap_or_default() {
+            [0x1b, _, b'A']                 => Some(Key::Up),
+            [0x1b, _, b'B']                 => Some(Key::Down),
+            [0x1b, _, b'C']                 => Some(Key::Right),
+            [0x1b, _, b'D']                 => Some(Key::Left),
+            [0x1b, _, b'H']                 => Some(Key::Home),
+            [0x1b, _, b'F']                 => Some(Key::End),
+            [0x1b, b'[', b'1' | b'7', b'~'] => Some(Key::Home),
+            [0x1b, b'[', b'4' | b'8', b'~'] => Some(Key::End),
+            [0x1b, b'[', b'3', b'~']        => Some(Key::Delete),
+            _                               => None
+        };
+
+        self.len = 0;
+        key
+    }
+
+    // Past the end only the length grows, which no known sequence matches
+    fn push(&mut self, byte: u8) {
+        if let Some(slot) = self.sequence.get_mut(self.len) { *slot = byte; }
+        self.len += 1;
+    }
+}
+
+// The serial port has no interrupt wired up, so it is polled between keystrokes
+pub async fn next(kb: &mut Keyboard, serial: &mut Serial) -> Key {
+    loop {
+        if console::output() == Output::Framebuffer {
+            if let Some(key) = kb.next().await.and_then(from_keyboard) { return key; }
+            continue;
+        }
+
+        while let Some(byte) = console::serial().read_byte() {
+            if let Some(key) = serial.feed(byte) { return key; }
+        }
+
+        if let Either::Left((Some(key), _)) = future::select(kb.next(), timer::sleep(SERIAL_POLL)).await {
+            if let Some(key) = from_keyboard(key) { return key; }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(bytes: &[u8]) -> [Option<Key>; 3] {
+        let mut serial = Serial::default();
+        let mut keys = bytes.iter().filter_map(|&byte| serial.feed(byte));
+        core::array::from_fn(|_| keys.next())
+    }
+
+    #[test_case]
+    fn serial_decodes_plain_bytes() {
+        assert_eq!(keys(b"a\r\x7f"), [Some(Key::Char('a')), Some(Key::Enter), Some(Key::Backspace)]);
+    }
+
+    #[test_case]
+    fn serial_decodes_escape_sequences() {
+        assert_eq!(keys(b"\x1b[A\x1b[3~\x1bOH"), [Some(Key::Up), Some(Key::Delete), Some(Key::Home)]);
+    }
+
+    #[test_case]
+    fn serial_drops_unknown_sequences() {
+        assert_eq!(keys(b"\x1b[Zx"), [Some(Key::Char('x')), None, None]);
+    }
+
+    #[test_case]
+    fn serial_skips_long_sequences_whole() {
+        assert_eq!(keys(b"\x1b[15~a\x1b[1;5Ab"), [Some(Key::Char('a')), Some(Key::Char('b')), None]);
+        assert_eq!(keys(b"\x1b[1;2;3;4;5;6;7~\x1b[D"), [Some(Key::Left), None, None]);
+    }
+
+    #[test_case]
+    fn keyboard_maps_editing_keys() {
+        assert_eq!(from_keyboard(DecodedKey::Unicode('\x08')), Some(Key::Backspace));
+        assert_eq!(from_keyboard(DecodedKey::RawKey(KeyCode::ArrowLeft)), Some(Key::Left));
+        assert_eq!(from_keyboard(DecodedKey::RawKey(KeyCode::F1)), None);
+    }
+}
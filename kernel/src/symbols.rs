@@ -0,0 +1,108 @@
+use core::fmt::{self, Display, Formatter};
+use core::str;
+
+use crate::bootinfo::Region;
+use crate::memory;
+
+// Elf64_Sym: name u32, info u8, other u8, shndx u16, value u64, size u64
+const SYM_SIZE: usize = 24;
+const STT_FUNC: u8 = 2;
+
+struct Symbols {
+    symtab: &'static [u8],
+    strtab: &'static [u8],
+    base:   u64
+}
+
+static mut SYMBOLS: Option<Symbols> = None;
+
+pub fn init(symtab: Region, strtab: Region) {
+    let symbols = unsafe { Symbols { symtab: symtab.as_slice(), strtab: strtab.as_slice(), base: memory::kernel_base() } };
+    unsafe { SYMBOLS = Some(symbols); }
+}
+
+// A linear scan, it only runs on panics and faults so it is not worth sorting or allocating
+pub fn lookup(addr: u64) -> Option<(&'static str, u64)> {
+    let symbols = unsafe { SYMBOLS.as_ref()? };
+    let addr = addr.checked_sub(symbols.base)?;
+
+    symbols.symtab.chunks_exact(SYM_SIZE)
+        .find_map(|sym| {
+            let word = |i: usize| u64::from_le_bytes(sym[i..i + 8].try_into().expect("Impossible"));
+            let (value, size) = (word(8), word(16));
+
+            if sym[4] & 0xf != STT_FUNC || addr < value || addr >= value + size.max(1) { return None; }
+
+            let name = u32::from_le_bytes(sym[0..4].try_into().expect("Impossible")) as usize;
+            let name = symbols.strtab.get(name..)?;
+            let end = name.iter().position(|&b| b == 0)?;
+
+            Some((str::from_utf8(&name[..end]).ok()?, addr - value))
+        })
+}
+
+// Prints an address as function+offset when it falls inside a known function
+pub struct Symbol(pub u64);
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match lookup(self.0) {
+            Some((name, offset)) => write!(f, "0x{:016x} {}+0x{offset:x}", self.0, Demangle(name)),
+            None                 => write!(f, "0x{:016x} ???", self.0)
+        }
+    }
+}
+
+// Legacy Rust mangling: _ZN <len><ident>... 17h<hash> E, anything else is printed as is
+struct Demangle<'a>(&'a str);
+
+impl Display for Demangle<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let Some(mut rest) = self.0.strip_prefix("_ZN").and_then(|s| s.strip_suffix('E')) else {
+            return f.write_str(self.0);
+        };
+
+        let mut first = true;
+        while !rest.is_empty() {
+            let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+            let Ok(len) = rest[..digits].parse::<usize>() else { return f.write_str(self.0); };
+            let Some(ident) = rest.get(digits..digits + len) else { return f.write_str(self.0); };
+            rest = &rest[digits + len..];
+
+            let is_hash = rest.is_empty() && ident.len() == 17 && ident.starts_with('h');
+            if is_hash { break; }
+
+            if !first { f.write_str("::")?; }
+            first = false;
+
+            write_ident(f, ident.strip_prefix('_').filter(|s| s.starts_with('$')).unwrap_or(ident))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_ident(f: &mut Formatter, mut ident: &str) -> fmt::Result {
+    const ESCAPES: &[(&str, &str)] = &[
+        ("$LT$", "<"), ("$GT$", ">"), ("$RF$", "&"), ("$BP$", "*"), ("$C$", ","),
+        ("$SP$", "@"), ("$LP$", "("), ("$RP$", ")"), ("$u20$", " "), ("$u27$", "'"),
+        ("$u5b$", "["), ("$u5d$", "]"), ("$u7b$", "{"), ("$u7d$", "}"), ("$u7e$", "~"),
+        ("..", "::")
+    ];
+
+    while !ident.is_empty() {
+        match ESCAPES.iter().find(|(escape, _)| ident.starts_with(escape)) {
+            Some((escape, text)) => {
+                f.write_str(text)?;
+                ident = &ident[escape.len()..];
+            }
+            None => {
+                let len = ident.chars().next().map(char::len_utf8).unwrap_or(1);
+                f.write_str(&ident[..len])?;
+                ident = &ident[len..];
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,223 @@
+use core::arch::naked_asm;
+use core::slice;
+use log::{info, warn};
+use x86_64::VirtAddr;
+use x86_64::instructions::interrupts;
+use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask, Star};
+use x86_64::registers::rflags::RFlags;
+use x86_64::structures::paging::PageTableFlags;
+
+use crate::{gdt, memory, print, process, time};
+use crate::abi::{number, prot, Error, PAGE_SIZE, STDOUT};
+use crate::drivers::keyboard;
+use crate::memory::address_space::AddressSpace;
+use crate::process::ExitStatus;
+
+type Handler = fn(&[u64; 6]) -> Result<u64, Error>;
+
+static TABLE: [Handler; number::COUNT] = [
+    sys_write,
+    sys_read_key,
+    sys_exit,
+    sys_sleep,
+    sys_time,
+    sys_mmap,
+    sys_munmap
+];
+
+// SYSCALL does not switch stacks, the entry stub does it through these
+static mut KERNEL_STACK: u64 = 0;
+static mut USER_STACK: u64 = 0;
+
+pub fn init() {
+    info!("Initializing..");
+
+    let selectors = gdt::selectors();
+
+    unsafe {
+        Efer::update(|flags| flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS));
+        Star::write(selectors.user_code, selectors.user_data, selectors.kernel_code, selectors.kernel_data).unwrap();
+        LStar::write(VirtAddr::new(syscall_entry as *const () as u64));
+    }
+
+    SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::TRAP_FLAG | RFlags::DIRECTION_FLAG);
+
+    info!("Success");
+}
+
+pub fn set_kernel_stack(top: VirtAddr) {
+    unsafe { KERNEL_STACK = top.as_u64(); }
+}
+
+extern "sysv64" fn dispatch(number: u64, a0: u64, a1: u64, a2: u64, a3: u64, a4: u64, a5: u64) -> i64 {
+    let Some(handler) = TABLE.get(number as usize) else {
+        return -Error::NoSys.code();
+    };
+
+    match handler(&[a0, a1, a2, a3, a4, a5]) {
+        Ok(ret) => ret as i64,
+        Err(e)  => -e.code()
+    }
+}
+
+fn user_slice(ptr: u64, len: u64, flags: PageTableFlags) -> Result<&'static [u8], Error> {
+    let process = process::current().ok_or(Error::BadAddress)?;
+
+    if !process.space().is_mapped(ptr, len, flags) {
+        return Err(Error::BadAddress);
+    }
+
+    Ok(unsafe { slice::from_raw_parts(ptr as *const u8, len as usize) })
+}
+
+fn blocking_wait() {
+    // SFMASK cleared IF on entry, the wait itself must let interrupts through
+    interrupts::enable_and_hlt();
+    interrupts::disable();
+}
+
+fn sys_write(args: &[u64; 6]) -> Result<u64, Error> {
+    let [fd, buf, len, ..] = *args;
+    if fd != STDOUT { return Err(Error::BadDescriptor); }
+
+    let buf = user_slice(buf, len, PageTableFlags::empty())?;
+    for chunk in buf.utf8_chunks() {
+        print!("{}", chunk.valid());
+    }
+
+    Ok(len)
+}
+
+fn sys_read_key(_args: &[u64; 6]) -> Result<u64, Error> {
+    let kb = keyboard::get();
+
+    loop {
+        if let Ok(Some(c)) = kb.read_char() {
+            return Ok(c as u64);
+        }
+
+        blocking_wait();
+    }
+}
+
+fn sys_exit(args: &[u64; 6]) -> Result<u64, Error> {
+    process::exit(ExitStatus::Exited(args[0] as i64))
+}
+
+fn sys_sleep(args: &[u64; 6]) -> Result<u64, Error> {
+    let deadline = time::ticks() + time::ticks_from(core::time::Duration::from_millis(args[0]));
+
+    while time::ticks() < deadline {
+        blocking_wait();
+    }
+
+    Ok(0)
+}
+
+fn sys_time(_args: &[u64; 6]) -> Result<u64, Error> {
+    Ok(time::uptime().as_millis() as u64)
+}
+
+fn sys_mmap(args: &[u64; 6]) -> Result<u64, Error> {
+    let [hint, len, protection, ..] = *args;
+
+    if len == 0 || protection & !(prot::READ | prot::WRITE | prot::EXEC) != 0 || hint % PAGE_SIZE != 0 {
+        return Err(Error::InvalidArgument);
+    }
+
+    let len = pages(len)?;
+    let process = process::current().ok_or(Error::BadAddress)?;
+
+    let start = if hint != 0 {
+        // Checked before the walk, which would otherwise go on for as long as the caller likes
+        AddressSpace::check_user(hint, len).map_err(|_| Error::InvalidArgument)?;
+        if process.space().last_mapped(hint, len).is_some() { return Err(Error::InvalidArgument); }
+
+        hint
+    } else {
+        process.reserve_mmap(len).ok_or(Error::NoMemory)?
+    };
+
+    let mut flags = PageTableFlags::empty();
+    if protection & prot::WRITE != 0 { flags |= PageTableFlags::WRITABLE; }
+    if protection & prot::EXEC == 0 { flags |= memory::no_execute(); }
+
+    // On failure map_anonymous takes back whatever part it had mapped
+    process.space()
+        .map_anonymous(start, len, flags)
+        .map_err(|e| {
+            warn!("mmap failed: {e}");
+            Error::NoMemory
+        })?;
+
+    Ok(start)
+}
+
+fn sys_munmap(args: &[u64; 6]) -> Result<u64, Error> {
+    let [start, len, ..] = *args;
+
+    if len == 0 || start % PAGE_SIZE != 0 {
+        return Err(Error::InvalidArgument);
+    }
+
+    let len = pages(len)?;
+    AddressSpace::check_user(start, len).map_err(|_| Error::InvalidArgument)?;
+
+    let process = process::current().ok_or(Error::BadAddress)?;
+    process.space()
+        .unmap(start, len)
+        .map_err(|_| Error::InvalidArgument)?;
+
+    Ok(0)
+}
+
+// Rounds a length from user space up to whole pages, which align_up would panic on near u64::MAX
+fn pages(len: u64) -> Result<u64, Error> {
+    len.checked_add(PAGE_SIZE - 1).map(|len| len & !(PAGE_SIZE - 1)).ok_or(Error::InvalidArgument)
+}
+
+#[unsafe(naked)]
+unsafe extern "sysv64" fn syscall_entry() {
+    naked_asm!(
+        "mov [rip + {user_stack}], rsp",
+        "mov rsp, [rip + {kernel_stack}]",
+
+        // Return address and flags for SYSRET, then everything the ABI promises to preserve
+        "push rcx",
+        "push r11",
+        "push rdi",
+        "push rsi",
+        "push rdx",
+        "push r10",
+        "push r8",
+        "push r9",
+
+        // Shuffle into the System V order, the sixth argument goes on the (aligned) stack
+        "sub rsp, 8",
+        "push r9",
+        "mov r9, r8",
+        "mov r8, r10",
+        "mov rcx, rdx",
+        "mov rdx, rsi",
+        "mov rsi, rdi",
+        "mov rdi, rax",
+        "call {dispatch}",
+        "add rsp, 16",
+
+        "pop r9",
+        "pop r8",
+        "pop r10",
+        "pop rdx",
+        "pop rsi",
+        "pop rdi",
+        "pop r11",
+        "pop rcx",
+
+        "mov rsp, [rip + {user_stack}]",
+        "sysretq",
+
+        user_stack = sym USER_STACK,
+        kernel_stack = sym KERNEL_STACK,
+        dispatch = sym dispatch
+    )
+}
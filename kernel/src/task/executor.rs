@@ -0,0 +1,148 @@
+extern crate alloc;
+
+use core::future::Future;
+use core::pin::pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use crossbeam_queue::ArrayQueue;
+use x86_64::instructions::interrupts;
+
+use crate::task::{timer, Task, TaskId};
+
+const QUEUE_SIZE: usize = 128;
+
+pub struct Executor {
+    tasks:  BTreeMap<TaskId, Task>,
+    queue:  Arc<ArrayQueue<TaskId>>,
+    wakers: BTreeMap<TaskId, Arc<TaskWaker>>
+}
+
+impl Executor {
+    pub fn new() -> Executor {
+        Executor {
+            tasks:  BTreeMap::new(),
+            queue:  Arc::new(ArrayQueue::new(QUEUE_SIZE)),
+            wakers: BTreeMap::new()
+        }
+    }
+
+    pub fn spawn(&mut self, future: impl Future<Output = ()> + 'static) {
+        let task = Task::new(future);
+        let id = task.id();
+        let waker = TaskWaker::new(id, self.queue.clone());
+
+        self.tasks.insert(id, task);
+        waker.wake_by_ref();
+        self.wakers.insert(id, waker);
+    }
+
+    pub fn run(&mut self) -> ! {
+        loop {
+            timer::wake_expired();
+            self.run_ready();
+            self.sleep_if_idle();
+        }
+    }
+
+    fn run_ready(&mut self) {
+        while let Some(id) = self.queue.pop() {
+            let (Some(task), Some(waker)) = (self.tasks.get_mut(&id), self.wakers.get(&id)) else { continue; };
+
+            // Cleared before the poll, so a wakeup from inside it queues the task again
+            waker.queued.store(false, Ordering::Release);
+            let waker = Waker::from(waker.clone());
+            let mut cx = Context::from_waker(&waker);
+
+            if task.poll(&mut cx).is_ready() {
+                self.tasks.remove(&id);
+                self.wakers.remove(&id);
+            }
+        }
+    }
+
+    fn sleep_if_idle(&self) {
+        // Interrupts are disabled across the check so a wakeup cannot slip in between it and hlt
+        interrupts::disable();
+
+        if self.queue.is_empty() && !timer::has_expired() {
+            interrupts::enable_and_hlt();
+        } else {
+            interrupts::enable();
+        }
+    }
+}
+
+impl Default for Executor {
+    fn default() -> Executor {
+        Executor::new()
+    }
+}
+
+struct TaskWaker {
+    id:     TaskId,
+    queue:  Arc<ArrayQueue<TaskId>>,
+    queued: AtomicBool
+}
+
+impl TaskWaker {
+    fn new(id: TaskId, queue: Arc<ArrayQueue<TaskId>>) -> Arc<TaskWaker> {
+        Arc::new(TaskWaker { id, queue, queued: AtomicBool::new(false) })
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        // Each task is queued at most once, so only more tasks than QUEUE_SIZE can fill the queue
+        if !self.queued.swap(true, Ordering::AcqRel) {
+            self.queue.push(self.id).expect("More tasks than the executor queue holds");
+        }
+    }
+}
+
+struct FlagWaker {
+    woken: AtomicBool
+}
+
+impl Wake for FlagWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::Release);
+    }
+}
+
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let flag = Arc::new(FlagWaker { woken: AtomicBool::new(true) });
+    let waker = Waker::from(flag.clone());
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+
+    loop {
+        timer::wake_expired();
+
+        if flag.woken.swap(false, Ordering::Acquire) {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+
+            continue;
+        }
+
+        interrupts::disable();
+
+        if !flag.woken.load(Ordering::Acquire) && !timer::has_expired() {
+            interrupts::enable_and_hlt();
+        } else {
+            interrupts::enable();
+        }
+    }
+}
@@ -0,0 +1,72 @@
+extern crate alloc;
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use alloc::collections::BTreeMap;
+
+use crate::time;
+
+// Only touched from task context; the timer interrupt just advances the tick counter
+static mut TIMERS: BTreeMap<(u64, u64), Waker> = BTreeMap::new();
+static mut NEXT_ID: u64 = 0;
+
+pub fn wake_expired() {
+    let now = time::ticks();
+
+    unsafe {
+        while let Some(entry) = TIMERS.first_entry() {
+            if entry.key().0 > now { break; }
+            entry.remove().wake();
+        }
+    }
+}
+
+pub fn has_expired() -> bool {
+    unsafe {
+        TIMERS
+            .first_key_value()
+            .is_some_and(|((deadline, _), _)| *deadline <= time::ticks())
+    }
+}
+
+pub struct Sleep {
+    deadline: u64,
+    id:       Option<u64>
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if time::ticks() >= self.deadline {
+            return Poll::Ready(());
+        }
+
+        let id = *self.id.get_or_insert_with(|| unsafe {
+            NEXT_ID += 1;
+            NEXT_ID
+        });
+
+        unsafe { TIMERS.insert((self.deadline, id), cx.waker().clone()); }
+
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            unsafe { TIMERS.remove(&(self.deadline, id)); }
+        }
+    }
+}
+
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(time::ticks() + time::ticks_from(duration))
+}
+
+pub fn sleep_until(deadline: u64) -> Sleep {
+    Sleep { deadline, id: None }
+}
@@ -0,0 +1,140 @@
+use core::any;
+
+// The same #[test_case] functions run inside a test kernel under QEMU and as a plain host binary,
+// anything touching real hardware is additionally gated on target_os = "none"
+
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        output(format_args!("{} ... ", any::type_name::<T>()));
+        self();
+        output(format_args!("[ok]\n"));
+    }
+}
+
+#[cfg(target_os = "none")]
+#[path = "alloc.rs"]
+mod heap;
+
+#[cfg(target_os = "none")]
+pub use qemu::*;
+
+#[cfg(target_os = "none")]
+mod qemu {
+    use core::fmt::Arguments;
+    use core::panic::PanicInfo;
+    use x86_64::instructions::port::Port;
+
+    use super::Testable;
+    use crate::bootinfo::BootInfo;
+    use crate::drivers::console::{self, Output};
+    use crate::{cmdline, gdt, logger, memory, println};
+
+    // isa-debug-exit turns a write of v into QEMU exiting with (v << 1) | 1, test-runner.sh maps it back
+    #[derive(Clone, Copy)]
+    #[repr(u32)]
+    pub enum QemuExitCode {
+        Success = 0x10,
+        Failed  = 0x11
+    }
+
+    pub fn exit_qemu(code: QemuExitCode) -> ! {
+        unsafe { Port::<u32>::new(0xf4).write(code as u32); }
+
+        loop { x86_64::instructions::hlt(); }
+    }
+
+    pub fn output(args: Arguments) {
+        console::_print(args);
+    }
+
+    pub fn run(tests: &[&dyn Testable]) {
+        println!("Running {} tests", tests.len());
+
+        for test in tests {
+            test.run();
+        }
+
+        exit_qemu(QemuExitCode::Success);
+    }
+
+    #[no_mangle]
+    #[link_section = ".ltext.astart"]
+    extern "sysv64" fn astart(info: &'static mut BootInfo) -> ! {
+        cmdline::init(info.cmdline());
+        console::init(Output::Serial);
+        logger::init(cmdline::log_filter());
+        logger::attach_console();
+
+        unsafe { memory::init(info.free_ptr, info.free_size); }
+        gdt::init();
+
+        crate::test_main();
+
+        exit_qemu(QemuExitCode::Success);
+    }
+
+    #[panic_handler]
+    fn panic_handler(info: &PanicInfo) -> ! {
+        println!("[failed]\n{info}");
+        exit_qemu(QemuExitCode::Failed);
+    }
+}
+
+#[cfg(not(target_os = "none"))]
+pub use host::*;
+
+#[cfg(not(target_os = "none"))]
+mod host {
+    extern crate std;
+
+    use core::fmt::Arguments;
+    use std::io::Write;
+    use std::panic::{self, AssertUnwindSafe};
+
+    use super::Testable;
+
+    pub fn output(args: Arguments) {
+        let mut stdout = std::io::stdout();
+        let _ = stdout.write_fmt(args);
+        let _ = stdout.flush();
+    }
+
+    // Failures are collected instead of stopping the run, the panic hook has already printed the reason
+    pub fn run(tests: &[&dyn Testable]) {
+        std::println!("Running {} tests", tests.len());
+
+        let failed = tests.iter()
+            .filter(|test| {
+                let ok = panic::catch_unwind(AssertUnwindSafe(|| test.run())).is_ok();
+                if !ok { std::println!("[failed]"); }
+                !ok
+            })
+            .count();
+
+        std::println!("{} passed, {failed} failed", tests.len() - failed);
+
+        if failed > 0 { std::process::exit(1); }
+    }
+
+    // Compares against testdata/<name>, UPDATE_GOLDEN=1 rewrites the file instead
+    pub fn golden(name: &str, actual: &[u8]) {
+        let path = std::format!("{}/testdata/{name}", env!("CARGO_MANIFEST_DIR"));
+
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            let _ = std::fs::create_dir_all(std::format!("{}/testdata", env!("CARGO_MANIFEST_DIR")));
+            std::fs::write(&path, actual).expect("Unable to write golden file");
+            return;
+        }
+
+        let expected = std::fs::read(&path).unwrap_or_else(|e| panic!("{path}: {e}, run with UPDATE_GOLDEN=1 to create it"));
+
+        if expected != actual {
+            let _ = std::fs::write(std::format!("{path}.actual"), actual);
+            panic!("{path} does not match, the output was saved next to it");
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use x86_64::instructions::port::Port;
+
+pub const TICK_HZ: u64 = 1000;
+
+const PIT_FREQUENCY: u64 = 1193182;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+pub fn init() {
+    let divisor = (PIT_FREQUENCY / TICK_HZ) as u16;
+
+    unsafe {
+        // Channel 0, lobyte/hibyte, rate generator
+        Port::<u8>::new(0x43).write(0x34);
+
+        let mut data = Port::<u8>::new(0x40);
+        data.write(divisor as u8);
+        data.write((divisor >> 8) as u8);
+    }
+}
+
+pub fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+pub fn uptime() -> Duration {
+    Duration::from_millis(ticks() * 1000 / TICK_HZ)
+}
+
+pub fn ticks_from(duration: Duration) -> u64 {
+    (duration.as_millis() as u64 * TICK_HZ).div_ceil(1000)
+}
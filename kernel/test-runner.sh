@@ -0,0 +1,11 @@
+#!/bin/sh
+
+# Cargo runner for the kernel, see `cargo xtask test-runner`
+
+set -e
+
+KERNEL="$(realpath "$1")"
+shift
+
+cd "$(dirname "$0")/.."
+exec cargo run --quiet --package xtask -- test-runner "$KERNEL" "$@"
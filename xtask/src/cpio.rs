@@ -0,0 +1,61 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use anyhow::{Context, Result};
+
+// newc archive of everything below dir, names are relative and directories come before their contents
+pub fn pack(dir: &Path) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut ino = 1;
+
+    walk(dir, "", &mut out, &mut ino)?;
+    entry(&mut out, 0, "TRAILER!!!", 0, &[]);
+
+    Ok(out)
+}
+
+fn walk(dir: &Path, prefix: &str, out: &mut Vec<u8>, ino: &mut u32) -> Result<()> {
+    let mut children = fs::read_dir(dir)
+        .with_context(|| format!("Reading {}", dir.display()))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Sorted so the same tree always produces the same archive
+    children.sort_by_key(|child| child.file_name());
+
+    for child in children {
+        let name = format!("{prefix}{}", child.file_name().to_string_lossy());
+        let meta = child.metadata()?;
+        *ino += 1;
+
+        if meta.is_dir() {
+            entry(out, *ino, &name, 0o040755, &[]);
+            walk(&child.path(), &format!("{name}/"), out, ino)?;
+        } else if meta.is_file() {
+            let mode = 0o100000 | meta.permissions().mode() & 0o777;
+            entry(out, *ino, &name, mode, &fs::read(child.path())?);
+        }
+    }
+
+    Ok(())
+}
+
+fn entry(out: &mut Vec<u8>, ino: u32, name: &str, mode: u32, data: &[u8]) {
+    let nlink = if mode & 0o040000 != 0 { 2 } else { 1 };
+    let fields = [ino, mode, 0, 0, nlink, 0, data.len() as u32, 0, 0, 0, 0, name.len() as u32 + 1, 0];
+
+    out.extend(b"070701");
+    for field in fields {
+        out.extend(format!("{field:08x}").as_bytes());
+    }
+
+    out.extend(name.as_bytes());
+    out.push(0);
+    pad(out);
+
+    out.extend(data);
+    pad(out);
+}
+
+fn pad(out: &mut Vec<u8>) {
+    out.resize(out.len().next_multiple_of(4), 0);
+}
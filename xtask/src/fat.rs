@@ -0,0 +1,425 @@
+use anyhow::{anyhow, Result};
+
+use crate::gpt::SECTOR;
+
+const RESERVED_SECTORS: u32 = 32;
+const FAT_COUNT: u32 = 2;
+const ROOT_CLUSTER: u32 = 2;
+const DIR_ENTRY: usize = 32;
+
+// FAT32 needs at least this many clusters, otherwise firmware treats the volume as FAT16
+const MIN_CLUSTERS: u32 = 65525;
+
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LFN: u8 = 0x0f;
+
+// 2024-01-01, 00:00, FAT does not accept a zero date
+const DATE: u16 = (2024 - 1980) << 9 | 1 << 5 | 1;
+
+pub enum Node {
+    File(Vec<u8>),
+    Dir(Dir)
+}
+
+#[derive(Default)]
+pub struct Dir {
+    entries: Vec<(String, Node)>
+}
+
+impl Dir {
+    // Creates intermediate directories, paths use '/' and must not start with one
+    pub fn add(&mut self, path: &str, data: Vec<u8>) -> Result<()> {
+        let (dir, name) = match path.rsplit_once('/') {
+            Some((dir, name)) => (self.dir(dir)?, name),
+            None              => (self, path)
+        };
+
+        if dir.entries.iter().any(|(existing, _)| existing.eq_ignore_ascii_case(name)) {
+            return Err(anyhow!("{path} already exists"));
+        }
+
+        dir.entries.push((name.to_string(), Node::File(data)));
+        Ok(())
+    }
+
+    fn dir(&mut self, path: &str) -> Result<&mut Dir> {
+        let mut dir = self;
+
+        for part in path.split('/') {
+            let index = match dir.entries.iter().position(|(name, _)| name.eq_ignore_ascii_case(part)) {
+                Some(index) => index,
+                None        => {
+                    dir.entries.push((part.to_string(), Node::Dir(Dir::default())));
+                    dir.entries.len() - 1
+                }
+            };
+
+            dir = match &mut dir.entries[index].1 {
+                Node::Dir(child) => child,
+                Node::File(_)    => return Err(anyhow!("{part} is a file"))
+            };
+        }
+
+        Ok(dir)
+    }
+
+    fn size(&self, root: bool) -> usize {
+        let dots = if root { 0 } else { 2 };
+        let names = self.entries.iter().map(|(name, _)| lfn_count(name) + 1).sum::<usize>();
+        (dots + names + 1) * DIR_ENTRY
+    }
+}
+
+struct Layout {
+    sectors:         u32,
+    cluster_sectors: u32,
+    fat_sectors:     u32,
+    hidden:          u32
+}
+
+impl Layout {
+    fn new(sectors: u32, hidden: u32) -> Result<Layout> {
+        let too_small = || anyhow!("Volume of {sectors} sectors is too small for FAT32");
+
+        // Microsoft's table, 512 byte clusters up to 260MB and 4KB clusters above that
+        let cluster_sectors = if sectors as u64 * SECTOR as u64 <= 260 << 20 { 1 } else { 8 };
+        let fat_sectors = (sectors.checked_sub(RESERVED_SECTORS).ok_or_else(too_small)? / cluster_sectors + 2).div_ceil((SECTOR / 4) as u32);
+
+        let layout = Layout { sectors, cluster_sectors, fat_sectors, hidden };
+        if layout.sectors.checked_sub(layout.data_start()).is_none_or(|data| data / cluster_sectors < MIN_CLUSTERS) {
+            return Err(too_small());
+        }
+
+        Ok(layout)
+    }
+
+    fn data_start(&self) -> u32 {
+        RESERVED_SECTORS + FAT_COUNT * self.fat_sectors
+    }
+
+    fn clusters(&self) -> u32 {
+        (self.sectors - self.data_start()) / self.cluster_sectors
+    }
+
+    fn cluster_bytes(&self) -> usize {
+        self.cluster_sectors as usize * SECTOR
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> usize {
+        (self.data_start() + (cluster - 2) * self.cluster_sectors) as usize * SECTOR
+    }
+}
+
+struct Writer<'a> {
+    volume: &'a mut [u8],
+    layout: Layout,
+    fat:    Vec<u32>,
+    next:   u32
+}
+
+impl Writer<'_> {
+    // Every file and directory gets one contiguous run of clusters
+    fn alloc(&mut self, bytes: usize) -> Result<u32> {
+        if bytes == 0 { return Ok(0); }
+
+        let count = bytes.div_ceil(self.layout.cluster_bytes()) as u32;
+        let first = self.next;
+
+        if first + count > self.layout.clusters() + 2 {
+            return Err(anyhow!("Volume is full"));
+        }
+
+        for cluster in first..first + count {
+            self.fat[cluster as usize] = if cluster + 1 == first + count { 0x0fffffff } else { cluster + 1 };
+        }
+
+        self.next += count;
+        Ok(first)
+    }
+
+    fn write(&mut self, cluster: u32, data: &[u8]) {
+        let offset = self.layout.cluster_offset(cluster);
+        self.volume[offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    fn write_dir(&mut self, dir: &Dir, cluster: u32, parent: u32, root: bool) -> Result<()> {
+        let mut entries = Vec::with_capacity(dir.size(root));
+
+        if !root {
+            entries.extend(short_entry(b".          ", ATTR_DIRECTORY, cluster, 0));
+            entries.extend(short_entry(b"..         ", ATTR_DIRECTORY, parent, 0));
+        }
+
+        let mut used = Vec::new();
+        for (name, node) in &dir.entries {
+            let short = short_name(name, &used);
+            used.push(short);
+
+            let (child, attr, size) = match node {
+                Node::File(data) => {
+                    let child = self.alloc(data.len())?;
+                    if child != 0 { self.write(child, data); }
+                    (child, ATTR_ARCHIVE, data.len() as u32)
+                }
+                Node::Dir(sub) => {
+                    let child = self.alloc(sub.size(false))?;
+                    // ".." of a directory right below the root points at cluster 0, not the root cluster
+                    self.write_dir(sub, child, if root { 0 } else { cluster }, false)?;
+                    (child, ATTR_DIRECTORY, 0)
+                }
+            };
+
+            if short != upper(name) {
+                entries.extend(lfn_entries(name, checksum(&short)));
+            }
+
+            entries.extend(short_entry(&short, attr, child, size));
+        }
+
+        self.write(cluster, &entries);
+        Ok(())
+    }
+}
+
+// Whether a volume of that many sectors can be formatted at all
+pub fn check(sectors: u64) -> Result<()> {
+    let sectors = u32::try_from(sectors).map_err(|_| anyhow!("Volume of {sectors} sectors is too large for FAT32"))?;
+    Layout::new(sectors, 0).map(|_| ())
+}
+
+pub fn format(volume: &mut [u8], hidden: u32, label: &str, serial: u32, root: &Dir) -> Result<()> {
+    let sectors = u32::try_from(volume.len() / SECTOR).map_err(|_| anyhow!("Volume is too large for FAT32"))?;
+    let layout = Layout::new(sectors, hidden)?;
+    let clusters = layout.clusters();
+
+    let mut writer = Writer { volume, layout, fat: vec![0; clusters as usize + 2], next: ROOT_CLUSTER };
+    writer.fat[0] = 0x0ffffff8;
+    writer.fat[1] = 0x0fffffff;
+
+    let root_cluster = writer.alloc(root.size(true))?;
+    writer.write_dir(root, root_cluster, 0, true)?;
+
+    let Writer { volume, layout, fat, next } = writer;
+
+    let boot = boot_sector(&layout, label, serial);
+    let info = fs_info(clusters + 2 - next, next);
+
+    for base in [0, 6] {
+        let at = |sector: usize| (base + sector) * SECTOR;
+        volume[at(0)..at(1)].copy_from_slice(&boot);
+        volume[at(1)..at(2)].copy_from_slice(&info);
+    }
+
+    let fat = fat.iter().flat_map(|entry| entry.to_le_bytes()).collect::<Vec<_>>();
+    for i in 0..FAT_COUNT {
+        let start = (RESERVED_SECTORS + i * layout.fat_sectors) as usize * SECTOR;
+        volume[start..start + fat.len()].copy_from_slice(&fat);
+    }
+
+    Ok(())
+}
+
+fn boot_sector(layout: &Layout, label: &str, serial: u32) -> [u8; SECTOR] {
+    let mut bs = [0u8; SECTOR];
+
+    bs[0..3].copy_from_slice(&[0xeb, 0x58, 0x90]);
+    bs[3..11].copy_from_slice(b"AOS     ");
+    bs[11..13].copy_from_slice(&(SECTOR as u16).to_le_bytes());
+    bs[13] = layout.cluster_sectors as u8;
+    bs[14..16].copy_from_slice(&(RESERVED_SECTORS as u16).to_le_bytes());
+    bs[16] = FAT_COUNT as u8;
+    bs[21] = 0xf8;
+    bs[24..26].copy_from_slice(&32u16.to_le_bytes());
+    bs[26..28].copy_from_slice(&64u16.to_le_bytes());
+    bs[28..32].copy_from_slice(&layout.hidden.to_le_bytes());
+    bs[32..36].copy_from_slice(&layout.sectors.to_le_bytes());
+    bs[36..40].copy_from_slice(&layout.fat_sectors.to_le_bytes());
+    bs[44..48].copy_from_slice(&ROOT_CLUSTER.to_le_bytes());
+    bs[48..50].copy_from_slice(&1u16.to_le_bytes());
+    bs[50..52].copy_from_slice(&6u16.to_le_bytes());
+    bs[64] = 0x80;
+    bs[66] = 0x29;
+    bs[67..71].copy_from_slice(&serial.to_le_bytes());
+
+    let mut padded = [b' '; 11];
+    for (dst, src) in padded.iter_mut().zip(label.bytes()) { *dst = src.to_ascii_uppercase(); }
+    bs[71..82].copy_from_slice(&padded);
+
+    bs[82..90].copy_from_slice(b"FAT32   ");
+    bs[510] = 0x55;
+    bs[511] = 0xaa;
+
+    bs
+}
+
+fn fs_info(free: u32, next: u32) -> [u8; SECTOR] {
+    let mut info = [0u8; SECTOR];
+
+    info[0..4].copy_from_slice(&0x41615252u32.to_le_bytes());
+    info[484..488].copy_from_slice(&0x61417272u32.to_le_bytes());
+    info[488..492].copy_from_slice(&free.to_le_bytes());
+    info[492..496].copy_from_slice(&next.to_le_bytes());
+    info[508..512].copy_from_slice(&0xaa550000u32.to_le_bytes());
+
+    info
+}
+
+fn short_entry(name: &[u8; 11], attr: u8, cluster: u32, size: u32) -> [u8; DIR_ENTRY] {
+    let mut entry = [0u8; DIR_ENTRY];
+
+    entry[0..11].copy_from_slice(name);
+    entry[11] = attr;
+    entry[16..18].copy_from_slice(&DATE.to_le_bytes());
+    entry[18..20].copy_from_slice(&DATE.to_le_bytes());
+    entry[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    entry[24..26].copy_from_slice(&DATE.to_le_bytes());
+    entry[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    entry[28..32].copy_from_slice(&size.to_le_bytes());
+
+    entry
+}
+
+// The name as an 8.3 entry if it already is one, uppercase
+fn upper(name: &str) -> [u8; 11] {
+    let mut out = [b' '; 11];
+    let (base, ext) = name.rsplit_once('.').unwrap_or((name, ""));
+
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 || !name.bytes().all(valid) || base.contains('.') {
+        return [0; 11];
+    }
+
+    out[..base.len()].copy_from_slice(&base.to_ascii_uppercase().into_bytes());
+    out[8..8 + ext.len()].copy_from_slice(&ext.to_ascii_uppercase().into_bytes());
+    out
+}
+
+fn valid(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"$%'-_@~`!(){}^#&.".contains(&b)
+}
+
+// Long names get a NAME~N.EXT alias that is unique within the directory
+fn short_name(name: &str, used: &[[u8; 11]]) -> [u8; 11] {
+    let exact = upper(name);
+    if exact != [0; 11] && !used.contains(&exact) { return exact; }
+
+    let (base, ext) = name.rsplit_once('.').unwrap_or((name, ""));
+    let clean = |s: &str, len: usize| {
+        s.bytes()
+            .filter(|&b| valid(b) && b != b'.')
+            .map(|b| b.to_ascii_uppercase())
+            .take(len)
+            .collect::<Vec<_>>()
+    };
+
+    let base = clean(base, 6);
+    let ext = clean(ext, 3);
+
+    (1..)
+        .map(|n| {
+            let tail = format!("~{n}").into_bytes();
+            let keep = base.len().min(8 - tail.len());
+
+            let mut out = [b' '; 11];
+            out[..keep].copy_from_slice(&base[..keep]);
+            out[keep..keep + tail.len()].copy_from_slice(&tail);
+            out[8..8 + ext.len()].copy_from_slice(&ext);
+            out
+        })
+        .find(|candidate| !used.contains(candidate))
+        .expect("Impossible")
+}
+
+fn checksum(short: &[u8; 11]) -> u8 {
+    short.iter().fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+}
+
+// Counted even for plain 8.3 names, a directory may end up a little larger than needed
+fn lfn_count(name: &str) -> usize {
+    name.encode_utf16().count().div_ceil(13)
+}
+
+// Stored in reverse, the first physical entry carries the 0x40 "last" flag
+fn lfn_entries(name: &str, checksum: u8) -> Vec<u8> {
+    let mut chars = name.encode_utf16().collect::<Vec<_>>();
+    let count = chars.len().div_ceil(13);
+
+    if chars.len() % 13 != 0 { chars.push(0); }
+    chars.resize(count * 13, 0xffff);
+
+    let mut out = Vec::with_capacity(count * DIR_ENTRY);
+    for i in (0..count).rev() {
+        let mut entry = [0u8; DIR_ENTRY];
+        entry[0] = (i + 1) as u8 | if i + 1 == count { 0x40 } else { 0 };
+        entry[11] = ATTR_LFN;
+        entry[13] = checksum;
+
+        let part = &chars[i * 13..(i + 1) * 13];
+        let offsets = (1..11).step_by(2).chain((14..26).step_by(2)).chain((28..32).step_by(2));
+        for (offset, c) in offsets.zip(part) {
+            entry[offset..offset + 2].copy_from_slice(&c.to_le_bytes());
+        }
+
+        out.extend(entry);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_names_pass_through() {
+        assert_eq!(&short_name("BOOTX64.EFI", &[]), b"BOOTX64 EFI");
+        assert_eq!(&short_name("kernel.elf", &[]), b"KERNEL  ELF");
+    }
+
+    #[test]
+    fn long_names_get_unique_aliases() {
+        let first = short_name("initrd.cpio", &[]);
+        assert_eq!(&first, b"INITRD~1CPI");
+        assert_eq!(&short_name("initrd.cpio.old", &[first]), b"INITRD~1OLD");
+        assert_eq!(&short_name("initrd.cpiox", &[first]), b"INITRD~2CPI");
+    }
+
+    #[test]
+    fn lfn_entries_pad_and_order() {
+        let entries = lfn_entries("initrd.cpio", 0xab);
+        assert_eq!(entries.len(), DIR_ENTRY);
+        assert_eq!(entries[0], 0x41);
+        assert_eq!(entries[13], 0xab);
+        assert_eq!(&entries[1..3], &[b'i', 0]);
+        assert_eq!(&entries[28..30], &[0, 0]);
+        assert_eq!(&entries[30..32], &[0xff, 0xff]);
+    }
+
+    #[test]
+    fn small_volumes_are_rejected() {
+        assert!(check(0).is_err());
+        assert!(check(RESERVED_SECTORS as u64).is_err());
+        assert!(check(MIN_CLUSTERS as u64).is_err());
+        assert!(check(64 << 11).is_ok());
+        assert!(check(1 << 32).is_err());
+    }
+
+    #[test]
+    fn format_places_files_in_clusters() {
+        let mut volume = vec![0u8; 64 << 20];
+        let mut root = Dir::default();
+        root.add("EFI/BOOT/BOOTX64.EFI", vec![0x4d, 0x5a]).unwrap();
+        root.add("initrd.cpio", b"070701".to_vec()).unwrap();
+
+        format(&mut volume, 2048, "AOS", 1, &root).unwrap();
+
+        assert_eq!(&volume[82..90], b"FAT32   ");
+        assert_eq!(&volume[510..512], &[0x55, 0xaa]);
+
+        // Root is cluster 2, EFI gets 3, BOOT 4 and the loader 5, then the initrd
+        let layout = Layout::new((volume.len() / SECTOR) as u32, 2048).unwrap();
+        assert_eq!(&volume[layout.cluster_offset(2)..][..11], b"EFI        ");
+        assert_eq!(&volume[layout.cluster_offset(5)..][..2], &[0x4d, 0x5a]);
+        assert_eq!(&volume[layout.cluster_offset(6)..][..6], b"070701");
+    }
+}
@@ -0,0 +1,146 @@
+use anyhow::{anyhow, Result};
+
+use crate::crc32::crc32;
+
+pub const SECTOR: usize = 512;
+
+// Partitions start on a 1MB boundary like every other partitioning tool does
+pub const FIRST_LBA: u64 = 2048;
+
+const ENTRY_COUNT: usize = 128;
+const ENTRY_SIZE: usize = 128;
+const ENTRIES_SECTORS: u64 = (ENTRY_COUNT * ENTRY_SIZE / SECTOR) as u64;
+
+pub const ESP_TYPE: Guid = Guid(0xc12a7328, 0xf81f, 0x11d2, [0xba, 0x4b, 0x00, 0xa0, 0xc9, 0x3e, 0xc9, 0x3b]);
+
+#[derive(Clone, Copy)]
+pub struct Guid(pub u32, pub u16, pub u16, pub [u8; 8]);
+
+impl Guid {
+    // Version 4 layout filled from the seed, good enough to tell images apart
+    pub fn from_seed(seed: u64) -> Guid {
+        let mut x = seed | 1;
+        let mut next = || {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x
+        };
+
+        let (a, b) = (next(), next());
+        let mut tail = b.to_le_bytes();
+        tail[0] = tail[0] & 0x3f | 0x80;
+
+        Guid(a as u32, (a >> 32) as u16, (a >> 48) as u16 & 0x0fff | 0x4000, tail)
+    }
+
+    // The first three fields are little-endian on disk
+    fn bytes(&self) -> [u8; 16] {
+        let mut out = [0; 16];
+        out[0..4].copy_from_slice(&self.0.to_le_bytes());
+        out[4..6].copy_from_slice(&self.1.to_le_bytes());
+        out[6..8].copy_from_slice(&self.2.to_le_bytes());
+        out[8..16].copy_from_slice(&self.3);
+        out
+    }
+}
+
+pub struct Partition {
+    pub kind:  Guid,
+    pub guid:  Guid,
+    pub name:  &'static str,
+    pub first: u64,
+    pub last:  u64
+}
+
+// Protective MBR, primary header and entries at the front, backup entries and header at the back
+pub fn write(disk: &mut [u8], guid: Guid, partitions: &[Partition]) -> Result<()> {
+    let sectors = (disk.len() / SECTOR) as u64;
+    let first_usable = 2 + ENTRIES_SECTORS;
+    let last_usable = last_usable(sectors).ok_or(anyhow!("Disk of {sectors} sectors cannot hold a GPT"))?;
+
+    if partitions.len() > ENTRY_COUNT {
+        return Err(anyhow!("Too many partitions"));
+    }
+
+    let mut entries = vec![0u8; ENTRY_COUNT * ENTRY_SIZE];
+    for (i, partition) in partitions.iter().enumerate() {
+        if partition.first < first_usable || partition.last > last_usable || partition.first > partition.last {
+            return Err(anyhow!("Partition {} does not fit on the disk", partition.name));
+        }
+
+        let entry = &mut entries[i * ENTRY_SIZE..(i + 1) * ENTRY_SIZE];
+        entry[0..16].copy_from_slice(&partition.kind.bytes());
+        entry[16..32].copy_from_slice(&partition.guid.bytes());
+        entry[32..40].copy_from_slice(&partition.first.to_le_bytes());
+        entry[40..48].copy_from_slice(&partition.last.to_le_bytes());
+
+        for (j, c) in partition.name.encode_utf16().take(36).enumerate() {
+            entry[56 + j * 2..58 + j * 2].copy_from_slice(&c.to_le_bytes());
+        }
+    }
+
+    write_mbr(&mut disk[..SECTOR], sectors);
+
+    let entries_crc = crc32(&entries);
+    let backup_entries = sectors - 1 - ENTRIES_SECTORS;
+
+    let at = |lba: u64| lba as usize * SECTOR;
+    disk[at(2)..at(2) + entries.len()].copy_from_slice(&entries);
+    disk[at(backup_entries)..at(backup_entries) + entries.len()].copy_from_slice(&entries);
+
+    let header = |current: u64, backup: u64, entries_lba: u64| {
+        let mut header = [0u8; SECTOR];
+        header[0..8].copy_from_slice(b"EFI PART");
+        header[8..12].copy_from_slice(&0x00010000u32.to_le_bytes());
+        header[12..16].copy_from_slice(&92u32.to_le_bytes());
+        header[24..32].copy_from_slice(&current.to_le_bytes());
+        header[32..40].copy_from_slice(&backup.to_le_bytes());
+        header[40..48].copy_from_slice(&first_usable.to_le_bytes());
+        header[48..56].copy_from_slice(&last_usable.to_le_bytes());
+        header[56..72].copy_from_slice(&guid.bytes());
+        header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+        header[80..84].copy_from_slice(&(ENTRY_COUNT as u32).to_le_bytes());
+        header[84..88].copy_from_slice(&(ENTRY_SIZE as u32).to_le_bytes());
+        header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+
+        let crc = crc32(&header[..92]);
+        header[16..20].copy_from_slice(&crc.to_le_bytes());
+        header
+    };
+
+    disk[at(1)..at(2)].copy_from_slice(&header(1, sectors - 1, 2));
+    disk[at(sectors - 1)..].copy_from_slice(&header(sectors - 1, 1, backup_entries));
+
+    Ok(())
+}
+
+fn write_mbr(mbr: &mut [u8], sectors: u64) {
+    let entry = &mut mbr[446..462];
+    entry[1..4].copy_from_slice(&[0x00, 0x02, 0x00]);
+    entry[4] = 0xee;
+    entry[5..8].copy_from_slice(&[0xff, 0xff, 0xff]);
+    entry[8..12].copy_from_slice(&1u32.to_le_bytes());
+    entry[12..16].copy_from_slice(&((sectors - 1).min(u32::MAX as u64) as u32).to_le_bytes());
+
+    mbr[510] = 0x55;
+    mbr[511] = 0xaa;
+}
+
+// None if both copies of the tables do not fit
+pub fn last_usable(sectors: u64) -> Option<u64> {
+    sectors.checked_sub(2 + ENTRIES_SECTORS).filter(|&last| last >= 2 + ENTRIES_SECTORS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiny_disks_have_no_usable_sectors() {
+        assert_eq!(last_usable(0), None);
+        assert_eq!(last_usable(2 * (2 + ENTRIES_SECTORS) - 1), None);
+        assert_eq!(last_usable(2048), Some(2048 - 2 - ENTRIES_SECTORS));
+        assert!(write(&mut [0; 16 * SECTOR], Guid::from_seed(1), &[]).is_err());
+    }
+}
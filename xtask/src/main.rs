@@ -0,0 +1,254 @@
+mod cpio;
+mod crc32;
+mod fat;
+mod gpt;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, ExitStatus};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use anyhow::{anyhow, bail, Context, Result};
+
+use fat::Dir;
+use gpt::{Guid, Partition, ESP_TYPE, FIRST_LBA, SECTOR};
+
+const USAGE: &str = "\
+usage: cargo xtask <command> [options]
+
+commands:
+    build                 build the bootloader and the kernel
+    image                 build and write a bootable GPT disk image
+    run                   build the image and boot it in QEMU
+    test-runner <kernel>  boot a test kernel headless and report its exit status
+
+options:
+    --release             use release builds
+    --out <path>          image path, target/aos.img by default
+    --kernel <path>       use this kernel instead of building one
+    --initrd <path>       initrd file or directory to pack, initrd/ if it exists
+    --config <path>       boot.cfg to install, boot.cfg if it exists
+    --size <MiB>          image size, 64 by default
+    --debug               wait for gdb on :1234 (run only)
+
+environment:
+    OVMF                  firmware image, /usr/share/ovmf/OVMF.fd by default
+    TEST_TIMEOUT          seconds before a test run is killed, 300 by default";
+
+// QEMU reports a write of v to isa-debug-exit as exit status (v << 1) | 1, see kernel/src/testing.rs
+const TEST_SUCCESS: i32 = 0x10 << 1 | 1;
+
+const TEST_CONFIG: &str = "\
+timeout = 0
+
+[entry]
+title   = Tests
+initrd  =
+cmdline = console=serial log=warn
+video   = 1024x768
+";
+
+#[derive(Default)]
+struct Options {
+    release: bool,
+    debug:   bool,
+    out:     Option<PathBuf>,
+    kernel:  Option<PathBuf>,
+    initrd:  Option<PathBuf>,
+    config:  Option<PathBuf>,
+    size:    Option<u64>
+}
+
+impl Options {
+    fn parse(args: &mut impl Iterator<Item = String>) -> Result<Options> {
+        let mut options = Options::default();
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(anyhow!("{arg} expects a value"));
+
+            match arg.as_str() {
+                "--release" => options.release = true,
+                "--debug"   => options.debug = true,
+                "--out"     => options.out = Some(value()?.into()),
+                "--kernel"  => options.kernel = Some(value()?.into()),
+                "--initrd"  => options.initrd = Some(value()?.into()),
+                "--config"  => options.config = Some(value()?.into()),
+                "--size"    => options.size = Some(value()?.parse().context("--size expects a number of MiB")?),
+                _           => bail!("Unknown option {arg}\n\n{USAGE}")
+            }
+        }
+
+        Ok(options)
+    }
+
+    fn profile(&self) -> &'static str {
+        if self.release { "release" } else { "debug" }
+    }
+}
+
+fn root() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR")).parent().expect("xtask lives inside the workspace")
+}
+
+fn cargo_build(package: &str, release: bool) -> Result<()> {
+    let cargo = env::var("CARGO").unwrap_or("cargo".to_string());
+
+    // Each crate carries its own target and linker settings in <crate>/.cargo/config.toml
+    let mut command = Command::new(cargo);
+    command.arg("build").current_dir(root().join(package));
+    if release { command.arg("--release"); }
+
+    check(command.status()?, &format!("Building {package}"))
+}
+
+fn check(status: ExitStatus, what: &str) -> Result<()> {
+    if status.success() { Ok(()) } else { Err(anyhow!("{what} failed: {status}")) }
+}
+
+fn build(options: &Options) -> Result<()> {
+    cargo_build("boot", options.release)?;
+    if options.kernel.is_none() { cargo_build("kernel", options.release)?; }
+
+    Ok(())
+}
+
+fn seed() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0) ^ process::id() as u64
+}
+
+fn existing(path: Option<&PathBuf>, default: &str) -> Option<PathBuf> {
+    path.cloned().or_else(|| Some(root().join(default)).filter(|path| path.exists()))
+}
+
+// The whole image in bytes and the last sector of the ESP, which fills everything between the GPT tables
+fn layout(options: &Options) -> Result<(usize, u64)> {
+    let mib = options.size.unwrap_or(64);
+    let size = mib.checked_mul(1 << 20).and_then(|size| usize::try_from(size).ok()).ok_or(anyhow!("--size {mib} is too large"))?;
+
+    let last = gpt::last_usable((size / SECTOR) as u64)
+        .filter(|&last| last >= FIRST_LBA)
+        .ok_or(anyhow!("--size {mib} leaves no room for the EFI partition"))?;
+
+    fat::check(last + 1 - FIRST_LBA).with_context(|| format!("--size {mib} is too small"))?;
+
+    Ok((size, last))
+}
+
+fn image(options: &Options, config: Option<&str>) -> Result<PathBuf> {
+    let (size, last) = layout(options)?;
+    build(options)?;
+
+    let profile = options.profile();
+    let loader = root().join(format!("target/x86_64-unknown-uefi/{profile}/boot.efi"));
+    let kernel = options.kernel.clone().unwrap_or(root().join(format!("target/x86_64-unknown-none/{profile}/kernel")));
+    let out = options.out.clone().unwrap_or(root().join("target/aos.img"));
+
+    let read = |path: &Path| fs::read(path).with_context(|| format!("Reading {}", path.display()));
+
+    let mut files = Dir::default();
+    files.add("EFI/BOOT/BOOTX64.EFI", read(&loader)?)?;
+    files.add("kernel.elf", read(&kernel)?)?;
+
+    if let Some(initrd) = existing(options.initrd.as_ref(), "initrd") {
+        let data = if initrd.is_dir() { cpio::pack(&initrd)? } else { read(&initrd)? };
+        files.add("initrd.cpio", data)?;
+    }
+
+    match config {
+        Some(text) => files.add("boot.cfg", text.as_bytes().to_vec())?,
+        None       => if let Some(path) = existing(options.config.as_ref(), "boot.cfg") {
+            files.add("boot.cfg", read(&path)?)?;
+        }
+    }
+
+    let mut disk = vec![0u8; size];
+
+    let seed = seed();
+    let esp = Partition { kind: ESP_TYPE, guid: Guid::from_seed(seed), name: "EFI System", first: FIRST_LBA, last };
+    gpt::write(&mut disk, Guid::from_seed(seed.rotate_left(17)), &[esp])?;
+
+    let volume = &mut disk[FIRST_LBA as usize * SECTOR..(last as usize + 1) * SECTOR];
+    fat::format(volume, FIRST_LBA as u32, "AOS", seed as u32, &files)?;
+
+    fs::write(&out, disk).with_context(|| format!("Writing {}", out.display()))?;
+    println!("Wrote {}", out.display());
+
+    Ok(out)
+}
+
+fn qemu(image: &Path) -> Command {
+    let ovmf = env::var("OVMF").unwrap_or("/usr/share/ovmf/OVMF.fd".to_string());
+
+    let mut command = Command::new("qemu-system-x86_64");
+    command.args(["-m", "4G", "-machine", "q35", "-net", "none", "-serial", "stdio"])
+        .arg("-bios").arg(ovmf)
+        .arg("-drive").arg(format!("format=raw,file={}", image.display()));
+
+    command
+}
+
+fn run(options: &Options) -> Result<()> {
+    let image = image(options, None)?;
+
+    let mut command = qemu(&image);
+    if options.debug { command.args(["-s", "-S"]); }
+
+    check(command.status()?, "QEMU")
+}
+
+fn test_runner(kernel: &str, options: &mut Options) -> Result<()> {
+    options.kernel = Some(kernel.into());
+    if options.out.is_none() {
+        options.out = Some(env::temp_dir().join(format!("aos-test-{}.img", process::id())));
+    }
+
+    let image = image(options, Some(TEST_CONFIG))?;
+
+    let timeout = env::var("TEST_TIMEOUT").ok().and_then(|s| s.parse().ok()).unwrap_or(300);
+    let deadline = Instant::now() + Duration::from_secs(timeout);
+
+    let mut child = qemu(&image)
+        .args(["-display", "none", "-device", "isa-debug-exit,iobase=0xf4,iosize=0x04"])
+        .spawn()?;
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? { break Some(status); }
+
+        if Instant::now() > deadline {
+            child.kill()?;
+            child.wait()?;
+            break None;
+        }
+
+        thread::sleep(Duration::from_millis(100));
+    };
+
+    let _ = fs::remove_file(&image);
+
+    match status.and_then(|status| status.code()) {
+        Some(TEST_SUCCESS) => Ok(()),
+        Some(code)         => Err(anyhow!("Tests failed, QEMU exited with {code}")),
+        None               => Err(anyhow!("Tests did not finish within {timeout}s"))
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let result = match args.next().as_deref() {
+        Some("build")       => Options::parse(&mut args).and_then(|options| build(&options)),
+        Some("image")       => Options::parse(&mut args).and_then(|options| image(&options, None).map(|_| ())),
+        Some("run")         => Options::parse(&mut args).and_then(|options| run(&options)),
+        Some("test-runner") => match args.next() {
+            Some(kernel) => Options::parse(&mut args).and_then(|mut options| test_runner(&kernel, &mut options)),
+            None         => Err(anyhow!("test-runner expects a kernel\n\n{USAGE}"))
+        },
+        _ => Err(anyhow!("{USAGE}"))
+    };
+
+    if let Err(e) = result {
+        eprintln!("{e:#}");
+        process::exit(1);
+    }
+}